@@ -0,0 +1,267 @@
+//! Bundles: several tokens of one seller sold as one lot, with a single
+//! payment. The tokens pass through the marketplace's escrow so the lot is
+//! delivered whole or not at all, then go out with one `nft_transfer_payout`
+//! per token
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::{
+    env, log, near_bindgen, AccountId, NearToken, Promise, PromiseOrValue, PromiseResult,
+    json_types::U128,
+    serde::{Deserialize, Serialize},
+};
+use std::collections::HashMap;
+
+use crate::{
+    contract_and_token_id, events, ext_nft_contract, payout_royalties, Listing, ListingKind, ListingStatus, Marketplace,
+    MarketplaceExt, Payout, GAS_FOR_BUNDLE_ESCROW, GAS_FOR_BUNDLE_TRANSFER, GAS_FOR_RESOLVE_BUNDLE_ESCROW,
+    GAS_FOR_RESOLVE_BUNDLE_PURCHASE, MAX_LEN_PAYOUT,
+};
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+pub struct BundleItemView {
+    pub nft_contract_id: String,
+    pub token_id: String,
+    pub approved: bool,
+    pub weight: u32,
+}
+
+/// A token of a bundle as given in the listing `msg`
+#[derive(Serialize, Deserialize)]
+pub struct BundleItemArgs {
+    pub nft_contract_id: AccountId,
+    pub token_id: String,
+    /// Share of the price the token's royalties are computed on, 1 when omitted
+    #[serde(default)]
+    pub weight: Option<u32>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
+pub struct BundleItem {
+    /// The NFT contract of the token
+    pub nft_contract_id: AccountId,
+    /// The token ID
+    pub token_id: String,
+    /// The NEP-178 approval ID, `None` until the owner approved the marketplace
+    pub approval_id: Option<u64>,
+    /// Share of the bundle price paid for this token
+    pub weight: u32,
+}
+
+#[near_bindgen]
+impl Marketplace {
+    /// Continues a bundle purchase once the marketplace tried to take every
+    /// token into escrow. A bundle is sold whole or not at all: when some
+    /// token could not be taken, the escrowed ones go back to the seller, the
+    /// listing is cancelled and the buyer's `deposit` is refunded. Otherwise
+    /// the tokens are transferred to the buyer and `resolve_bundle_purchase`
+    /// settles the sale. Resolves to the part of the deposit the buyer gets
+    /// back, like `resolve_purchase`.
+    #[private]
+    pub fn resolve_bundle_escrow(
+        &mut self,
+        buyer_id: AccountId,
+        listing_id: U128,
+        price: U128,
+        deposit: U128,
+        marketplace_fee: U128,
+    ) -> PromiseOrValue<U128> {
+        let mut listing = self.listings.get(&listing_id).expect("Listing not found");
+        let ListingKind::Bundle { items, .. } = listing.kind.clone() else {
+            env::panic_str("Listing is not a bundle");
+        };
+
+        let escrowed: Vec<&BundleItem> = items
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| matches!(env::promise_result(*index as u64), PromiseResult::Successful(_)))
+            .map(|(_, item)| item)
+            .collect();
+        if escrowed.len() < items.len() {
+            log!("Not every token of bundle {} could be transferred, refunding {}", listing.id.0, buyer_id);
+            for item in escrowed {
+                ext_nft_contract::ext(item.nft_contract_id.clone())
+                    .with_attached_deposit(NearToken::from_yoctonear(1))
+                    .with_static_gas(GAS_FOR_BUNDLE_ESCROW)
+                    .nft_transfer(
+                        listing.seller_id.clone(),
+                        item.token_id.clone(),
+                        None,
+                        Some(format!("Marketplace bundle {} cancelled", listing.id.0)),
+                    );
+            }
+            return PromiseOrValue::Value(self.internal_refund_purchase(&mut listing, buyer_id, deposit.0));
+        }
+
+        // Each payout splits the token's weighted share of the proceeds
+        let proceeds = price.0 - marketplace_fee.0;
+        let total_weight: u128 = items.iter().map(|item| item.weight as u128).sum();
+        let transfers = items
+            .iter()
+            .map(|item| {
+                ext_nft_contract::ext(item.nft_contract_id.clone())
+                    .with_attached_deposit(NearToken::from_yoctonear(1))
+                    .with_static_gas(GAS_FOR_BUNDLE_TRANSFER)
+                    .nft_transfer_payout(
+                        buyer_id.clone(),
+                        item.token_id.clone(),
+                        None,
+                        Some(format!("Marketplace bundle {}", listing.id.0)),
+                        U128(proceeds * item.weight as u128 / total_weight),
+                        Some(MAX_LEN_PAYOUT),
+                    )
+            })
+            .reduce(|transfers, transfer| transfers.and(transfer))
+            .expect("Bundle has no tokens");
+
+        transfers
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_BUNDLE_PURCHASE)
+                    .resolve_bundle_purchase(buyer_id, listing_id, price, deposit, marketplace_fee),
+            )
+            .into()
+    }
+
+    /// Settles a bundle purchase once the escrowed tokens were transferred to
+    /// the buyer. Each token's payout covers its weighted share of the
+    /// proceeds and its royalties are paid out of that share. A token the
+    /// NFT contract did not transfer with a payout is still held by the
+    /// marketplace and is handed over without royalties, the buyer pays the
+    /// full price either way. Returns the part of the deposit the buyer gets
+    /// back, like `resolve_purchase`.
+    #[private]
+    pub fn resolve_bundle_purchase(
+        &mut self,
+        buyer_id: AccountId,
+        listing_id: U128,
+        price: U128,
+        deposit: U128,
+        marketplace_fee: U128,
+    ) -> U128 {
+        let listing = self.listings.get(&listing_id).expect("Listing not found");
+        let ListingKind::Bundle { items, .. } = listing.kind.clone() else {
+            env::panic_str("Listing is not a bundle");
+        };
+
+        // The payouts owe the rest of each share to the marketplace, which held the tokens
+        let escrow_id = env::current_account_id();
+        let proceeds = price.0 - marketplace_fee.0;
+        let total_weight: u128 = items.iter().map(|item| item.weight as u128).sum();
+        let mut royalties: HashMap<AccountId, u128> = HashMap::new();
+        for (index, item) in items.iter().enumerate() {
+            let payout = match env::promise_result(index as u64) {
+                PromiseResult::Successful(value) => near_sdk::serde_json::from_slice::<Payout>(&value).ok(),
+                PromiseResult::Failed => {
+                    log!("Transfer of token {} with a payout failed, handing it over without royalties", item.token_id);
+                    ext_nft_contract::ext(item.nft_contract_id.clone())
+                        .with_attached_deposit(NearToken::from_yoctonear(1))
+                        .with_static_gas(GAS_FOR_BUNDLE_ESCROW)
+                        .nft_transfer(buyer_id.clone(), item.token_id.clone(), None, Some(format!("Marketplace bundle {}", listing.id.0)));
+                    None
+                }
+            };
+            let Some(payout) = payout else {
+                continue;
+            };
+
+            let balance = proceeds * item.weight as u128 / total_weight;
+            for (receiver_id, amount) in payout_royalties(payout, balance, &escrow_id, &item.token_id) {
+                *royalties.entry(receiver_id).or_default() += amount;
+            }
+        }
+
+        // Royalties of several tokens together may exceed what can be paid out,
+        // the largest are paid and the rest stays with the seller
+        if royalties.len() > MAX_LEN_PAYOUT as usize {
+            log!("Bundle {} owes royalties to {} receivers, paying the largest {}", listing.id.0, royalties.len(), MAX_LEN_PAYOUT);
+            let mut receivers: Vec<(AccountId, u128)> = royalties.into_iter().collect();
+            receivers.sort_by(|(a_id, a), (b_id, b)| b.cmp(a).then_with(|| a_id.cmp(b_id)));
+            receivers.truncate(MAX_LEN_PAYOUT as usize);
+            royalties = receivers.into_iter().collect();
+        }
+
+        self.internal_pay_out(&listing, buyer_id, price.0, deposit.0, marketplace_fee.0, royalties)
+    }
+}
+
+impl Marketplace {
+    /// Adds an approved token to an active bundle of its owner. A listing of
+    /// the token outside the bundle is cancelled.
+    pub(crate) fn internal_approve_bundle_item(
+        &mut self,
+        listing_id: U128,
+        nft_contract_id: &AccountId,
+        token_id: &str,
+        owner_id: &AccountId,
+        approval_id: u64,
+    ) {
+        let mut listing = self.listings.get(&listing_id).expect("Listing not found");
+        assert_eq!(listing.status, ListingStatus::Active, "Listing is not active");
+        assert_eq!(&listing.seller_id, owner_id, "Only the seller can add tokens to the bundle");
+
+        let token_key = contract_and_token_id(nft_contract_id, token_id);
+        if self.listings_by_token.get(&token_key) != Some(listing_id) {
+            self.internal_cancel_token_listing(nft_contract_id, token_id);
+        }
+
+        let ListingKind::Bundle { items, .. } = &mut listing.kind else {
+            env::panic_str("Listing is not a bundle");
+        };
+        let item = items
+            .iter_mut()
+            .find(|item| &item.nft_contract_id == nft_contract_id && item.token_id == token_id)
+            .expect("Token is not part of the bundle");
+        item.approval_id = Some(approval_id);
+
+        self.listings.insert(&listing.id, &listing);
+        self.listings_by_token.insert(&token_key, &listing.id);
+        events::emit_listing_updated(&listing, Some(token_id));
+
+        log!("Added token {} of {} to bundle {}", token_id, nft_contract_id, listing.id.0);
+    }
+
+    /// Takes every token of a closed bundle into escrow, so that
+    /// `resolve_bundle_escrow` can deliver them all to the buyer or none,
+    /// and settle `price` out of the buyer's `deposit`.
+    pub(crate) fn internal_settle_bundle(&self, listing: &Listing, buyer_id: AccountId, price: U128, deposit: U128) -> Promise {
+        let ListingKind::Bundle { items, .. } = &listing.kind else {
+            env::panic_str("Listing is not a bundle");
+        };
+
+        let marketplace_fee = self.internal_marketplace_fee(&listing.seller_id, price.0);
+        let escrow = items
+            .iter()
+            .map(|item| {
+                ext_nft_contract::ext(item.nft_contract_id.clone())
+                    .with_attached_deposit(NearToken::from_yoctonear(1))
+                    .with_static_gas(GAS_FOR_BUNDLE_ESCROW)
+                    .nft_transfer(
+                        env::current_account_id(),
+                        item.token_id.clone(),
+                        item.approval_id,
+                        Some(format!("Marketplace bundle {}", listing.id.0)),
+                    )
+            })
+            .reduce(|escrow, transfer| escrow.and(transfer))
+            .expect("Bundle has no tokens");
+
+        // Room to deliver every token and settle the purchase
+        let resolve_gas = GAS_FOR_RESOLVE_BUNDLE_ESCROW
+            .saturating_add(GAS_FOR_BUNDLE_TRANSFER.saturating_mul(items.len() as u64))
+            .saturating_add(GAS_FOR_RESOLVE_BUNDLE_PURCHASE);
+        escrow.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(resolve_gas)
+                .resolve_bundle_escrow(buyer_id, listing.id, price, deposit, U128(marketplace_fee)),
+        )
+    }
+
+    pub(crate) fn bundle_item_to_view(&self, item: BundleItem) -> BundleItemView {
+        BundleItemView {
+            nft_contract_id: item.nft_contract_id.to_string(),
+            token_id: item.token_id,
+            approved: item.approval_id.is_some(),
+            weight: item.weight,
+        }
+    }
+}
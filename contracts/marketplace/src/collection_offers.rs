@@ -0,0 +1,269 @@
+//! Collection offers: NEAR escrowed for any tokens of an NFT contract that
+//! match the buyer's criteria, accepted by whichever owners sell first
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::{
+    env, log, near_bindgen, AccountId, Promise, PromiseError, PromiseOrValue,
+    json_types::{U128, U64},
+    serde::{Deserialize, Serialize},
+};
+
+use crate::{
+    add_to_set, ext_nft_contract, external::Token, paginate_ids, remove_from_set, Marketplace, MarketplaceExt, StorageKey,
+    GAS_FOR_NFT_TOKEN, GAS_FOR_RESOLVE_COLLECTION_OFFER, MAX_COLLECTION_OFFER_QUANTITY, MAX_OFFER_DURATION, MIN_PRICE,
+};
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+pub struct CollectionOfferView {
+    pub id: String,
+    pub buyer_id: String,
+    pub nft_contract_id: String,
+    pub creator_id: Option<String>,
+    pub voice_type: Option<String>,
+    pub language: Option<String>,
+    pub tag: Option<String>,
+    pub price: String,
+    pub remaining: u32,
+    pub filled: u32,
+    pub expires_at: String,
+    pub created_at: String,
+}
+
+/// What a token must match to fill a collection offer. Metadata is only as
+/// trustworthy as its NFT contract, so an offer is always for one contract.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TokenCriteria {
+    /// The NFT contract of the tokens
+    pub nft_contract_id: AccountId,
+    /// The account that recorded the voice
+    #[serde(default)]
+    pub creator_id: Option<AccountId>,
+    /// Type of voice content, compared case-insensitively
+    #[serde(default)]
+    pub voice_type: Option<String>,
+    /// Language of the voice, compared case-insensitively
+    #[serde(default)]
+    pub language: Option<String>,
+    /// A tag the token must have, compared case-insensitively
+    #[serde(default)]
+    pub tag: Option<String>,
+}
+
+impl TokenCriteria {
+    /// Whether a token read from the NFT contract matches
+    pub fn matches(&self, token: &Token) -> bool {
+        let metadata = token.metadata.as_ref();
+        let same = |wanted: &String, value: &String| normalize(wanted) == normalize(value);
+
+        self.creator_id.as_ref().is_none_or(|creator_id| token.creator_id.as_ref() == Some(creator_id))
+            && self.voice_type.as_ref().is_none_or(|wanted| {
+                metadata.and_then(|metadata| metadata.voice_type.as_ref()).is_some_and(|value| same(wanted, value))
+            })
+            && self.language.as_ref().is_none_or(|wanted| {
+                metadata.and_then(|metadata| metadata.language.as_ref()).is_some_and(|value| same(wanted, value))
+            })
+            && self.tag.as_ref().is_none_or(|wanted| {
+                metadata.and_then(|metadata| metadata.tags.as_ref()).is_some_and(|tags| tags.iter().any(|tag| same(wanted, tag)))
+            })
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CollectionOffer {
+    /// The ID of the offer, counted with the offers on single tokens
+    pub id: U128,
+    /// The account ID of the buyer
+    pub buyer_id: AccountId,
+    /// What the tokens must match
+    pub criteria: TokenCriteria,
+    /// The price paid per token in yoctoNEAR
+    pub price: U128,
+    /// How many more tokens the buyer takes, their price is held in escrow
+    pub remaining: u32,
+    /// How many tokens were sold to the offer
+    pub filled: u32,
+    /// The time the offer can no longer be accepted, in nanoseconds
+    pub expires_at: U64,
+    /// The timestamp when the offer was made
+    pub created_at: U64,
+}
+
+#[near_bindgen]
+impl Marketplace {
+    /// Offers `price` for each of up to `quantity` tokens matching
+    /// `criteria`, until `expires_at` (nanoseconds). The attached deposit
+    /// must be the price of every token and is held in escrow. Owners accept
+    /// by approving the marketplace with an `accept_collection_offer` msg.
+    #[payable]
+    pub fn make_collection_offer(&mut self, criteria: TokenCriteria, price: U128, quantity: u32, expires_at: U64) -> U128 {
+        let buyer_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit().as_yoctonear();
+        let now = env::block_timestamp();
+
+        self.assert_not_paused();
+        self.assert_not_blocked(&buyer_id);
+        self.assert_not_blocked(&criteria.nft_contract_id);
+        assert!(price.0 >= MIN_PRICE, "Offer is below the minimum price");
+        assert!(
+            (1..=MAX_COLLECTION_OFFER_QUANTITY).contains(&quantity),
+            "An offer takes 1 to {} tokens",
+            MAX_COLLECTION_OFFER_QUANTITY
+        );
+        assert_eq!(deposit, price.0 * quantity as u128, "Deposit must be the price times the quantity");
+        assert!(expires_at.0 > now, "Offer expiry is in the past");
+        assert!(expires_at.0 <= now + MAX_OFFER_DURATION, "Offer expiry is too far out");
+
+        self.total_offers.0 += 1;
+        let offer = CollectionOffer {
+            id: self.total_offers,
+            buyer_id,
+            criteria,
+            price,
+            remaining: quantity,
+            filled: 0,
+            expires_at,
+            created_at: U64(now / 1_000_000),
+        };
+        self.collection_offers.insert(&offer.id, &offer);
+
+        let nft_contract_id = &offer.criteria.nft_contract_id;
+        add_to_set(&mut self.collection_offers_by_contract, nft_contract_id, &offer.id, StorageKey::CollectionOffersPerContract {
+            account_hash: env::sha256_array(nft_contract_id.as_bytes()),
+        });
+
+        log!("Collection offer {} of {} for {} tokens of {}", offer.id.0, price.0, quantity, nft_contract_id);
+
+        offer.id
+    }
+
+    /// Withdraws a collection offer and refunds the escrow of the tokens it
+    /// still takes. The buyer can cancel it at any time, anyone once expired.
+    pub fn cancel_collection_offer(&mut self, offer_id: U128) -> Promise {
+        let offer = self.collection_offers.get(&offer_id).expect("Offer not found");
+        assert!(
+            env::predecessor_account_id() == offer.buyer_id || env::block_timestamp() >= offer.expires_at.0,
+            "Only the buyer can cancel an offer before it expires"
+        );
+
+        log!("Collection offer {} cancelled", offer_id.0);
+
+        self.internal_remove_collection_offer(&offer);
+        self.internal_send(offer.buyer_id, None, offer.price.0 * offer.remaining as u128)
+    }
+
+    /// Sells a token to a collection offer, as it was when the token was
+    /// reserved, once the NFT contract showed that the token matches the
+    /// criteria and is still owned by `owner_id`. Otherwise the reserved
+    /// token goes back to the offer, or its price to the buyer when the offer
+    /// was closed in the meantime.
+    #[private]
+    pub fn resolve_collection_offer(
+        &mut self,
+        offer: CollectionOffer,
+        token_id: String,
+        owner_id: AccountId,
+        approval_id: u64,
+        #[callback_result] token: Result<Option<Token>, PromiseError>,
+    ) -> PromiseOrValue<bool> {
+        let token = token.ok().flatten().filter(|token| token.owner_id == owner_id);
+        if token.is_none_or(|token| !offer.criteria.matches(&token)) {
+            log!("Token {} does not match collection offer {}", token_id, offer.id.0);
+            match self.collection_offers.get(&offer.id) {
+                Some(mut open_offer) => {
+                    open_offer.remaining += 1;
+                    self.collection_offers.insert(&offer.id, &open_offer);
+                }
+                None => {
+                    self.internal_send(offer.buyer_id, None, offer.price.0);
+                }
+            }
+            return PromiseOrValue::Value(false);
+        }
+
+        if let Some(mut open_offer) = self.collection_offers.get(&offer.id) {
+            open_offer.filled += 1;
+            self.collection_offers.insert(&offer.id, &open_offer);
+        }
+
+        let CollectionOffer { id: offer_id, buyer_id, criteria, price, .. } = offer;
+        let listing = self.internal_add_offer_sale(criteria.nft_contract_id, token_id, owner_id, approval_id, buyer_id.clone(), price);
+
+        log!("Collection offer {} accepted as listing {}", offer_id.0, listing.id.0);
+
+        PromiseOrValue::Promise(self.internal_settle(&listing, buyer_id, price, price))
+    }
+
+    /// Gets the details of an open collection offer.
+    pub fn get_collection_offer(&self, offer_id: U128) -> Option<CollectionOfferView> {
+        self.collection_offers.get(&offer_id).map(|offer| self.collection_offer_to_view(offer))
+    }
+
+    /// Gets a page of the open collection offer IDs on an NFT contract.
+    pub fn get_collection_offers(&self, nft_contract_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<String> {
+        self.collection_offers_by_contract.get(&nft_contract_id).map_or_else(Vec::new, |set| paginate_ids(&set, from_index, limit))
+    }
+}
+
+impl Marketplace {
+    /// Accepts a collection offer for the token owner, who approved the
+    /// marketplace with `approval_id`. One token of the offer is reserved
+    /// while the NFT contract is asked whether the token matches.
+    pub(crate) fn internal_accept_collection_offer(
+        &mut self,
+        offer_id: U128,
+        nft_contract_id: &AccountId,
+        token_id: &str,
+        owner_id: AccountId,
+        approval_id: u64,
+    ) -> Promise {
+        let mut offer = self.collection_offers.get(&offer_id).expect("Offer not found");
+        assert_eq!(&offer.criteria.nft_contract_id, nft_contract_id, "Offer is for another collection");
+        assert!(env::block_timestamp() < offer.expires_at.0, "Offer has expired");
+        assert_ne!(offer.buyer_id, owner_id, "Cannot accept your own offer");
+        self.assert_not_blocked(&offer.buyer_id);
+
+        // The last token closes the offer, no other owner can take it
+        offer.remaining -= 1;
+        if offer.remaining == 0 {
+            self.internal_remove_collection_offer(&offer);
+        } else {
+            self.collection_offers.insert(&offer_id, &offer);
+        }
+
+        ext_nft_contract::ext(nft_contract_id.clone())
+            .with_static_gas(GAS_FOR_NFT_TOKEN)
+            .nft_token(token_id.to_string())
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_COLLECTION_OFFER)
+                    .resolve_collection_offer(offer, token_id.to_string(), owner_id, approval_id),
+            )
+    }
+
+    fn internal_remove_collection_offer(&mut self, offer: &CollectionOffer) {
+        self.collection_offers.remove(&offer.id);
+        remove_from_set(&mut self.collection_offers_by_contract, &offer.criteria.nft_contract_id, &offer.id);
+    }
+
+    fn collection_offer_to_view(&self, offer: CollectionOffer) -> CollectionOfferView {
+        CollectionOfferView {
+            id: offer.id.0.to_string(),
+            buyer_id: offer.buyer_id.to_string(),
+            nft_contract_id: offer.criteria.nft_contract_id.to_string(),
+            creator_id: offer.criteria.creator_id.map(|id| id.to_string()),
+            voice_type: offer.criteria.voice_type,
+            language: offer.criteria.language,
+            tag: offer.criteria.tag,
+            price: offer.price.0.to_string(),
+            remaining: offer.remaining,
+            filled: offer.filled,
+            expires_at: offer.expires_at.0.to_string(),
+            created_at: offer.created_at.0.to_string(),
+        }
+    }
+}
+
+/// Metadata values compare like the voice NFT contract indexes them
+fn normalize(value: &str) -> String {
+    value.trim().to_lowercase()
+}
@@ -0,0 +1,189 @@
+//! NEP-297 events of the marketplace, in the schema of `marketplace-events`
+
+use marketplace_events::{
+    AuctionEndedData, BidPlacedData, BidRefundedData, BundleItemData, DisputeOpenedData, DisputeResolvedData,
+    ListingCancelledData, ListingCreatedData, ListingFrozenData, ListingUnfrozenData, ListingUpdatedData, MarketplaceEvent,
+    RentStreamedData, RentalEndedData, RentalStartedData, SaleSettledData, SealedBidCommittedData, SealedBidRevealedData,
+    SealedBidSlashedData,
+};
+use near_sdk::{env, AccountId};
+
+use crate::{Dispute, Listing, ListingKind, PayoutBreakdown, Rental, SealedBid};
+
+fn emit(event: MarketplaceEvent) {
+    env::log_str(&event.to_log_string());
+}
+
+pub(crate) fn emit_listing_created(listing: &Listing) {
+    let bundle_items = match &listing.kind {
+        ListingKind::Bundle { items, .. } => Some(
+            items
+                .iter()
+                .map(|item| BundleItemData { nft_contract_id: item.nft_contract_id.to_string(), token_id: item.token_id.clone() })
+                .collect(),
+        ),
+        _ => None,
+    };
+
+    emit(MarketplaceEvent::ListingCreated(vec![ListingCreatedData {
+        listing_id: listing.id.0.to_string(),
+        listing_type: listing.kind.as_str().to_string(),
+        status: listing.status.as_str().to_string(),
+        seller_id: listing.seller_id.to_string(),
+        nft_contract_id: listing.nft_contract_id.to_string(),
+        token_id: listing.token_id.clone(),
+        ft_token_id: listing.ft_token_id.as_ref().map(|id| id.to_string()),
+        price: listing.kind.price_at(env::block_timestamp()).to_string(),
+        expires_at: listing.expires_at.0.to_string(),
+        bundle_items,
+    }]));
+}
+
+pub(crate) fn emit_listing_updated(listing: &Listing, added_token_id: Option<&str>) {
+    emit(MarketplaceEvent::ListingUpdated(vec![ListingUpdatedData {
+        listing_id: listing.id.0.to_string(),
+        price: listing.kind.price_at(env::block_timestamp()).to_string(),
+        added_token_id: added_token_id.map(str::to_string),
+    }]));
+}
+
+pub(crate) fn emit_listing_cancelled(listing: &Listing) {
+    emit(MarketplaceEvent::ListingCancelled(vec![ListingCancelledData {
+        listing_id: listing.id.0.to_string(),
+        status: listing.status.as_str().to_string(),
+    }]));
+}
+
+pub(crate) fn emit_bid_placed(listing: &Listing, bid_id: u128, bidder_id: &AccountId, amount: u128) {
+    let end_time = match &listing.kind {
+        ListingKind::Auction { end_time, .. } => end_time.0,
+        _ => listing.expires_at.0,
+    };
+
+    emit(MarketplaceEvent::BidPlaced(vec![BidPlacedData {
+        listing_id: listing.id.0.to_string(),
+        bid_id: bid_id.to_string(),
+        bidder_id: bidder_id.to_string(),
+        amount: amount.to_string(),
+        end_time: end_time.to_string(),
+    }]));
+}
+
+pub(crate) fn emit_bid_refunded(listing: &Listing, bidder_id: &AccountId, amount: u128) {
+    emit(MarketplaceEvent::BidRefunded(vec![BidRefundedData {
+        listing_id: listing.id.0.to_string(),
+        bidder_id: bidder_id.to_string(),
+        amount: amount.to_string(),
+    }]));
+}
+
+pub(crate) fn emit_sale_settled(listing: &Listing, buyer_id: &AccountId, price: u128, breakdown: &PayoutBreakdown) {
+    emit(MarketplaceEvent::SaleSettled(vec![SaleSettledData {
+        listing_id: listing.id.0.to_string(),
+        seller_id: listing.seller_id.to_string(),
+        buyer_id: buyer_id.to_string(),
+        ft_token_id: listing.ft_token_id.as_ref().map(|id| id.to_string()),
+        price: price.to_string(),
+        marketplace_fee: breakdown.marketplace_fee.to_string(),
+        royalties: breakdown.royalties.iter().map(|(receiver_id, amount)| (receiver_id.to_string(), amount.to_string())).collect(),
+        seller_proceeds: breakdown.seller_proceeds.to_string(),
+    }]));
+}
+
+pub(crate) fn emit_auction_ended(listing: &Listing, winner: Option<(&AccountId, u128)>) {
+    emit(MarketplaceEvent::AuctionEnded(vec![AuctionEndedData {
+        listing_id: listing.id.0.to_string(),
+        status: listing.status.as_str().to_string(),
+        winner_id: winner.map(|(winner_id, _)| winner_id.to_string()),
+        winning_bid: winner.map(|(_, amount)| amount.to_string()),
+    }]));
+}
+
+pub(crate) fn emit_rental_started(listing: &Listing, rental: &Rental) {
+    emit(MarketplaceEvent::RentalStarted(vec![RentalStartedData {
+        listing_id: listing.id.0.to_string(),
+        renter_id: rental.renter_id.to_string(),
+        rent: rental.rent.0.to_string(),
+        collateral: rental.collateral.0.to_string(),
+        ends_at: rental.ends_at.0.to_string(),
+    }]));
+}
+
+pub(crate) fn emit_rent_streamed(listing: &Listing, amount: u128, breakdown: &PayoutBreakdown) {
+    emit(MarketplaceEvent::RentStreamed(vec![RentStreamedData {
+        listing_id: listing.id.0.to_string(),
+        amount: amount.to_string(),
+        marketplace_fee: breakdown.marketplace_fee.to_string(),
+        royalties: breakdown.royalties.iter().map(|(receiver_id, amount)| (receiver_id.to_string(), amount.to_string())).collect(),
+        seller_proceeds: breakdown.seller_proceeds.to_string(),
+    }]));
+}
+
+pub(crate) fn emit_rental_ended(listing: &Listing, rental: &Rental) {
+    emit(MarketplaceEvent::RentalEnded(vec![RentalEndedData {
+        listing_id: listing.id.0.to_string(),
+        renter_id: rental.renter_id.to_string(),
+        collateral: rental.collateral.0.to_string(),
+    }]));
+}
+
+pub(crate) fn emit_sealed_bid_committed(bid: &SealedBid) {
+    emit(MarketplaceEvent::SealedBidCommitted(vec![SealedBidCommittedData {
+        listing_id: bid.auction_id.0.to_string(),
+        bid_id: bid.id.0.to_string(),
+        bidder_id: bid.bidder_id.to_string(),
+        deposit: bid.deposit.0.to_string(),
+    }]));
+}
+
+pub(crate) fn emit_sealed_bid_revealed(bid: &SealedBid, amount: u128) {
+    emit(MarketplaceEvent::SealedBidRevealed(vec![SealedBidRevealedData {
+        listing_id: bid.auction_id.0.to_string(),
+        bid_id: bid.id.0.to_string(),
+        bidder_id: bid.bidder_id.to_string(),
+        amount: amount.to_string(),
+    }]));
+}
+
+pub(crate) fn emit_sealed_bid_slashed(bid: &SealedBid) {
+    emit(MarketplaceEvent::SealedBidSlashed(vec![SealedBidSlashedData {
+        listing_id: bid.auction_id.0.to_string(),
+        bid_id: bid.id.0.to_string(),
+        bidder_id: bid.bidder_id.to_string(),
+        amount: bid.deposit.0.to_string(),
+    }]));
+}
+
+pub(crate) fn emit_listing_frozen(listing_id: u128, moderator_id: &AccountId, reason: &str) {
+    emit(MarketplaceEvent::ListingFrozen(vec![ListingFrozenData {
+        listing_id: listing_id.to_string(),
+        moderator_id: moderator_id.to_string(),
+        reason: reason.to_string(),
+    }]));
+}
+
+pub(crate) fn emit_listing_unfrozen(listing_id: u128, moderator_id: &AccountId) {
+    emit(MarketplaceEvent::ListingUnfrozen(vec![ListingUnfrozenData {
+        listing_id: listing_id.to_string(),
+        moderator_id: moderator_id.to_string(),
+    }]));
+}
+
+pub(crate) fn emit_dispute_opened(dispute: &Dispute) {
+    emit(MarketplaceEvent::DisputeOpened(vec![DisputeOpenedData {
+        listing_id: dispute.listing_id.0.to_string(),
+        moderator_id: dispute.opened_by.to_string(),
+        reason: dispute.reason.clone(),
+        expires_at: dispute.expires_at.0.to_string(),
+    }]));
+}
+
+pub(crate) fn emit_dispute_resolved(dispute: &Dispute, payee_id: Option<&AccountId>, timed_out: bool) {
+    emit(MarketplaceEvent::DisputeResolved(vec![DisputeResolvedData {
+        listing_id: dispute.listing_id.0.to_string(),
+        payee_id: payee_id.map(|id| id.to_string()),
+        ft_token_id: dispute.ft_token_id.as_ref().map(|id| id.to_string()),
+        amount: dispute.held_total().to_string(),
+        timed_out,
+    }]));
+}
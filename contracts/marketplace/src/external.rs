@@ -0,0 +1,70 @@
+//! Interfaces of the contracts the marketplace calls into
+
+use near_sdk::{
+    ext_contract, AccountId,
+    json_types::{U128, U64},
+    serde::{Deserialize, Serialize},
+};
+use std::collections::HashMap;
+
+/// NEP-199 payout returned by `nft_transfer_payout`
+#[derive(Serialize, Deserialize)]
+pub struct Payout {
+    pub payout: HashMap<AccountId, U128>,
+}
+
+/// The part of a NEP-171 token the marketplace reads, with the voice NFT
+/// fields collection offers match on (absent on other NFT contracts)
+#[derive(Serialize, Deserialize)]
+pub struct Token {
+    pub token_id: String,
+    pub owner_id: AccountId,
+    pub creator_id: Option<AccountId>,
+    pub metadata: Option<TokenMetadata>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TokenMetadata {
+    pub voice_type: Option<String>,
+    pub language: Option<String>,
+    pub tags: Option<Vec<String>>,
+}
+
+/// NFT contract holding the listed tokens (NEP-171 core + NEP-199 payouts,
+/// and the time-limited user role rentals rely on)
+#[ext_contract(ext_nft_contract)]
+#[allow(dead_code)] // Only called through the generated `ext_nft_contract`
+pub trait ExtNftContract {
+    fn nft_token(&self, token_id: String) -> Option<Token>;
+
+    fn nft_payout(&self, token_id: String, balance: U128, max_len_payout: Option<u32>) -> Payout;
+
+    /// Grants the use of a token until `expires_at`, see `voice_nft`
+    fn nft_set_user(&mut self, token_id: String, user_id: AccountId, expires_at: U64, approval_id: Option<u64>);
+
+    fn nft_transfer(&mut self, receiver_id: AccountId, token_id: String, approval_id: Option<u64>, memo: Option<String>);
+
+    fn nft_transfer_payout(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: String,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+        balance: U128,
+        max_len_payout: Option<u32>,
+    ) -> Payout;
+}
+
+/// NEP-141 token listings can be priced in
+#[ext_contract(ext_ft_contract)]
+#[allow(dead_code)] // Only called through the generated `ext_ft_contract`
+pub trait ExtFtContract {
+    fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+}
+
+/// Treasury swept NEAR fees are deposited to, such as the DAO contract
+#[ext_contract(ext_treasury)]
+#[allow(dead_code)] // Only called through the generated `ext_treasury`
+pub trait ExtTreasury {
+    fn add_to_treasury(&mut self);
+}
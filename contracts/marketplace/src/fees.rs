@@ -0,0 +1,197 @@
+//! Marketplace fees: a governance-controlled schedule tiered by seller volume,
+//! with fees accrued in the contract and swept to the treasury
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::{
+    env, log, near_bindgen, AccountId, NearToken, Promise, PromiseError,
+    json_types::U128,
+    serde::{Deserialize, Serialize},
+};
+
+use crate::{
+    ext_ft_contract, external::ext_treasury, Marketplace, MarketplaceExt, GAS_FOR_ADD_TO_TREASURY, GAS_FOR_FT_TRANSFER,
+    GAS_FOR_RESOLVE_SWEEP, MARKETPLACE_FEE, MAX_MARKETPLACE_FEE,
+};
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+pub struct FeeScheduleView {
+    pub base_fee_bps: u32,
+    pub tiers: Vec<FeeTierView>,
+    pub governance_id: String,
+    pub treasury_id: String,
+}
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+pub struct FeeTierView {
+    pub min_volume: String,
+    pub fee_bps: u32,
+}
+
+/// A reduced fee for sellers whose NEAR sales reached `min_volume`
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeTier {
+    /// Lifetime volume of NEAR sales in yoctoNEAR the tier starts at
+    pub min_volume: U128,
+    /// The fee in basis points
+    pub fee_bps: u32,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
+pub struct FeeSchedule {
+    /// The fee in basis points of sellers below every tier
+    pub base_fee_bps: u32,
+    /// Tiers by ascending `min_volume`
+    pub tiers: Vec<FeeTier>,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self { base_fee_bps: MARKETPLACE_FEE, tiers: Vec::new() }
+    }
+}
+
+impl FeeSchedule {
+    /// The fee in basis points of a seller with the given volume, that of
+    /// the highest tier reached
+    pub fn fee_bps(&self, volume: u128) -> u32 {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|tier| volume >= tier.min_volume.0)
+            .map_or(self.base_fee_bps, |tier| tier.fee_bps)
+    }
+}
+
+#[near_bindgen]
+impl Marketplace {
+    /// Replaces the fee schedule (governance only). Tiers must be sorted by
+    /// ascending `min_volume` and no fee may exceed `MAX_MARKETPLACE_FEE`.
+    pub fn set_fee_schedule(&mut self, base_fee_bps: u32, tiers: Vec<FeeTier>) {
+        self.assert_governance();
+        assert!(base_fee_bps <= MAX_MARKETPLACE_FEE, "Fee is too high");
+        for tier in tiers.iter() {
+            assert!(tier.fee_bps <= MAX_MARKETPLACE_FEE, "Fee is too high");
+        }
+        assert!(tiers.windows(2).all(|pair| pair[0].min_volume.0 < pair[1].min_volume.0), "Tiers must be sorted by volume");
+
+        self.fee_schedule = FeeSchedule { base_fee_bps, tiers };
+
+        log!("Fee schedule updated, base fee {} bps", base_fee_bps);
+    }
+
+    /// Sets the account swept fees are sent to (governance only).
+    pub fn set_treasury(&mut self, treasury_id: AccountId) {
+        self.assert_governance();
+        log!("Treasury changed from {} to {}", self.treasury_id, treasury_id);
+        self.treasury_id = treasury_id;
+    }
+
+    /// Hands governance of the fees over to another account, such as the
+    /// DAO contract (governance only).
+    pub fn set_governance(&mut self, governance_id: AccountId) {
+        self.assert_governance();
+        log!("Governance changed from {} to {}", self.governance_id, governance_id);
+        self.governance_id = governance_id;
+    }
+
+    /// Sends the fees accrued in NEAR or in a NEP-141 token to the treasury,
+    /// anyone can call it. NEAR goes through the treasury's `add_to_treasury`.
+    pub fn sweep_fees(&mut self, ft_token_id: Option<AccountId>) -> Promise {
+        let amount = self.accrued_fees.remove(&ft_token_id).expect("No fees to sweep");
+        let treasury_id = self.treasury_id.clone();
+
+        log!("Sweeping {} of fees to {}", amount.0, treasury_id);
+
+        let transfer = match &ft_token_id {
+            Some(ft_token_id) => ext_ft_contract::ext(ft_token_id.clone())
+                .with_attached_deposit(NearToken::from_yoctonear(1))
+                .with_static_gas(GAS_FOR_FT_TRANSFER)
+                .ft_transfer(treasury_id, amount, Some("Marketplace fees".to_string())),
+            None => ext_treasury::ext(treasury_id)
+                .with_attached_deposit(NearToken::from_yoctonear(amount.0))
+                .with_static_gas(GAS_FOR_ADD_TO_TREASURY)
+                .add_to_treasury(),
+        };
+
+        transfer.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_SWEEP)
+                .resolve_sweep(ft_token_id, amount),
+        )
+    }
+
+    /// Puts the fees of a failed sweep back to be swept again.
+    #[private]
+    pub fn resolve_sweep(&mut self, ft_token_id: Option<AccountId>, amount: U128, #[callback_result] result: Result<(), PromiseError>) -> bool {
+        if result.is_ok() {
+            return true;
+        }
+
+        log!("Sweep of {} to {} failed", amount.0, self.treasury_id);
+        self.internal_accrue_fee(&ft_token_id, amount.0);
+
+        false
+    }
+
+    /// Gets the fee schedule and the accounts governing it.
+    pub fn get_fee_schedule(&self) -> FeeScheduleView {
+        FeeScheduleView {
+            base_fee_bps: self.fee_schedule.base_fee_bps,
+            tiers: self
+                .fee_schedule
+                .tiers
+                .iter()
+                .map(|tier| FeeTierView { min_volume: tier.min_volume.0.to_string(), fee_bps: tier.fee_bps })
+                .collect(),
+            governance_id: self.governance_id.to_string(),
+            treasury_id: self.treasury_id.to_string(),
+        }
+    }
+
+    /// Gets the fee in basis points a seller currently pays.
+    pub fn get_fee_bps(&self, seller_id: AccountId) -> u32 {
+        self.fee_schedule.fee_bps(self.internal_seller_volume(&seller_id))
+    }
+
+    /// Gets the lifetime volume of a seller's NEAR sales.
+    pub fn get_seller_volume(&self, seller_id: AccountId) -> String {
+        self.internal_seller_volume(&seller_id).to_string()
+    }
+
+    /// Gets the fees accrued in NEAR or in a NEP-141 token, not yet swept.
+    pub fn get_accrued_fees(&self, ft_token_id: Option<AccountId>) -> String {
+        self.accrued_fees.get(&ft_token_id).map_or(0, |amount| amount.0).to_string()
+    }
+}
+
+impl Marketplace {
+    fn assert_governance(&self) {
+        assert_eq!(env::predecessor_account_id(), self.governance_id, "Only governance can change fees");
+    }
+
+    fn internal_seller_volume(&self, seller_id: &AccountId) -> u128 {
+        self.seller_volumes.get(seller_id).map_or(0, |volume| volume.0)
+    }
+
+    /// The fee on a sale by `seller_id` for `price`
+    pub(crate) fn internal_marketplace_fee(&self, seller_id: &AccountId, price: u128) -> u128 {
+        price * self.get_fee_bps(seller_id.clone()) as u128 / 10000
+    }
+
+    pub(crate) fn internal_accrue_fee(&mut self, ft_token_id: &Option<AccountId>, amount: u128) {
+        if amount == 0 {
+            return;
+        }
+        let accrued = self.accrued_fees.get(ft_token_id).map_or(0, |accrued| accrued.0);
+        self.accrued_fees.insert(ft_token_id, &U128(accrued + amount));
+    }
+
+    /// Counts a settled sale towards the seller's fee tier. Only NEAR sales
+    /// count, token amounts are not comparable.
+    pub(crate) fn internal_record_volume(&mut self, seller_id: &AccountId, ft_token_id: &Option<AccountId>, price: u128) {
+        if ft_token_id.is_none() {
+            let volume = self.internal_seller_volume(seller_id);
+            self.seller_volumes.insert(seller_id, &U128(volume + price));
+        }
+    }
+}
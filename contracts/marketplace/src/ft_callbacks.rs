@@ -0,0 +1,32 @@
+//! NEP-141 transfer receiver, the entry point for paying in VOICE or another accepted token
+
+use near_sdk::{
+    env, near_bindgen, AccountId, PromiseOrValue,
+    json_types::U128,
+    serde::{Deserialize, Serialize},
+};
+
+use crate::{Marketplace, MarketplaceExt};
+
+/// Arguments passed as `msg` to `ft_transfer_call` on the token contract
+#[derive(Serialize, Deserialize)]
+pub struct PurchaseArgs {
+    /// The listing to buy
+    pub listing_id: U128,
+}
+
+#[near_bindgen]
+impl Marketplace {
+    /// Called by a NEP-141 token contract when `sender_id` transfers `amount`
+    /// to the marketplace. Buys the listing given in `msg`, which must be
+    /// priced in that token; what exceeds the price, or everything when the
+    /// purchase fails, is returned as unused and refunded by the token contract.
+    /// A token removed from the accepted ones still pays for the listings
+    /// already priced in it.
+    pub fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) -> PromiseOrValue<U128> {
+        let ft_token_id = env::predecessor_account_id();
+        let PurchaseArgs { listing_id } = near_sdk::serde_json::from_str(&msg).expect("Invalid purchase arguments");
+
+        self.internal_purchase(listing_id, sender_id, Some(ft_token_id), amount.0).into()
+    }
+}
@@ -3,59 +3,200 @@
 // include!("../../src/marketplace.rs");
 
 use near_sdk::{
-    env, log, AccountId, PanicOnDefault, Promise,
-    collections::{LookupMap, UnorderedSet},
+    assert_one_yocto, env, log, AccountId, BorshStorageKey, Gas, GasWeight, PanicOnDefault, Promise, PromiseError,
+    collections::{LookupMap, TreeMap, UnorderedSet},
     json_types::{U128, U64},
     serde::{Deserialize, Serialize},
     NearToken,
 };
 use borsh::{BorshDeserialize, BorshSerialize};
-
-const MARKETPLACE_FEE: u32 = 250; // 2.5% marketplace fee
+use std::collections::HashMap;
+
+mod bundles;
+mod collection_offers;
+mod events;
+mod external;
+mod fees;
+mod ft_callbacks;
+mod migration;
+mod moderation;
+mod nft_callbacks;
+mod offers;
+mod rentals;
+mod sealed_auctions;
+mod stats;
+mod sweep;
+mod views;
+
+pub use bundles::{BundleItem, BundleItemView};
+pub use collection_offers::{CollectionOffer, CollectionOfferView, TokenCriteria};
+pub use external::Payout;
+use external::{ext_ft_contract, ext_nft_contract};
+pub use fees::{FeeSchedule, FeeScheduleView, FeeTier, FeeTierView};
+pub use moderation::{Dispute, DisputeView};
+pub use offers::{CounterOffer, Offer, OfferView};
+pub use rentals::{CollateralClaim, CollateralClaimView, Rental};
+pub use sealed_auctions::{SealedBid, SealedBidPricing, SealedBidView};
+pub use stats::{AccountStats, AccountStatsView, MarketStatsView, TradeStats, TradeStatsView, VolumeBucket};
+pub use views::ListingFilter;
+use views::paginate_ids;
+
+const MARKETPLACE_FEE: u32 = 250; // 2.5% default marketplace fee
+const MAX_MARKETPLACE_FEE: u32 = 1000; // 10% max marketplace fee
 const ROYALTY_CAP: u32 = 2000; // 20% max royalty
-const MIN_PRICE: u128 = 1_000_000_000_000_000_000_000_000; // 1 VOICE token minimum
+const MIN_PRICE: u128 = 1_000_000_000_000_000_000_000_000; // 1 NEAR minimum for listings priced in NEAR
 const LISTING_DURATION: u64 = 30 * 24 * 60 * 60 * 1_000_000_000; // 30 days in nanoseconds
+const STORAGE_PER_LISTING: u128 = 10_000_000_000_000_000_000_000; // 0.01 NEAR locked per active listing
+const MAX_MIN_INCREMENT_BPS: u32 = 5000; // 50% max minimum bid increment
+const MAX_EXTENSION_WINDOW: u64 = 60 * 60 * 1_000_000_000; // 1 hour in nanoseconds
+const MIN_REVEAL_WINDOW: u64 = 10 * 60 * 1_000_000_000; // 10 minutes in nanoseconds to reveal sealed bids
+const MAX_OFFER_DURATION: u64 = 30 * 24 * 60 * 60 * 1_000_000_000; // 30 days in nanoseconds
+const MAX_LEN_PAYOUT: u32 = 10; // Max payout receivers a purchase can settle
+const MAX_BUNDLE_SIZE: usize = 5; // Max tokens a bundle transfers in one purchase
+const MAX_SWEEP_SIZE: u32 = 2; // Max listings a sweep buys in one call, settling them all fits in the gas of a transaction
+const MAX_COLLECTION_OFFER_QUANTITY: u32 = 100; // Max tokens a collection offer takes
+const RENTAL_DAY: u64 = 24 * 60 * 60 * 1_000_000_000; // 1 day in nanoseconds, the unit rentals are priced in
+const MAX_RENTAL_DAYS: u32 = 365; // Max days a rental can last
+const RENTAL_CLAIM_WINDOW: u64 = RENTAL_DAY; // Time after a rental ends the seller has to claim the collateral
+const DISPUTE_HOLD_DURATION: u64 = 14 * 24 * 60 * 60 * 1_000_000_000; // 14 days in nanoseconds a dispute holds settlement funds
+const MAX_MODERATION_REASON_LEN: usize = 280; // Max bytes of a freeze, blocklist or dispute reason
+const STATS_BUCKET_DURATION: u64 = 60 * 60 * 1_000_000_000; // 1 hour in nanoseconds, the resolution of rolling volumes
+const STATS_BUCKETS: u64 = 7 * 24; // Hourly volumes kept, enough for the 7-day volume
+const DEFAULT_PAGE_SIZE: u64 = 50; // Items a paginated view returns without a limit
+const MAX_PAGE_SIZE: u64 = 100; // Max items a paginated view returns
+const GAS_FOR_NFT_TRANSFER: Gas = Gas::from_tgas(50);
+const GAS_FOR_BUNDLE_ESCROW: Gas = Gas::from_tgas(10); // Per token of a bundle, moved into or out of escrow
+const GAS_FOR_BUNDLE_TRANSFER: Gas = Gas::from_tgas(20); // Per token of a bundle
+const GAS_FOR_SWEEP_TRANSFER: Gas = Gas::from_tgas(20); // Per listing of a sweep
+const GAS_FOR_RESOLVE_SWEEP_LISTING: Gas = Gas::from_tgas(10); // Per listing of a sweep, besides its payments
+const GAS_FOR_SWEEP_PAYMENT: Gas = Gas::from_tgas(8); // Per payout receiver of a swept listing, a transfer and its resolution
+const GAS_FOR_RESOLVE_PURCHASE: Gas = Gas::from_tgas(150); // Room for a transfer and its resolution per payout receiver
+const GAS_FOR_RESOLVE_BUNDLE_ESCROW: Gas = Gas::from_tgas(10); // Besides the transfers and the purchase it settles
+const GAS_FOR_RESOLVE_BUNDLE_PURCHASE: Gas = Gas::from_tgas(100); // Room for a transfer and its resolution per payout receiver
+const GAS_FOR_RESOLVE_REFUND: Gas = Gas::from_tgas(5);
+const GAS_FOR_FT_TRANSFER: Gas = Gas::from_tgas(5);
+const GAS_FOR_ADD_TO_TREASURY: Gas = Gas::from_tgas(10);
+const GAS_FOR_RESOLVE_SWEEP: Gas = Gas::from_tgas(5);
+const GAS_FOR_NFT_TOKEN: Gas = Gas::from_tgas(10);
+const GAS_FOR_RESOLVE_REJECT_OFFER: Gas = Gas::from_tgas(15);
+const GAS_FOR_RESOLVE_COLLECTION_OFFER: Gas = Gas::from_tgas(210); // Room for the sale it settles
+const GAS_FOR_NFT_SET_USER: Gas = Gas::from_tgas(10);
+const GAS_FOR_NFT_PAYOUT: Gas = Gas::from_tgas(10);
+const GAS_FOR_RESOLVE_RENT: Gas = Gas::from_tgas(30);
+const GAS_FOR_MIGRATE: Gas = Gas::from_tgas(50); // At least, migrate also gets the gas left after the upgrade
+const DELIMETER: &str = "."; // Separates contract and token IDs in listing keys
+
+// Storage keys
+#[derive(BorshSerialize, BorshStorageKey)]
+enum StorageKey {
+    Listings,
+    ListingsByToken,
+    ListingsBySeller,
+    ListingsPerSeller { account_hash: [u8; 32] },
+    ListingsByBuyer,
+    ListingsPerBuyer { account_hash: [u8; 32] },
+    Bids,
+    BidsByBidder,
+    BidsPerBidder { account_hash: [u8; 32] },
+    PendingWithdrawals,
+    AcceptedFtTokens,
+    ActiveListings,
+    StorageDeposits,
+    AccruedFees,
+    SellerVolumes,
+    Offers,
+    OffersByToken,
+    OffersPerToken { token_hash: [u8; 32] },
+    OffersByBuyer,
+    OffersPerBuyer { account_hash: [u8; 32] },
+    SealedBids,
+    UnrevealedBids,
+    UnrevealedBidsPerAuction { auction_hash: [u8; 32] },
+    CollectionOffers,
+    CollectionOffersByContract,
+    CollectionOffersPerContract { account_hash: [u8; 32] },
+    Moderators,
+    Blocklist,
+    FrozenListings,
+    Disputes,
+    AccountStats,
+    CollectionStats,
+    TokenStats,
+    VolumeBuckets,
+    ListingsByStatus,
+    ListingsPerStatus { status: ListingStatus },
+    ListingsByPrice,
+    AuctionsByEndTime,
+    DutchAuctions,
+    CollateralClaims,
+}
+
+/// Layout of the contract state, `migrate` converts older layouts to the current one
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateVersion {
+    /// Separate sale, auction and item maps
+    V1,
+    /// A single listing map with a status per listing
+    V2,
+    /// Listings with a currency, an expiry and a storage deposit, and
+    /// withdrawable balances per currency
+    V3,
+    /// Listings indexed by status, by price and by end of bidding
+    V4,
+    /// Claimed rental collateral escrowed apart from disputes
+    V5,
+}
+
+const CURRENT_STATE_VERSION: StateVersion = StateVersion::V5;
 
 // JSON-compatible types for view methods
 #[derive(Serialize, Deserialize, schemars::JsonSchema)]
-pub struct MarketItemView {
+pub struct ListingView {
     pub id: String,
-    pub item_type: String,
+    pub listing_type: String,
+    pub status: String,
     pub seller_id: String,
+    pub nft_contract_id: String,
+    pub token_id: String,
+    pub approval_id: String,
+    pub ft_token_id: Option<String>, // None when priced in NEAR
     pub price: Option<String>,
     pub buyer_id: Option<String>,
     pub start_price: Option<String>,
     pub end_price: Option<String>,
-    pub royalty: Option<String>,
     pub start_time: Option<String>,
     pub end_time: Option<String>,
+    pub highest_bidder_id: Option<String>,
+    pub highest_bid: Option<String>,
+    pub reserve_price: Option<String>, // Only shown for public reserves
+    pub reserve_met: Option<bool>,
+    pub min_increment_bps: Option<u32>,
+    pub extension_window: Option<String>,
+    pub min_next_bid: Option<String>,
+    pub reveal_end_time: Option<String>,
+    pub pricing: Option<String>,
+    pub slash_unrevealed: Option<bool>,
+    pub bid_count: Option<u32>,
+    pub items: Option<Vec<BundleItemView>>,
+    pub max_rental_days: Option<u32>,
+    pub collateral: Option<String>,
+    pub renter_id: Option<String>,
+    pub rented_until: Option<String>,
+    pub frozen: bool,
+    pub disputed: bool,
+    pub expires_at: String,
+    pub storage_deposit: String,
     pub created_at: String,
     pub updated_at: String,
 }
 
 #[derive(Serialize, Deserialize, schemars::JsonSchema)]
-pub struct SaleView {
-    pub id: String,
-    pub seller_id: String,
+pub struct PayoutBreakdownView {
     pub price: String,
-    pub royalty: Option<String>,
-    pub created_at: String,
-    pub updated_at: String,
-}
-
-#[derive(Serialize, Deserialize, schemars::JsonSchema)]
-pub struct AuctionView {
-    pub id: String,
+    pub marketplace_fee: String,
+    pub royalties: HashMap<String, String>,
     pub seller_id: String,
-    pub start_price: String,
-    pub end_price: String,
-    pub royalty: Option<String>,
-    pub start_time: String,
-    pub end_time: String,
-    pub highest_bidder_id: Option<String>,
-    pub highest_bid: Option<String>,
-    pub created_at: String,
-    pub updated_at: String,
+    pub seller_proceeds: String,
 }
 
 #[derive(Serialize, Deserialize, schemars::JsonSchema)]
@@ -68,88 +209,223 @@ pub struct BidView {
 }
 
 // Internal contract types (no JsonSchema needed)
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListingStatus {
+    /// Open for purchase or bids
+    Active,
+    /// Bought or won, the token went to `buyer_id`
+    Sold,
+    /// Withdrawn, replaced by a newer listing or no longer transferable
+    Cancelled,
+    /// Ended without a buyer
+    Expired,
+}
+
+impl ListingStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ListingStatus::Active => "active",
+            ListingStatus::Sold => "sold",
+            ListingStatus::Cancelled => "cancelled",
+            ListingStatus::Expired => "expired",
+        }
+    }
+}
+
 #[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
-pub enum MarketItemType {
-    /// An item that is for sale
+pub enum ListingKind {
+    /// A token for sale at a fixed price
     Sale {
         /// The price of the item in yoctoNEAR
         price: U128,
-        /// The account ID of the seller
-        seller_id: AccountId,
-        /// The royalty percentage for the item
-        royalty: Option<U128>,
     },
-    /// An item that is being auctioned
+    /// A token being auctioned to the highest bidder
     Auction {
         /// The starting price of the auction in yoctoNEAR
         start_price: U128,
-        /// The account ID of the seller
-        seller_id: AccountId,
-        /// The ending price of the auction in yoctoNEAR
+        /// The start time of the auction
+        start_time: U64,
+        /// The end time of the auction
+        end_time: U64,
+        /// The account ID of the highest bidder
+        highest_bidder_id: Option<AccountId>,
+        /// The highest bid amount in yoctoNEAR
+        highest_bid: Option<U128>,
+        /// The lowest winning bid, the token is not sold below it
+        reserve_price: Option<U128>,
+        /// Whether the reserve price is kept out of views
+        reserve_hidden: bool,
+        /// How much a bid must exceed the highest bid by, in basis points
+        min_increment_bps: u32,
+        /// Bids within this long of the end push the end back to this long after the bid
+        extension_window: U64,
+    },
+    /// A token auctioned with sealed bids, committed until `commit_end` and
+    /// revealed until `reveal_end`; the highest revealed bid wins
+    SealedAuction {
+        /// The lowest bid that can win in yoctoNEAR
+        start_price: U128,
+        /// The start time of the bidding phase
+        start_time: U64,
+        /// The end time of the bidding phase, when the reveal phase starts
+        commit_end: U64,
+        /// The end time of the reveal phase
+        reveal_end: U64,
+        /// What the winner pays
+        pricing: SealedBidPricing,
+        /// Whether the deposits of bids not revealed in time are kept as fees
+        slash_unrevealed: bool,
+        /// The number of bids committed
+        bid_count: u32,
+        /// The highest revealed bid, whose deposit stays in escrow
+        highest_bid_id: Option<U128>,
+        /// The account ID of the highest revealed bidder
+        highest_bidder_id: Option<AccountId>,
+        /// The highest revealed bid amount in yoctoNEAR
+        highest_bid: Option<U128>,
+        /// The second highest revealed bid amount in yoctoNEAR
+        second_bid: Option<U128>,
+    },
+    /// A token whose price decays linearly from `start_price` to `end_price`
+    /// over the auction, sold to the first buyer at the current price
+    DutchAuction {
+        /// The price at `start_time` in yoctoNEAR
+        start_price: U128,
+        /// The price at `end_time` in yoctoNEAR
         end_price: U128,
-        /// The royalty percentage for the item
-        royalty: Option<U128>,
         /// The start time of the auction
         start_time: U64,
         /// The end time of the auction
         end_time: U64,
     },
+    /// Several tokens of one seller sold together at a fixed price, each
+    /// joins the bundle once its owner approved the marketplace for it
+    Bundle {
+        /// The price of the whole bundle
+        price: U128,
+        /// The bundled tokens, the listed token first
+        items: Vec<BundleItem>,
+    },
+    /// A token whose use is rented out by the day while the seller keeps it
+    Rental {
+        /// The rent per day in yoctoNEAR
+        price_per_day: U128,
+        /// The longest rental in days
+        max_days: u32,
+        /// Escrowed on top of the rent, returned to the renter at the end
+        collateral: U128,
+        /// The current rental, if any
+        rental: Option<Rental>,
+    },
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
-pub struct MarketItem {
-    /// The ID of the item
-    pub id: U128,
-    /// The type of the item (sale or auction)
-    pub item_type: MarketItemType,
-    /// The account ID of the buyer
-    pub buyer_id: Option<AccountId>,
-    /// The timestamp when the item was created
-    pub created_at: U64,
-    /// The timestamp when the item was last updated
-    pub updated_at: U64,
+impl ListingKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ListingKind::Sale { .. } => "sale",
+            ListingKind::Auction { .. } => "auction",
+            ListingKind::DutchAuction { .. } => "dutch_auction",
+            ListingKind::SealedAuction { .. } => "sealed_auction",
+            ListingKind::Bundle { .. } => "bundle",
+            ListingKind::Rental { .. } => "rental",
+        }
+    }
+
+    /// The price the listing goes for at `timestamp`: the fixed or current
+    /// Dutch price, the highest (revealed) bid of an auction, its start price
+    /// without bids, or the rent per day
+    pub fn price_at(&self, timestamp: u64) -> u128 {
+        match self {
+            ListingKind::Sale { price } | ListingKind::Bundle { price, .. } => price.0,
+            ListingKind::Rental { price_per_day, .. } => price_per_day.0,
+            ListingKind::Auction { start_price, highest_bid, .. } | ListingKind::SealedAuction { start_price, highest_bid, .. } => {
+                highest_bid.unwrap_or(*start_price).0
+            }
+            ListingKind::DutchAuction { .. } => self.dutch_price(timestamp),
+        }
+    }
+
+    /// When bidding ends on an auction or a Dutch auction, sealed bids can be
+    /// committed until their reveal phase
+    pub fn bidding_end(&self) -> Option<u64> {
+        match self {
+            ListingKind::Auction { end_time, .. } | ListingKind::DutchAuction { end_time, .. } => Some(end_time.0),
+            ListingKind::SealedAuction { commit_end, .. } => Some(commit_end.0),
+            _ => None,
+        }
+    }
+
+    /// Price of a Dutch auction at `timestamp`, flat before the start and after the end
+    pub fn dutch_price(&self, timestamp: u64) -> u128 {
+        let ListingKind::DutchAuction { start_price, end_price, start_time, end_time } = self else {
+            env::panic_str("Listing is not a Dutch auction");
+        };
+
+        if timestamp <= start_time.0 {
+            return start_price.0;
+        }
+        if timestamp >= end_time.0 {
+            return end_price.0;
+        }
+
+        let elapsed = (timestamp - start_time.0) as u128;
+        let duration = (end_time.0 - start_time.0) as u128;
+        start_price.0 - mul_div(start_price.0 - end_price.0, elapsed, duration)
+    }
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
-pub struct Sale {
-    /// The ID of the sale
+pub struct Listing {
+    /// The ID of the listing
     pub id: U128,
     /// The account ID of the seller
     pub seller_id: AccountId,
-    /// The price of the item in yoctoNEAR
-    pub price: U128,
-    /// The royalty percentage for the item
-    pub royalty: Option<U128>,
-    /// The timestamp when the sale was created
+    /// The NFT contract of the listed token
+    pub nft_contract_id: AccountId,
+    /// The listed token
+    pub token_id: String,
+    /// The NEP-178 approval ID the marketplace transfers the token with
+    pub approval_id: u64,
+    /// The NEP-141 token prices are in, NEAR when `None`
+    pub ft_token_id: Option<AccountId>,
+    /// Whether the token is sold at a fixed price or auctioned
+    pub kind: ListingKind,
+    /// Where the listing is in its lifecycle
+    pub status: ListingStatus,
+    /// The account ID of the buyer, once sold
+    pub buyer_id: Option<AccountId>,
+    /// The time a sale can no longer be bought, in nanoseconds; auctions
+    /// run until their end time
+    pub expires_at: U64,
+    /// Storage deposit locked by the seller, refunded when the listing closes
+    pub storage_deposit: U128,
+    /// The timestamp when the listing was created
     pub created_at: U64,
-    /// The timestamp when the sale was last updated
+    /// The timestamp when the listing was last updated
     pub updated_at: U64,
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
-pub struct Auction {
-    /// The ID of the auction
-    pub id: U128,
-    /// The account ID of the seller
-    pub seller_id: AccountId,
-    /// The starting price of the auction in yoctoNEAR
-    pub start_price: U128,
-    /// The ending price of the auction in yoctoNEAR
-    pub end_price: U128,
-    /// The royalty percentage for the item
-    pub royalty: Option<U128>,
-    /// The start time of the auction
-    pub start_time: U64,
-    /// The end time of the auction
-    pub end_time: U64,
-    /// The account ID of the highest bidder
-    pub highest_bidder_id: Option<AccountId>,
-    /// The highest bid amount in yoctoNEAR
-    pub highest_bid: Option<U128>,
-    /// The timestamp when the auction was created
-    pub created_at: U64,
-    /// The timestamp when the auction was last updated
-    pub updated_at: U64,
+impl Listing {
+    /// Keys of the tokens the listing holds, the approved ones of a bundle
+    pub fn token_keys(&self) -> Vec<String> {
+        match &self.kind {
+            ListingKind::Bundle { items, .. } => items
+                .iter()
+                .filter(|item| item.approval_id.is_some())
+                .map(|item| contract_and_token_id(&item.nft_contract_id, &item.token_id))
+                .collect(),
+            _ => vec![contract_and_token_id(&self.nft_contract_id, &self.token_id)],
+        }
+    }
+}
+
+/// How the price of a sale is split between the marketplace, royalty
+/// receivers and the seller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayoutBreakdown {
+    pub marketplace_fee: u128,
+    pub royalties: HashMap<AccountId, u128>,
+    pub seller_proceeds: u128,
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
@@ -170,32 +446,91 @@ pub struct Bid {
 #[near_sdk::near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Marketplace {
+    /// Layout of this state
+    pub state_version: StateVersion,
     /// The owner of the contract
     pub owner_id: AccountId,
-    /// The total number of items created
-    pub total_items: U128,
-    /// The total number of sales created
-    pub total_sales: U128,
-    /// The total number of auctions created
-    pub total_auctions: U128,
+    /// The total number of listings created, also the last listing ID
+    pub total_listings: U128,
     /// The total number of bids placed
     pub total_bids: U128,
-    /// The mapping of item IDs to items
-    pub items: LookupMap<U128, MarketItem>,
-    /// The mapping of sale IDs to sales
-    pub sales: LookupMap<U128, Sale>,
-    /// The mapping of auction IDs to auctions
-    pub auctions: LookupMap<U128, Auction>,
+    /// The mapping of listing IDs to sales and auctions
+    pub listings: LookupMap<U128, Listing>,
+    /// The mapping of "nft_contract_id.token_id" to the token's active listing ID
+    pub listings_by_token: LookupMap<String, U128>,
+    /// The mapping of account IDs to the listings they created
+    pub listings_by_seller: LookupMap<AccountId, UnorderedSet<U128>>,
+    /// The mapping of account IDs to the listings they bought or won
+    pub listings_by_buyer: LookupMap<AccountId, UnorderedSet<U128>>,
     /// The mapping of bid IDs to bids
     pub bids: LookupMap<U128, Bid>,
-    /// The mapping of account IDs to their sold items
-    pub sales_by_seller: LookupMap<AccountId, UnorderedSet<U128>>,
-    /// The mapping of account IDs to their purchased items
-    pub sales_by_buyer: LookupMap<AccountId, UnorderedSet<U128>>,
-    /// The mapping of account IDs to their created auctions
-    pub auctions_by_seller: LookupMap<AccountId, UnorderedSet<U128>>,
     /// The mapping of account IDs to their bids
     pub bids_by_bidder: LookupMap<AccountId, UnorderedSet<U128>>,
+    /// Refunds that could not be delivered, withdrawable by their account,
+    /// per NEP-141 token (`None` for NEAR)
+    pub pending_withdrawals: LookupMap<(AccountId, Option<AccountId>), U128>,
+    /// NEP-141 tokens listings can be priced in
+    pub accepted_ft_tokens: UnorderedSet<AccountId>,
+    /// IDs of the listings that are still active
+    pub active_listings: UnorderedSet<U128>,
+    /// Storage deposits of sellers not locked in a listing
+    pub storage_deposits: LookupMap<AccountId, U128>,
+    /// The account allowed to change the fee schedule and treasury
+    pub governance_id: AccountId,
+    /// The account accrued fees are swept to
+    pub treasury_id: AccountId,
+    /// Fees charged on sales, by seller volume
+    pub fee_schedule: FeeSchedule,
+    /// Fees collected and not yet swept, per NEP-141 token (`None` for NEAR)
+    pub accrued_fees: LookupMap<Option<AccountId>, U128>,
+    /// Lifetime volume of each seller's NEAR sales
+    pub seller_volumes: LookupMap<AccountId, U128>,
+    /// The total number of offers made on tokens and collections, also the last offer ID
+    pub total_offers: U128,
+    /// The mapping of offer IDs to open offers, with their escrowed amount
+    pub offers: LookupMap<U128, Offer>,
+    /// The mapping of "nft_contract_id.token_id" to the open offers on the token
+    pub offers_by_token: LookupMap<String, UnorderedSet<U128>>,
+    /// The mapping of account IDs to their open offers
+    pub offers_by_buyer: LookupMap<AccountId, UnorderedSet<U128>>,
+    /// The mapping of bid IDs to sealed bids
+    pub sealed_bids: LookupMap<U128, SealedBid>,
+    /// The mapping of sealed-bid auction IDs to their bids not revealed yet
+    pub unrevealed_bids: LookupMap<U128, UnorderedSet<U128>>,
+    /// The mapping of offer IDs to open collection offers
+    pub collection_offers: LookupMap<U128, CollectionOffer>,
+    /// The mapping of NFT contract IDs to the open collection offers on them
+    pub collection_offers_by_contract: LookupMap<AccountId, UnorderedSet<U128>>,
+    /// Accounts allowed to moderate the marketplace besides the owner
+    pub moderators: UnorderedSet<AccountId>,
+    /// Whether trading is stopped
+    pub paused: bool,
+    /// Accounts and NFT contracts that cannot trade
+    pub blocklist: UnorderedSet<AccountId>,
+    /// IDs of the listings taken out of trading by a moderator
+    pub frozen_listings: UnorderedSet<U128>,
+    /// The mapping of listing IDs to the open disputes over them
+    pub disputes: LookupMap<U128, Dispute>,
+    /// Lifetime sales of the marketplace in NEAR
+    pub market_stats: TradeStats,
+    /// The mapping of account IDs to what they sold and bought in NEAR
+    pub account_stats: LookupMap<AccountId, AccountStats>,
+    /// The mapping of NFT contract IDs to the sales of their tokens in NEAR
+    pub collection_stats: LookupMap<AccountId, TradeStats>,
+    /// The mapping of "nft_contract_id.token_id" to the sales of the token in NEAR
+    pub token_stats: LookupMap<String, TradeStats>,
+    /// Sales of the last `STATS_BUCKETS` hours, by hour modulo `STATS_BUCKETS`
+    pub volume_buckets: LookupMap<u64, VolumeBucket>,
+    /// The mapping of final statuses to the IDs of the listings closed with them
+    pub listings_by_status: LookupMap<ListingStatus, UnorderedSet<U128>>,
+    /// Active listings with a fixed current price, by price and ID
+    pub listings_by_price: TreeMap<(u128, u128), ()>,
+    /// Active auctions and Dutch auctions, by end of bidding and ID
+    pub auctions_by_end_time: TreeMap<(u64, u128), ()>,
+    /// IDs of the active Dutch auctions, whose price falls with time
+    pub dutch_auctions: UnorderedSet<U128>,
+    /// The mapping of rental listing IDs to the collateral their seller claimed
+    pub collateral_claims: LookupMap<U128, CollateralClaim>,
 }
 
 #[near_sdk::near_bindgen]
@@ -205,21 +540,7 @@ impl Marketplace {
     pub fn new(owner_id: AccountId) -> Self {
         assert!(!env::state_exists(), "The contract is already initialized");
 
-        let this = Self {
-            owner_id: owner_id.clone(),
-            total_items: U128(0),
-            total_sales: U128(0),
-            total_auctions: U128(0),
-            total_bids: U128(0),
-            items: LookupMap::new(b"i".to_vec()),
-            sales: LookupMap::new(b"s".to_vec()),
-            auctions: LookupMap::new(b"a".to_vec()),
-            bids: LookupMap::new(b"b".to_vec()),
-            sales_by_seller: LookupMap::new(b"ss".to_vec()),
-            sales_by_buyer: LookupMap::new(b"sb".to_vec()),
-            auctions_by_seller: LookupMap::new(b"as".to_vec()),
-            bids_by_bidder: LookupMap::new(b"bb".to_vec()),
-        };
+        let this = Self::empty_state(owner_id.clone());
 
         // Log the initial owner
         log!("Marketplace contract deployed. Owner: {}", owner_id);
@@ -227,199 +548,368 @@ impl Marketplace {
         this
     }
 
-    /// Creates a new item for sale.
+    /// Deploys new code to the marketplace and migrates the state to its
+    /// layout (owner or governance only, 1 yoctoNEAR). `code` is the wasm,
+    /// passed as Borsh. Deploy and migrate are one batch, a failed migration
+    /// leaves the old code and state in place.
+    #[payable]
+    pub fn upgrade(&mut self, #[serializer(borsh)] code: Vec<u8>) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        assert!(account_id == self.owner_id || account_id == self.governance_id, "Only owner or governance can upgrade the contract");
+
+        log!("Upgrading the marketplace, {} bytes of code from {}", code.len(), account_id);
+
+        Promise::new(env::current_account_id()).deploy_contract(code).function_call_weight(
+            "migrate".to_string(),
+            vec![],
+            NearToken::from_yoctonear(0),
+            GAS_FOR_MIGRATE,
+            GasWeight(1),
+        )
+    }
+
+    /// Migrate the state of a previous deployment to the current layout.
+    /// Called by `upgrade`, or right after deploying the new code in the
+    /// same batch transaction.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        migration::migrate_state()
+    }
+
+    /// Get the layout version of the contract state
+    pub fn get_state_version(&self) -> String {
+        format!("{:?}", self.state_version)
+    }
+
+    /// Buys a token listed for sale in NEAR, or in a running Dutch auction at
+    /// its current price. Listings priced in a NEP-141 token are bought with
+    /// `ft_transfer_call` instead.
     #[payable]
-    pub fn create_sale(
+    pub fn buy_item(&mut self, listing_id: U128) -> Promise {
+        let buyer_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit().as_yoctonear();
+
+        self.internal_purchase(listing_id, buyer_id, None, deposit)
+    }
+
+    /// Settles a sale once the NFT contract answered. When the token was
+    /// transferred, the price is split into the marketplace fee (accrued for
+    /// the treasury), the
+    /// royalties of the NFT payout (capped at `ROYALTY_CAP` of the price) and
+    /// the seller's proceeds; a payout that does not add up is ignored and the
+    /// seller receives all proceeds. Otherwise the token can no longer be sold
+    /// through this listing, which is cancelled, and the buyer's `deposit` is
+    /// refunded.
+    ///
+    /// Returns the part of the deposit the buyer gets back, everything above
+    /// the price. NEAR is refunded here; for purchases in a NEP-141 token it
+    /// is the unused amount of `ft_on_transfer`, refunded by the token contract.
+    #[private]
+    pub fn resolve_purchase(
         &mut self,
+        buyer_id: AccountId,
+        listing_id: U128,
         price: U128,
-        royalty: Option<U128>,
+        deposit: U128,
+        marketplace_fee: U128,
+        #[callback_result] payout: Result<Payout, PromiseError>,
     ) -> U128 {
-        let seller_id = env::predecessor_account_id();
-        let id = self.total_items.0 + 1;
-
-        // Create the sale
-        let sale = Sale {
-            id: U128(id),
-            seller_id: seller_id.clone(),
-            price,
-            royalty,
-            created_at: U64(env::block_timestamp() / 1_000_000),
-            updated_at: U64(env::block_timestamp() / 1_000_000),
+        let mut listing = self.listings.get(&listing_id).expect("Listing not found");
+
+        let Ok(payout) = payout else {
+            log!("Transfer of token {} failed, refunding {}", listing.token_id, buyer_id);
+            return self.internal_refund_purchase(&mut listing, buyer_id, deposit.0);
         };
 
-        // Insert the sale into the sales map
-        self.sales.insert(&U128(id), &sale);
+        let royalties = payout_royalties(payout, price.0 - marketplace_fee.0, &listing.seller_id, &listing.token_id);
+        self.internal_pay_out(&listing, buyer_id, price.0, deposit.0, marketplace_fee.0, royalties)
+    }
 
-        // Add the sale ID to the seller's list of sales
-        let mut sales_by_seller = self.sales_by_seller.get(&seller_id).unwrap_or_else(|| UnorderedSet::new(seller_id.as_bytes().to_vec()));
-        sales_by_seller.insert(&U128(id));
-        self.sales_by_seller.insert(&seller_id, &sales_by_seller);
+    /// Places a bid on an auction. The bid is held in escrow by the
+    /// marketplace and the bid it outbids is refunded.
+    #[payable]
+    pub fn place_bid(&mut self, auction_id: U128) -> U128 {
+        let bidder_id = env::predecessor_account_id();
+        let mut listing = self.listings.get(&auction_id).expect("Auction not found");
+        let deposit = env::attached_deposit().as_yoctonear();
+
+        assert_eq!(listing.status, ListingStatus::Active, "Auction is not active");
+        self.assert_can_trade(&listing, &bidder_id);
+        assert_ne!(bidder_id, listing.seller_id, "Cannot bid on your own auction");
+        let min_bid = min_next_bid(&listing.kind);
+        let ListingKind::Auction { start_time, end_time, highest_bidder_id, highest_bid, extension_window, .. } = &mut listing.kind else {
+            panic!("Listing does not take bids");
+        };
+
+        // Ensure the auction is active
+        let now = env::block_timestamp();
+        assert!(now >= start_time.0, "Auction has not started");
+        assert!(now <= end_time.0, "Auction has ended");
+
+        // Ensure the bid clears the start price and the minimum increment
+        assert!(deposit >= min_bid, "Bid must be at least {}", min_bid);
+
+        // Anti-sniping, a late bid leaves the others a full window to respond
+        if end_time.0 - now < extension_window.0 {
+            end_time.0 = now + extension_window.0;
+            log!("Auction {} extended to {}", auction_id.0, end_time.0);
+        }
+
+        // Give the outbid amount back, the new bid takes its place in escrow
+        let outbid = highest_bidder_id.replace(bidder_id.clone()).zip(highest_bid.replace(U128(deposit)));
+        listing.updated_at = U64(env::block_timestamp() / 1_000_000);
+        self.internal_update_listing(&listing);
+
+        if let Some((outbid_id, outbid_amount)) = outbid {
+            log!("Bid of {} on auction {} outbid, refunding {}", outbid_id, auction_id.0, outbid_amount.0);
+            events::emit_bid_refunded(&listing, &outbid_id, outbid_amount.0);
+            self.internal_send(outbid_id, None, outbid_amount.0);
+        }
+
+        // Record the bid
+        self.total_bids.0 += 1;
+        let bid = Bid {
+            id: self.total_bids,
+            bidder_id: bidder_id.clone(),
+            auction_id,
+            amount: U128(deposit),
+            created_at: U64(env::block_timestamp() / 1_000_000),
+        };
+        self.bids.insert(&bid.id, &bid);
+        events::emit_bid_placed(&listing, bid.id.0, &bidder_id, deposit);
 
-        // Increment the total sales counter
-        self.total_sales.0 += 1;
+        // Add the bid ID to the bidder's list of bids
+        add_to_set(&mut self.bids_by_bidder, &bidder_id, &bid.id, StorageKey::BidsPerBidder {
+            account_hash: env::sha256_array(bidder_id.as_bytes()),
+        });
 
-        // Return the sale ID
-        U128(id)
+        bid.id
     }
 
-    /// Buys an item for sale.
-    #[payable]
-    pub fn buy_item(&mut self, item_id: U128) {
-        let buyer_id = env::predecessor_account_id();
-        let item = self.items.get(&item_id).expect("Item not found");
+    /// Ends an auction. The token goes to the highest bidder and the escrowed
+    /// bid is paid out like a sale; an auction without bids, or whose highest
+    /// bid is below the reserve price, expires and the token stays with the
+    /// seller. A sealed-bid auction ends after its reveal phase.
+    pub fn end_auction(&mut self, auction_id: U128) -> Option<Promise> {
+        let mut listing = self.listings.get(&auction_id).expect("Auction not found");
+
+        assert_eq!(listing.status, ListingStatus::Active, "Auction is not active");
+        // Bids stay in escrow while trading is stopped, a blocked seller's auction still ends
+        self.assert_not_paused();
+        assert!(!self.frozen_listings.contains(&auction_id), "Listing is frozen");
+        if let ListingKind::SealedAuction { .. } = listing.kind {
+            return self.internal_end_sealed_auction(&mut listing);
+        }
+        let (end_time, highest_bidder_id, highest_bid, reserve_price) = match &listing.kind {
+            ListingKind::Auction { end_time, highest_bidder_id, highest_bid, reserve_price, .. } => {
+                (*end_time, highest_bidder_id.clone(), *highest_bid, *reserve_price)
+            }
+            // A Dutch auction still active at its end found no buyer
+            ListingKind::DutchAuction { end_time, .. } => (*end_time, None, None, None),
+            _ => panic!("Listing is not an auction"),
+        };
 
-        // Ensure the item is for sale
-        match item.item_type {
+        // Ensure the auction has ended
+        assert!(env::block_timestamp() > end_time.0, "Auction has not ended yet");
 
-            MarketItemType::Sale { ref seller_id, price, royalty } => {
-                // Ensure the attached deposit is at least the price of the item
-                assert!(env::attached_deposit().as_yoctonear() >= price.0, "Insufficient deposit");
+        let Some((highest_bidder_id, highest_bid)) = highest_bidder_id.zip(highest_bid) else {
+            self.internal_close_listing(&mut listing, ListingStatus::Expired);
+            events::emit_auction_ended(&listing, None);
+            return None;
+        };
 
-                // Calculate the marketplace fee
-                let fee = (price.0 * MARKETPLACE_FEE as u128) / 10000;
-                let proceeds = price.0 - fee;
+        // No sale below the reserve, the escrowed bid goes back
+        if highest_bid.0 < reserve_price.map_or(0, |reserve| reserve.0) {
+            log!("Auction {} ended below its reserve price", auction_id.0);
+            self.internal_close_listing(&mut listing, ListingStatus::Expired);
+            events::emit_auction_ended(&listing, None);
+            events::emit_bid_refunded(&listing, &highest_bidder_id, highest_bid.0);
+            return Some(self.internal_send(highest_bidder_id, None, highest_bid.0));
+        }
 
-                // Transfer the marketplace fee to the contract owner
-                Promise::new(self.owner_id.clone()).transfer(NearToken::from_yoctonear(fee));
+        listing.buyer_id = Some(highest_bidder_id.clone());
+        self.internal_close_listing(&mut listing, ListingStatus::Sold);
+        events::emit_auction_ended(&listing, Some((&highest_bidder_id, highest_bid.0)));
 
-                // Transfer the proceeds to the seller
-                Promise::new(seller_id.clone()).transfer(NearToken::from_yoctonear(proceeds));
+        Some(self.internal_settle(&listing, highest_bidder_id, highest_bid, highest_bid))
+    }
 
-                // Update the item's buyer ID
-                let mut item = item.clone();
-                item.buyer_id = Some(buyer_id.clone());
-                self.items.insert(&item_id, &item);
+    /// Cancels an active listing (seller only). An auction with bids runs
+    /// until it ends, a rented token stays listed until its rental ended.
+    /// The listing's storage deposit is refunded.
+    pub fn cancel_listing(&mut self, listing_id: U128) {
+        let mut listing = self.listings.get(&listing_id).expect("Listing not found");
 
-                // Add the item ID to the buyer's list of purchased items
-                let mut sales_by_buyer = self.sales_by_buyer.get(&buyer_id).unwrap_or_else(|| UnorderedSet::new(buyer_id.as_bytes().to_vec()));
-                sales_by_buyer.insert(&item_id);
-                self.sales_by_buyer.insert(&buyer_id, &sales_by_buyer);
-            },
-            _ => panic!("Item is not for sale"),
+        assert_eq!(env::predecessor_account_id(), listing.seller_id, "Only the seller can cancel a listing");
+        assert_eq!(listing.status, ListingStatus::Active, "Listing is not active");
+        if let ListingKind::Auction { highest_bid: Some(_), .. } = listing.kind {
+            env::panic_str("Cannot cancel an auction that has bids");
+        }
+        if let ListingKind::SealedAuction { bid_count: 1.., .. } = listing.kind {
+            env::panic_str("Cannot cancel an auction that has bids");
+        }
+        if let ListingKind::Rental { rental: Some(_), .. } = listing.kind {
+            env::panic_str("Cannot cancel a listing that is rented, end the rental first");
         }
+
+        self.internal_close_listing(&mut listing, ListingStatus::Cancelled);
+
+        log!("Cancelled listing {}", listing_id.0);
     }
 
-    /// Creates a new auction.
-    #[payable]
-    pub fn create_auction(
-        &mut self,
-        start_price: U128,
-        end_price: U128,
-        royalty: Option<U128>,
-        start_time: U64,
-        end_time: U64,
-    ) -> U128 {
-        let seller_id = env::predecessor_account_id();
-        let id = self.total_items.0 + 1;
-
-        // Create the auction
-        let auction = Auction {
-            id: U128(id),
-            seller_id: seller_id.clone(),
-            start_price,
-            end_price,
-            royalty,
-            start_time,
-            end_time,
-            highest_bidder_id: None,
-            highest_bid: None,
-            created_at: U64(env::block_timestamp() / 1_000_000),
-            updated_at: U64(env::block_timestamp() / 1_000_000),
+    /// Changes the price of an active, unexpired sale or bundle (seller only).
+    pub fn update_price(&mut self, listing_id: U128, price: U128) {
+        let mut listing = self.listings.get(&listing_id).expect("Listing not found");
+
+        assert_eq!(env::predecessor_account_id(), listing.seller_id, "Only the seller can update the price");
+        assert_eq!(listing.status, ListingStatus::Active, "Listing is not active");
+        assert!(env::block_timestamp() < listing.expires_at.0, "Listing has expired");
+        let min_price = if listing.ft_token_id.is_some() { 1 } else { MIN_PRICE };
+        assert!(price.0 >= min_price, "Price is below the minimum");
+        let (ListingKind::Sale { price: listed_price } | ListingKind::Bundle { price: listed_price, .. }) = &mut listing.kind else {
+            env::panic_str("Only a sale can change its price");
         };
 
-        // Insert the auction into the auctions map
-        self.auctions.insert(&U128(id), &auction);
+        log!("Price of listing {} changed from {} to {}", listing_id.0, listed_price.0, price.0);
+
+        *listed_price = price;
+        listing.updated_at = U64(env::block_timestamp() / 1_000_000);
+        self.internal_update_listing(&listing);
+        events::emit_listing_updated(&listing, None);
+    }
 
-        // Add the auction ID to the seller's list of auctions
-        let mut auctions_by_seller = self.auctions_by_seller.get(&seller_id).unwrap_or_else(|| UnorderedSet::new(seller_id.as_bytes().to_vec()));
-        auctions_by_seller.insert(&U128(id));
-        self.auctions_by_seller.insert(&seller_id, &auctions_by_seller);
+    /// Removes the expired listings among `limit` active listings from
+    /// storage, starting at `from_index` of the index of active listings.
+    /// Anyone can call it. Their sellers get the storage deposits back.
+    /// Auctions with bids are settled through `end_auction` instead. A removed
+    /// listing leaves its place in the index to the last one, a caller walking
+    /// the index moves on by the number of listings kept. Returns how many
+    /// listings were removed.
+    pub fn purge_expired(&mut self, from_index: Option<U128>, limit: u32) -> u32 {
+        let now = env::block_timestamp();
+        let start = from_index.map_or(0, |index| index.0 as u64);
+        let end = start.saturating_add(limit as u64).min(self.active_listings.len());
+        let expired: Vec<U128> = (start..end)
+            .filter_map(|index| self.active_listings.as_vector().get(index))
+            .filter(|listing_id| self.listings.get(listing_id).is_some_and(|listing| is_expired(&listing, now)))
+            .collect();
+
+        for listing_id in expired.iter() {
+            let mut listing = self.listings.get(listing_id).expect("Listing not found");
+            self.internal_close_listing(&mut listing, ListingStatus::Expired);
+            self.internal_unindex_listing(&listing);
+            self.listings.remove(listing_id);
+            remove_from_set(&mut self.listings_by_seller, &listing.seller_id, listing_id);
+        }
 
-        // Increment the total auctions counter
-        self.total_auctions.0 += 1;
+        log!("Purged {} expired listings", expired.len());
 
-        // Return the auction ID
-        U128(id)
+        expired.len() as u32
     }
 
-    /// Places a bid on an auction.
+    /// Deposits NEAR towards the storage of an account's listings, each
+    /// active listing locks `STORAGE_PER_LISTING`. Returns the new balance.
     #[payable]
-    pub fn place_bid(&mut self, auction_id: U128) {
-        let bidder_id = env::predecessor_account_id();
-        let auction = self.auctions.get(&auction_id).expect("Auction not found");
-
-        // Ensure the auction is active
-        assert!(env::block_timestamp() >= auction.start_time.0, "Auction has not started");
-        assert!(env::block_timestamp() <= auction.end_time.0, "Auction has ended");
+    pub fn storage_deposit(&mut self, account_id: Option<AccountId>) -> String {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let deposit = env::attached_deposit().as_yoctonear();
+        assert!(deposit > 0, "Requires attached deposit");
 
-        // Ensure the bid is higher than the current highest bid
-        let highest_bid = auction.highest_bid.unwrap_or(U128(0));
+        let balance = self.storage_deposits.get(&account_id).map_or(0, |balance| balance.0) + deposit;
+        self.storage_deposits.insert(&account_id, &U128(balance));
 
-        assert!(env::attached_deposit().as_yoctonear() > highest_bid.0, "Bid is not high enough");
+        balance.to_string()
+    }
 
-        // Calculate the marketplace fee
-        let deposit = env::attached_deposit().as_yoctonear();
-        let fee = (deposit * MARKETPLACE_FEE as u128) / 10000;
-        let proceeds = deposit - fee;
+    /// Withdraws the caller's storage deposit not locked in a listing.
+    pub fn storage_withdraw(&mut self) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let amount = self.storage_deposits.remove(&account_id).expect("Nothing to withdraw");
 
-        // Transfer the marketplace fee to the contract owner
-        Promise::new(self.owner_id.clone()).transfer(NearToken::from_yoctonear(fee));
+        self.internal_send(account_id, None, amount.0)
+    }
 
-        // Transfer the proceeds to the seller
-        Promise::new(auction.seller_id.clone()).transfer(NearToken::from_yoctonear(proceeds));
+    /// Resolves a transfer of NEAR or of a NEP-141 token, crediting the
+    /// amount to the account's withdrawable balance when the transfer failed.
+    #[private]
+    pub fn resolve_refund(
+        &mut self,
+        account_id: AccountId,
+        ft_token_id: Option<AccountId>,
+        amount: U128,
+        #[callback_result] result: Result<(), PromiseError>,
+    ) -> bool {
+        if result.is_ok() {
+            return true;
+        }
 
-        // Update the auction with the new highest bid
-        let mut auction = auction.clone();
-        auction.highest_bidder_id = Some(bidder_id.clone());
-        auction.highest_bid = Some(U128(env::attached_deposit().as_yoctonear()));
-        self.auctions.insert(&auction_id, &auction);
+        let key = (account_id, ft_token_id);
+        let balance = self.pending_withdrawals.get(&key).map_or(0, |balance| balance.0);
+        self.pending_withdrawals.insert(&key, &U128(balance + amount.0));
 
-        // Add the bid ID to the bidder's list of bids
-        let mut bids_by_bidder = self.bids_by_bidder.get(&bidder_id).unwrap_or_else(|| UnorderedSet::new(bidder_id.as_bytes().to_vec()));
-        bids_by_bidder.insert(&U128(self.total_bids.0 + 1));
-        self.bids_by_bidder.insert(&bidder_id, &bids_by_bidder);
+        log!("Transfer of {} to {} failed, it can be withdrawn", amount.0, key.0);
 
-        // Increment the total bids counter
-        self.total_bids.0 += 1;
+        false
     }
 
-    /// Ends an auction and transfers the item to the highest bidder.
-    pub fn end_auction(&mut self, auction_id: U128) {
-        let auction = self.auctions.get(&auction_id).expect("Auction not found");
+    /// Withdraws the caller's balance of transfers that could not be
+    /// delivered, in NEAR or in the given NEP-141 token.
+    pub fn withdraw(&mut self, ft_token_id: Option<AccountId>) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let amount = self.pending_withdrawals.remove(&(account_id.clone(), ft_token_id.clone())).expect("Nothing to withdraw");
 
-        // Ensure the auction has ended
-        assert!(env::block_timestamp() > auction.end_time.0, "Auction has not ended yet");
+        self.internal_send(account_id, ft_token_id, amount.0)
+    }
 
-        // Transfer the item to the highest bidder
-        if let Some(highest_bidder_id) = auction.highest_bidder_id {
-            let item = self.items.get(&auction.id).expect("Item not found");
-            let mut item = item.clone();
-            item.buyer_id = Some(highest_bidder_id.clone());
-            self.items.insert(&auction.id, &item);
+    /// Accepts a NEP-141 token as a currency for listings (owner only).
+    pub fn add_accepted_ft_token(&mut self, ft_token_id: AccountId) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "Only owner can manage accepted tokens");
+        self.accepted_ft_tokens.insert(&ft_token_id);
+        log!("Accepted {} as a listing currency", ft_token_id);
+    }
 
-            // Add the item ID to the highest bidder's list of purchased items
-            let mut sales_by_buyer = self.sales_by_buyer.get(&highest_bidder_id).unwrap_or_else(|| UnorderedSet::new(highest_bidder_id.as_bytes().to_vec()));
-            sales_by_buyer.insert(&auction.id);
-            self.sales_by_buyer.insert(&highest_bidder_id, &sales_by_buyer);
-        }
+    /// Stops accepting a NEP-141 token for new listings (owner only).
+    /// Existing listings in the token can still be bought.
+    pub fn remove_accepted_ft_token(&mut self, ft_token_id: AccountId) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "Only owner can manage accepted tokens");
+        self.accepted_ft_tokens.remove(&ft_token_id);
+        log!("Removed {} from the listing currencies", ft_token_id);
+    }
 
-        // Remove the auction
-        self.auctions.remove(&auction_id);
+    /// Gets the details of a listing.
+    pub fn get_listing(&self, listing_id: U128) -> Option<ListingView> {
+        self.listings.get(&listing_id).map(|listing| self.listing_to_view(listing))
     }
 
-    /// Gets the details of an item.
-    pub fn get_item(&self, item_id: U128) -> Option<MarketItemView> {
-        self.items.get(&item_id).map(|item| self.item_to_view(item))
+    /// Gets the active listing of a token, if it is listed.
+    pub fn get_listing_by_token(&self, nft_contract_id: AccountId, token_id: String) -> Option<ListingView> {
+        self.listings_by_token
+            .get(&contract_and_token_id(&nft_contract_id, &token_id))
+            .and_then(|listing_id| self.listings.get(&listing_id))
+            .map(|listing| self.listing_to_view(listing))
     }
 
-    /// Gets the details of a sale.
-    pub fn get_sale(&self, sale_id: U128) -> Option<SaleView> {
-        self.sales.get(&sale_id).map(|sale| self.sale_to_view(sale))
+    /// Gets the price a Dutch auction can be bought at right now.
+    pub fn current_price(&self, auction_id: U128) -> String {
+        let listing = self.listings.get(&auction_id).expect("Auction not found");
+        listing.kind.dutch_price(env::block_timestamp()).to_string()
     }
 
-    /// Gets the details of an auction.
-    pub fn get_auction(&self, auction_id: U128) -> Option<AuctionView> {
-        self.auctions.get(&auction_id).map(|auction| self.auction_to_view(auction))
+    /// Previews how `price` would be split on a sale by `seller_id` of a token
+    /// with the given royalty (account -> basis points, as in the token's
+    /// metadata on the NFT contract), at the seller's current fee.
+    pub fn preview_payout(&self, price: U128, seller_id: AccountId, royalty: HashMap<AccountId, u32>) -> PayoutBreakdownView {
+        let marketplace_fee = self.internal_marketplace_fee(&seller_id, price.0);
+        let proceeds = price.0 - marketplace_fee;
+        let royalties = royalty
+            .into_iter()
+            .filter(|(receiver_id, _)| *receiver_id != seller_id)
+            .map(|(receiver_id, bps)| (receiver_id, proceeds * bps as u128 / 10000))
+            .collect();
+
+        breakdown_to_view(price.0, &seller_id, &split_price(price.0, marketplace_fee, royalties))
     }
 
     /// Gets the details of a bid.
@@ -427,131 +917,485 @@ impl Marketplace {
         self.bids.get(&bid_id).map(|bid| self.bid_to_view(bid))
     }
 
-    /// Gets the list of item IDs owned by an account.
-    pub fn get_items_by_owner(&self, owner_id: AccountId) -> Vec<String> {
-        let mut items = Vec::new();
+    /// Gets a page of the listing IDs created by an account.
+    pub fn get_listings_by_seller(&self, seller_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<String> {
+        self.listings_by_seller.get(&seller_id).map_or_else(Vec::new, |set| paginate_ids(&set, from_index, limit))
+    }
+
+    /// Gets a page of the listing IDs bought or won by an account.
+    pub fn get_listings_by_buyer(&self, buyer_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<String> {
+        self.listings_by_buyer.get(&buyer_id).map_or_else(Vec::new, |set| paginate_ids(&set, from_index, limit))
+    }
+
+    /// Gets the balance an account can withdraw, in NEAR or in the given NEP-141 token.
+    pub fn get_pending_withdrawal(&self, account_id: AccountId, ft_token_id: Option<AccountId>) -> String {
+        self.pending_withdrawals.get(&(account_id, ft_token_id)).map_or(0, |balance| balance.0).to_string()
+    }
+
+    /// Gets the storage deposit of an account not locked in a listing.
+    pub fn storage_balance_of(&self, account_id: AccountId) -> String {
+        self.storage_deposits.get(&account_id).map_or(0, |balance| balance.0).to_string()
+    }
+
+    /// Gets the storage deposit an account needs per active listing.
+    pub fn storage_minimum_balance(&self) -> String {
+        STORAGE_PER_LISTING.to_string()
+    }
+
+    /// Gets the NEP-141 tokens listings can be priced in.
+    pub fn get_accepted_ft_tokens(&self) -> Vec<String> {
+        self.accepted_ft_tokens.iter().map(|ft_token_id| ft_token_id.to_string()).collect()
+    }
+
+    /// Gets a page of the bid IDs of a bidder, open and sealed.
+    pub fn get_bids_by_bidder(&self, bidder_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<String> {
+        self.bids_by_bidder.get(&bidder_id).map_or_else(Vec::new, |set| paginate_ids(&set, from_index, limit))
+    }
+
+    /// Gets the total number of listings.
+    pub fn get_total_listings(&self) -> String {
+        self.total_listings.0.to_string()
+    }
+
+    /// Gets the total number of bids.
+    pub fn get_total_bids(&self) -> String {
+        self.total_bids.0.to_string()
+    }
+}
+
+impl Marketplace {
+    pub(crate) fn empty_state(owner_id: AccountId) -> Self {
+        Self {
+            state_version: CURRENT_STATE_VERSION,
+            owner_id: owner_id.clone(),
+            total_listings: U128(0),
+            total_bids: U128(0),
+            listings: LookupMap::new(StorageKey::Listings),
+            listings_by_token: LookupMap::new(StorageKey::ListingsByToken),
+            listings_by_seller: LookupMap::new(StorageKey::ListingsBySeller),
+            listings_by_buyer: LookupMap::new(StorageKey::ListingsByBuyer),
+            bids: LookupMap::new(StorageKey::Bids),
+            bids_by_bidder: LookupMap::new(StorageKey::BidsByBidder),
+            pending_withdrawals: LookupMap::new(StorageKey::PendingWithdrawals),
+            accepted_ft_tokens: UnorderedSet::new(StorageKey::AcceptedFtTokens),
+            active_listings: UnorderedSet::new(StorageKey::ActiveListings),
+            storage_deposits: LookupMap::new(StorageKey::StorageDeposits),
+            // The owner governs the fees until governance is handed to the DAO
+            governance_id: owner_id.clone(),
+            treasury_id: owner_id,
+            fee_schedule: FeeSchedule::default(),
+            accrued_fees: LookupMap::new(StorageKey::AccruedFees),
+            seller_volumes: LookupMap::new(StorageKey::SellerVolumes),
+            total_offers: U128(0),
+            offers: LookupMap::new(StorageKey::Offers),
+            offers_by_token: LookupMap::new(StorageKey::OffersByToken),
+            offers_by_buyer: LookupMap::new(StorageKey::OffersByBuyer),
+            sealed_bids: LookupMap::new(StorageKey::SealedBids),
+            unrevealed_bids: LookupMap::new(StorageKey::UnrevealedBids),
+            collection_offers: LookupMap::new(StorageKey::CollectionOffers),
+            collection_offers_by_contract: LookupMap::new(StorageKey::CollectionOffersByContract),
+            moderators: UnorderedSet::new(StorageKey::Moderators),
+            paused: false,
+            blocklist: UnorderedSet::new(StorageKey::Blocklist),
+            frozen_listings: UnorderedSet::new(StorageKey::FrozenListings),
+            disputes: LookupMap::new(StorageKey::Disputes),
+            market_stats: TradeStats::default(),
+            account_stats: LookupMap::new(StorageKey::AccountStats),
+            collection_stats: LookupMap::new(StorageKey::CollectionStats),
+            token_stats: LookupMap::new(StorageKey::TokenStats),
+            volume_buckets: LookupMap::new(StorageKey::VolumeBuckets),
+            listings_by_status: LookupMap::new(StorageKey::ListingsByStatus),
+            listings_by_price: TreeMap::new(StorageKey::ListingsByPrice),
+            auctions_by_end_time: TreeMap::new(StorageKey::AuctionsByEndTime),
+            dutch_auctions: UnorderedSet::new(StorageKey::DutchAuctions),
+            collateral_claims: LookupMap::new(StorageKey::CollateralClaims),
+        }
+    }
+
+    /// Stores a listing and indexes it by seller and status, and by token while active
+    pub(crate) fn internal_add_listing(&mut self, listing: &Listing) {
+        self.listings.insert(&listing.id, listing);
+        self.internal_index_listing(listing);
 
-        // Get the sales owned by the account
-        if let Some(sales) = self.sales_by_seller.get(&owner_id) {
-            for sale_id in sales.to_vec() {
-                if let Some(sale) = self.sales.get(&sale_id) {
-                    items.push(sale.id.0.to_string());
-                }
+        if listing.status == ListingStatus::Active {
+            for token_key in listing.token_keys() {
+                self.listings_by_token.insert(&token_key, &listing.id);
             }
         }
 
-        // Get the auctions owned by the account
-        if let Some(auctions) = self.auctions_by_seller.get(&owner_id) {
-            for auction_id in auctions.to_vec() {
-                if let Some(auction) = self.auctions.get(&auction_id) {
-                    items.push(auction.id.0.to_string());
-                }
+        add_to_set(&mut self.listings_by_seller, &listing.seller_id, &listing.id, StorageKey::ListingsPerSeller {
+            account_hash: env::sha256_array(listing.seller_id.as_bytes()),
+        });
+
+        events::emit_listing_created(listing);
+    }
+
+    /// Stores a changed listing and moves it in the indexes of `internal_index_listing`,
+    /// after a change of status, price or end of bidding
+    pub(crate) fn internal_update_listing(&mut self, listing: &Listing) {
+        if let Some(previous) = self.listings.get(&listing.id) {
+            self.internal_unindex_listing(&previous);
+        }
+        self.listings.insert(&listing.id, listing);
+        self.internal_index_listing(listing);
+    }
+
+    /// Indexes a listing by status. Active listings are also indexed by price,
+    /// or as Dutch auctions whose price cannot be indexed, and auctions by end
+    /// of bidding.
+    pub(crate) fn internal_index_listing(&mut self, listing: &Listing) {
+        if listing.status != ListingStatus::Active {
+            add_to_set(&mut self.listings_by_status, &listing.status, &listing.id, StorageKey::ListingsPerStatus { status: listing.status });
+            return;
+        }
+
+        self.active_listings.insert(&listing.id);
+        match listing.kind {
+            ListingKind::DutchAuction { .. } => {
+                self.dutch_auctions.insert(&listing.id);
             }
+            _ => {
+                self.listings_by_price.insert(&(listing.kind.price_at(env::block_timestamp()), listing.id.0), &());
+            }
+        }
+        if let Some(end_time) = listing.kind.bidding_end() {
+            self.auctions_by_end_time.insert(&(end_time, listing.id.0), &());
+        }
+    }
+
+    /// Removes a listing from the indexes it was added to, as it was stored
+    pub(crate) fn internal_unindex_listing(&mut self, listing: &Listing) {
+        if listing.status != ListingStatus::Active {
+            remove_from_set(&mut self.listings_by_status, &listing.status, &listing.id);
+            return;
         }
 
-        items
+        self.active_listings.remove(&listing.id);
+        match listing.kind {
+            ListingKind::DutchAuction { .. } => {
+                self.dutch_auctions.remove(&listing.id);
+            }
+            _ => {
+                self.listings_by_price.remove(&(listing.kind.price_at(env::block_timestamp()), listing.id.0));
+            }
+        }
+        if let Some(end_time) = listing.kind.bidding_end() {
+            self.auctions_by_end_time.remove(&(end_time, listing.id.0));
+        }
     }
 
-    /// Gets the list of sale IDs for an account.
-    pub fn get_sales_by_seller(&self, seller_id: AccountId) -> Vec<String> {
-        self.sales_by_seller.get(&seller_id).map_or_else(Vec::new, |set| 
-            set.to_vec().into_iter().map(|id| id.0.to_string()).collect()
-        )
+    /// Cancels the active listing of a token before it is listed again or
+    /// sold by other means. An auction with bids cannot be cancelled. The
+    /// storage deposit goes back to the seller's balance, ready for the next
+    /// listing.
+    pub(crate) fn internal_cancel_token_listing(&mut self, nft_contract_id: &AccountId, token_id: &str) {
+        if let Some(previous_id) = self.listings_by_token.get(&contract_and_token_id(nft_contract_id, token_id)) {
+            let mut previous = self.listings.get(&previous_id).expect("Listing not found");
+            if let ListingKind::Auction { highest_bid: Some(_), .. } = previous.kind {
+                env::panic_str("Token is in an auction that already has bids");
+            }
+            if let ListingKind::SealedAuction { bid_count: 1.., .. } = previous.kind {
+                env::panic_str("Token is in an auction that already has bids");
+            }
+            if let ListingKind::Rental { rental: Some(_), .. } = previous.kind {
+                env::panic_str("Token is rented, end the rental first");
+            }
+            let storage_deposit = std::mem::replace(&mut previous.storage_deposit, U128(0));
+            let balance = self.storage_deposits.get(&previous.seller_id).map_or(0, |balance| balance.0);
+            self.storage_deposits.insert(&previous.seller_id, &U128(balance + storage_deposit.0));
+            self.internal_close_listing(&mut previous, ListingStatus::Cancelled);
+        }
     }
 
-    /// Gets the list of auction IDs for an account.
-    pub fn get_auctions_by_seller(&self, seller_id: AccountId) -> Vec<String> {
-        self.auctions_by_seller.get(&seller_id).map_or_else(Vec::new, |set| 
-            set.to_vec().into_iter().map(|id| id.0.to_string()).collect()
-        )
+    /// Locks `STORAGE_PER_LISTING` of a seller's storage deposit for a new listing
+    pub(crate) fn internal_lock_storage(&mut self, seller_id: &AccountId) -> U128 {
+        let balance = self.storage_deposits.get(seller_id).map_or(0, |balance| balance.0);
+        assert!(balance >= STORAGE_PER_LISTING, "Insufficient storage deposit, {} is needed per listing", STORAGE_PER_LISTING);
+        self.storage_deposits.insert(seller_id, &U128(balance - STORAGE_PER_LISTING));
+        U128(STORAGE_PER_LISTING)
     }
 
-    /// Gets the list of bid IDs for a bidder.
-    pub fn get_bids_by_bidder(&self, bidder_id: AccountId) -> Vec<String> {
-        self.bids_by_bidder.get(&bidder_id).map_or_else(Vec::new, |set| 
-            set.to_vec().into_iter().map(|id| id.0.to_string()).collect()
-        )
+    /// Closes an active sale, bundle or Dutch auction for `buyer_id`, who
+    /// paid `deposit` in `ft_token_id` (`None` for NEAR), and settles it.
+    pub(crate) fn internal_purchase(&mut self, listing_id: U128, buyer_id: AccountId, ft_token_id: Option<AccountId>, deposit: u128) -> Promise {
+        let mut listing = self.listings.get(&listing_id).expect("Listing not found");
+
+        assert_eq!(listing.status, ListingStatus::Active, "Listing is not active");
+        self.assert_can_trade(&listing, &buyer_id);
+        assert_eq!(listing.ft_token_id, ft_token_id, "Listing is priced in another currency");
+        let price = match listing.kind {
+            ListingKind::Sale { price } => {
+                assert!(env::block_timestamp() < listing.expires_at.0, "Listing has expired");
+                price
+            }
+            ListingKind::Bundle { price, ref items } => {
+                assert!(env::block_timestamp() < listing.expires_at.0, "Listing has expired");
+                assert!(items.iter().all(|item| item.approval_id.is_some()), "Bundle is not fully approved yet");
+                price
+            }
+            ListingKind::DutchAuction { start_time, end_time, .. } => {
+                let now = env::block_timestamp();
+                assert!(now >= start_time.0, "Auction has not started");
+                assert!(now <= end_time.0, "Auction has ended");
+                U128(listing.kind.dutch_price(now))
+            }
+            _ => panic!("Listing is not for sale"),
+        };
+        assert_ne!(buyer_id, listing.seller_id, "Cannot buy your own listing");
+        // Ensure the deposit is at least the price of the item
+        assert!(deposit >= price.0, "Insufficient deposit");
+
+        // Closed before the transfer so the token cannot be bought twice
+        listing.buyer_id = Some(buyer_id.clone());
+        self.internal_close_listing(&mut listing, ListingStatus::Sold);
+
+        match listing.kind {
+            ListingKind::Bundle { .. } => self.internal_settle_bundle(&listing, buyer_id, price, U128(deposit)),
+            _ => self.internal_settle(&listing, buyer_id, price, U128(deposit)),
+        }
     }
 
-    /// Gets the total number of items.
-    pub fn get_total_items(&self) -> String {
-        self.total_items.0.to_string()
+    /// Transfers the token of a closed listing to the buyer through
+    /// `nft_transfer_payout` and settles `price` out of the buyer's `deposit`
+    /// in `resolve_purchase`.
+    pub(crate) fn internal_settle(&self, listing: &Listing, buyer_id: AccountId, price: U128, deposit: U128) -> Promise {
+        // The NFT contract splits what is left after the marketplace fee
+        let marketplace_fee = self.internal_marketplace_fee(&listing.seller_id, price.0);
+        let proceeds = price.0 - marketplace_fee;
+
+        ext_nft_contract::ext(listing.nft_contract_id.clone())
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .with_static_gas(GAS_FOR_NFT_TRANSFER)
+            .nft_transfer_payout(
+                buyer_id.clone(),
+                listing.token_id.clone(),
+                Some(listing.approval_id),
+                Some(format!("Marketplace listing {}", listing.id.0)),
+                U128(proceeds),
+                Some(MAX_LEN_PAYOUT),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_PURCHASE)
+                    .resolve_purchase(buyer_id, listing.id, price, deposit, U128(marketplace_fee)),
+            )
     }
 
-    /// Gets the total number of sales.
-    pub fn get_total_sales(&self) -> String {
-        self.total_sales.0.to_string()
+    /// Cancels a sold listing whose token could not be transferred and
+    /// refunds the buyer's `deposit`, returning the amount refunded
+    pub(crate) fn internal_refund_purchase(&mut self, listing: &mut Listing, buyer_id: AccountId, deposit: u128) -> U128 {
+        listing.buyer_id = None;
+        listing.status = ListingStatus::Cancelled;
+        listing.updated_at = U64(env::block_timestamp() / 1_000_000);
+        self.internal_update_listing(listing);
+        events::emit_listing_cancelled(listing);
+        if listing.ft_token_id.is_none() {
+            self.internal_send(buyer_id, None, deposit);
+        }
+        U128(deposit)
     }
 
-    /// Gets the total number of auctions.
-    pub fn get_total_auctions(&self) -> String {
-        self.total_auctions.0.to_string()
+    /// Pays out a settled sale of `price` out of the buyer's `deposit`: the
+    /// fee is accrued, royalties are capped and paid with the seller's
+    /// proceeds, and a NEAR surplus is refunded. Returns the surplus.
+    pub(crate) fn internal_pay_out(
+        &mut self,
+        listing: &Listing,
+        buyer_id: AccountId,
+        price: u128,
+        deposit: u128,
+        marketplace_fee: u128,
+        royalties: HashMap<AccountId, u128>,
+    ) -> U128 {
+        let ft_token_id = listing.ft_token_id.clone();
+        let breakdown = split_price(price, marketplace_fee, royalties);
+
+        // Everything is paid out in the currency of the listing
+        self.internal_accrue_fee(&ft_token_id, breakdown.marketplace_fee);
+        self.internal_record_volume(&listing.seller_id, &ft_token_id, price);
+        self.internal_record_trade(listing, &buyer_id, price);
+        for (receiver_id, amount) in breakdown.royalties.iter() {
+            self.internal_pay_or_hold(listing, receiver_id.clone(), *amount);
+        }
+        self.internal_pay_or_hold(listing, listing.seller_id.clone(), breakdown.seller_proceeds);
+
+        events::emit_sale_settled(listing, &buyer_id, price, &breakdown);
+
+        // Add the listing ID to the buyer's list of purchased items
+        add_to_set(&mut self.listings_by_buyer, &buyer_id, &listing.id, StorageKey::ListingsPerBuyer {
+            account_hash: env::sha256_array(buyer_id.as_bytes()),
+        });
+
+        log!("Sold listing {} of token {} to {} for {}", listing.id.0, listing.token_id, buyer_id, price);
+
+        // Overpaid NEP-141 tokens go back with the transfer resolution
+        let surplus = deposit - price;
+        if ft_token_id.is_none() && surplus > 0 {
+            log!("Refunding {} paid above the price to {}", surplus, buyer_id);
+            self.internal_send(buyer_id, None, surplus);
+        }
+
+        U128(surplus)
     }
 
-    /// Gets the total number of bids.
-    pub fn get_total_bids(&self) -> String {
-        self.total_bids.0.to_string()
+    /// Sends `amount` of NEAR or of a NEP-141 token to an account, falling
+    /// back to its withdrawable balance when the transfer fails
+    pub(crate) fn internal_send(&self, account_id: AccountId, ft_token_id: Option<AccountId>, amount: u128) -> Promise {
+        let transfer = match &ft_token_id {
+            Some(ft_token_id) => ext_ft_contract::ext(ft_token_id.clone())
+                .with_attached_deposit(NearToken::from_yoctonear(1))
+                .with_static_gas(GAS_FOR_FT_TRANSFER)
+                .ft_transfer(account_id.clone(), U128(amount), None),
+            None => Promise::new(account_id.clone()).transfer(NearToken::from_yoctonear(amount)),
+        };
+
+        transfer.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_REFUND)
+                .resolve_refund(account_id, ft_token_id, U128(amount)),
+        )
+    }
+
+    /// Pays out part of a settlement through `internal_send`, so a payment
+    /// that cannot be delivered stays withdrawable by its receiver
+    fn internal_pay(&self, account_id: AccountId, ft_token_id: &Option<AccountId>, amount: u128) {
+        if amount > 0 {
+            self.internal_send(account_id, ft_token_id.clone(), amount);
+        }
+    }
+
+    /// Moves an active listing to a final status, the token can be listed
+    /// again. The storage deposit of the listing is refunded to the seller.
+    pub(crate) fn internal_close_listing(&mut self, listing: &mut Listing, status: ListingStatus) {
+        listing.status = status;
+        listing.updated_at = U64(env::block_timestamp() / 1_000_000);
+        let storage_deposit = std::mem::replace(&mut listing.storage_deposit, U128(0));
+        self.internal_update_listing(listing);
+        for token_key in listing.token_keys() {
+            if self.listings_by_token.get(&token_key) == Some(listing.id) {
+                self.listings_by_token.remove(&token_key);
+            }
+        }
+        self.frozen_listings.remove(&listing.id);
+        if status != ListingStatus::Sold {
+            events::emit_listing_cancelled(listing);
+        }
+
+        self.internal_pay(listing.seller_id.clone(), &None, storage_deposit.0);
     }
 
     // Helper methods to convert internal types to view types
-    fn item_to_view(&self, item: MarketItem) -> MarketItemView {
-        match item.item_type {
-            MarketItemType::Sale { seller_id, price, royalty } => MarketItemView {
-                id: item.id.0.to_string(),
-                item_type: "sale".to_string(),
-                seller_id: seller_id.to_string(),
-                price: Some(price.0.to_string()),
-                buyer_id: item.buyer_id.map(|id| id.to_string()),
-                start_price: None,
-                end_price: None,
-                royalty: royalty.map(|r| r.0.to_string()),
-                start_time: None,
-                end_time: None,
-                created_at: item.created_at.0.to_string(),
-                updated_at: item.updated_at.0.to_string(),
-            },
-            MarketItemType::Auction { seller_id, start_price, end_price, royalty, start_time, end_time } => MarketItemView {
-                id: item.id.0.to_string(),
-                item_type: "auction".to_string(),
-                seller_id: seller_id.to_string(),
-                price: None,
-                buyer_id: item.buyer_id.map(|id| id.to_string()),
-                start_price: Some(start_price.0.to_string()),
-                end_price: Some(end_price.0.to_string()),
-                royalty: royalty.map(|r| r.0.to_string()),
-                start_time: Some(start_time.0.to_string()),
-                end_time: Some(end_time.0.to_string()),
-                created_at: item.created_at.0.to_string(),
-                updated_at: item.updated_at.0.to_string(),
-            },
-        }
-    }
-
-    fn sale_to_view(&self, sale: Sale) -> SaleView {
-        SaleView {
-            id: sale.id.0.to_string(),
-            seller_id: sale.seller_id.to_string(),
-            price: sale.price.0.to_string(),
-            royalty: sale.royalty.map(|r| r.0.to_string()),
-            created_at: sale.created_at.0.to_string(),
-            updated_at: sale.updated_at.0.to_string(),
-        }
-    }
-
-    fn auction_to_view(&self, auction: Auction) -> AuctionView {
-        AuctionView {
-            id: auction.id.0.to_string(),
-            seller_id: auction.seller_id.to_string(),
-            start_price: auction.start_price.0.to_string(),
-            end_price: auction.end_price.0.to_string(),
-            royalty: auction.royalty.map(|r| r.0.to_string()),
-            start_time: auction.start_time.0.to_string(),
-            end_time: auction.end_time.0.to_string(),
-            highest_bidder_id: auction.highest_bidder_id.map(|id| id.to_string()),
-            highest_bid: auction.highest_bid.map(|b| b.0.to_string()),
-            created_at: auction.created_at.0.to_string(),
-            updated_at: auction.updated_at.0.to_string(),
+    fn listing_to_view(&self, listing: Listing) -> ListingView {
+        let mut view = ListingView {
+            id: listing.id.0.to_string(),
+            listing_type: listing.kind.as_str().to_string(),
+            status: listing.status.as_str().to_string(),
+            seller_id: listing.seller_id.to_string(),
+            nft_contract_id: listing.nft_contract_id.to_string(),
+            token_id: listing.token_id,
+            approval_id: listing.approval_id.to_string(),
+            ft_token_id: listing.ft_token_id.map(|id| id.to_string()),
+            price: None,
+            buyer_id: listing.buyer_id.map(|id| id.to_string()),
+            start_price: None,
+            end_price: None,
+            start_time: None,
+            end_time: None,
+            highest_bidder_id: None,
+            highest_bid: None,
+            reserve_price: None,
+            reserve_met: None,
+            min_increment_bps: None,
+            extension_window: None,
+            min_next_bid: None,
+            reveal_end_time: None,
+            pricing: None,
+            slash_unrevealed: None,
+            bid_count: None,
+            items: None,
+            max_rental_days: None,
+            collateral: None,
+            renter_id: None,
+            rented_until: None,
+            frozen: self.frozen_listings.contains(&listing.id),
+            disputed: self.disputes.get(&listing.id).is_some(),
+            expires_at: listing.expires_at.0.to_string(),
+            storage_deposit: listing.storage_deposit.0.to_string(),
+            created_at: listing.created_at.0.to_string(),
+            updated_at: listing.updated_at.0.to_string(),
+        };
+
+        if let ListingKind::Auction { .. } = listing.kind {
+            view.min_next_bid = Some(min_next_bid(&listing.kind).to_string());
         }
+
+        match listing.kind {
+            ListingKind::Sale { price } => {
+                view.price = Some(price.0.to_string());
+            }
+            ListingKind::Auction {
+                start_price,
+                start_time,
+                end_time,
+                highest_bidder_id,
+                highest_bid,
+                reserve_price,
+                reserve_hidden,
+                min_increment_bps,
+                extension_window,
+            } => {
+                view.start_price = Some(start_price.0.to_string());
+                view.start_time = Some(start_time.0.to_string());
+                view.end_time = Some(end_time.0.to_string());
+                view.highest_bidder_id = highest_bidder_id.map(|id| id.to_string());
+                view.highest_bid = highest_bid.map(|b| b.0.to_string());
+                view.reserve_price = reserve_price.filter(|_| !reserve_hidden).map(|r| r.0.to_string());
+                view.reserve_met = Some(highest_bid.map_or(0, |b| b.0) >= reserve_price.map_or(0, |r| r.0));
+                view.min_increment_bps = Some(min_increment_bps);
+                view.extension_window = Some(extension_window.0.to_string());
+            }
+            ListingKind::DutchAuction { start_price, end_price, start_time, end_time } => {
+                view.start_price = Some(start_price.0.to_string());
+                view.end_price = Some(end_price.0.to_string());
+                view.start_time = Some(start_time.0.to_string());
+                view.end_time = Some(end_time.0.to_string());
+            }
+            ListingKind::SealedAuction {
+                start_price,
+                start_time,
+                commit_end,
+                reveal_end,
+                pricing,
+                slash_unrevealed,
+                bid_count,
+                highest_bidder_id,
+                highest_bid,
+                ..
+            } => {
+                view.start_price = Some(start_price.0.to_string());
+                view.start_time = Some(start_time.0.to_string());
+                view.end_time = Some(commit_end.0.to_string());
+                view.reveal_end_time = Some(reveal_end.0.to_string());
+                view.pricing = Some(pricing.as_str().to_string());
+                view.slash_unrevealed = Some(slash_unrevealed);
+                view.bid_count = Some(bid_count);
+                view.highest_bidder_id = highest_bidder_id.map(|id| id.to_string());
+                view.highest_bid = highest_bid.map(|b| b.0.to_string());
+            }
+            ListingKind::Bundle { price, items } => {
+                view.price = Some(price.0.to_string());
+                view.items = Some(items.into_iter().map(|item| self.bundle_item_to_view(item)).collect());
+            }
+            ListingKind::Rental { price_per_day, max_days, collateral, rental } => {
+                view.price = Some(price_per_day.0.to_string());
+                view.max_rental_days = Some(max_days);
+                view.collateral = Some(collateral.0.to_string());
+                view.renter_id = rental.as_ref().map(|rental| rental.renter_id.to_string());
+                view.rented_until = rental.map(|rental| rental.ends_at.0.to_string());
+            }
+        }
+
+        view
     }
 
     fn bid_to_view(&self, bid: Bid) -> BidView {
@@ -564,3 +1408,120 @@ impl Marketplace {
         }
     }
 }
+
+/// Key of a token across NFT contracts
+fn contract_and_token_id(nft_contract_id: &AccountId, token_id: &str) -> String {
+    format!("{}{}{}", nft_contract_id, DELIMETER, token_id)
+}
+
+/// Whether an active listing can no longer be bought or bid on and only
+/// waits to be removed. An auction with bids waits for `end_auction`.
+fn is_expired(listing: &Listing, timestamp: u64) -> bool {
+    match &listing.kind {
+        ListingKind::Sale { .. } | ListingKind::Bundle { .. } => timestamp >= listing.expires_at.0,
+        ListingKind::Auction { end_time, highest_bid, .. } => timestamp > end_time.0 && highest_bid.is_none(),
+        ListingKind::DutchAuction { end_time, .. } => timestamp > end_time.0,
+        ListingKind::SealedAuction { reveal_end, bid_count, .. } => timestamp > reveal_end.0 && *bid_count == 0,
+        // A rented token stays listed until its rental is settled
+        ListingKind::Rental { rental, .. } => timestamp >= listing.expires_at.0 && rental.is_none(),
+    }
+}
+
+/// Splits a price into the marketplace fee, royalties and the seller's
+/// proceeds. Royalties above `ROYALTY_CAP` of the price are scaled down
+/// pro rata, the seller receives everything else.
+fn split_price(price: u128, marketplace_fee: u128, mut royalties: HashMap<AccountId, u128>) -> PayoutBreakdown {
+    let royalty_cap = price * ROYALTY_CAP as u128 / 10000;
+
+    let total: u128 = royalties.values().sum();
+    if total > royalty_cap {
+        for amount in royalties.values_mut() {
+            *amount = mul_div(*amount, royalty_cap, total);
+        }
+    }
+
+    let total: u128 = royalties.values().sum();
+    PayoutBreakdown {
+        marketplace_fee,
+        royalties,
+        seller_proceeds: price - marketplace_fee - total,
+    }
+}
+
+/// Royalties of an NFT payout for `balance`, without the seller's own share,
+/// which is part of the proceeds. A payout that does not add up is ignored.
+fn payout_royalties(payout: Payout, balance: u128, seller_id: &AccountId, token_id: &str) -> HashMap<AccountId, u128> {
+    let total: u128 = payout.payout.values().map(|amount| amount.0).sum();
+    if payout.payout.len() > MAX_LEN_PAYOUT as usize || total > balance {
+        log!("Ignoring invalid payout for token {}", token_id);
+        return HashMap::new();
+    }
+
+    payout
+        .payout
+        .into_iter()
+        .filter(|(receiver_id, _)| receiver_id != seller_id)
+        .map(|(receiver_id, amount)| (receiver_id, amount.0))
+        .collect()
+}
+
+fn breakdown_to_view(price: u128, seller_id: &AccountId, breakdown: &PayoutBreakdown) -> PayoutBreakdownView {
+    PayoutBreakdownView {
+        price: price.to_string(),
+        marketplace_fee: breakdown.marketplace_fee.to_string(),
+        royalties: breakdown.royalties.iter().map(|(receiver_id, amount)| (receiver_id.to_string(), amount.to_string())).collect(),
+        seller_id: seller_id.to_string(),
+        seller_proceeds: breakdown.seller_proceeds.to_string(),
+    }
+}
+
+// The expansion of `construct_uint` predates these lints
+#[allow(clippy::assign_op_pattern, clippy::manual_div_ceil)]
+mod u256 {
+    uint::construct_uint! {
+        /// 256-bit integer holding the product of two amounts
+        pub struct U256(4);
+    }
+}
+
+/// `amount * numerator / denominator` rounded down, multiplied in 256 bits
+/// so amounts can be scaled by other amounts or by times in nanoseconds.
+/// The result fits in a `u128` as long as `numerator <= denominator`.
+pub(crate) fn mul_div(amount: u128, numerator: u128, denominator: u128) -> u128 {
+    use u256::U256;
+
+    (U256::from(amount) * U256::from(numerator) / U256::from(denominator)).as_u128()
+}
+
+/// Lowest bid an auction accepts next: the start price, then the highest bid
+/// raised by the minimum increment (and by at least one yoctoNEAR)
+fn min_next_bid(kind: &ListingKind) -> u128 {
+    match kind {
+        ListingKind::Auction { start_price, highest_bid: Some(highest_bid), min_increment_bps, .. } => {
+            let increment = highest_bid.0 * *min_increment_bps as u128 / 10000;
+            (highest_bid.0 + increment.max(1)).max(start_price.0)
+        }
+        ListingKind::Auction { start_price, .. } => start_price.0,
+        _ => env::panic_str("Listing does not take bids"),
+    }
+}
+
+/// Adds an ID to the set under `key`, creating the set under `storage_key` on first use
+fn add_to_set<K: BorshSerialize + BorshDeserialize>(index: &mut LookupMap<K, UnorderedSet<U128>>, key: &K, id: &U128, storage_key: StorageKey) {
+    let mut set = index.get(key).unwrap_or_else(|| UnorderedSet::new(storage_key));
+    set.insert(id);
+    index.insert(key, &set);
+}
+
+/// Removes an ID from the set under `key`, dropping the set once it is empty
+fn remove_from_set<K: BorshSerialize + BorshDeserialize>(index: &mut LookupMap<K, UnorderedSet<U128>>, key: &K, id: &U128) {
+    let Some(mut set) = index.get(key) else {
+        return;
+    };
+    set.remove(id);
+    if set.is_empty() {
+        index.remove(key);
+    } else {
+        index.insert(key, &set);
+    }
+}
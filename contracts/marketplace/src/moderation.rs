@@ -0,0 +1,283 @@
+//! Moderation: a global pause, a blocklist of accounts and NFT contracts,
+//! frozen listings and disputes holding settlement funds in escrow
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::{
+    env, log, near_bindgen, AccountId,
+    json_types::{U128, U64},
+    serde::{Deserialize, Serialize},
+};
+use std::collections::HashMap;
+
+use crate::{
+    events, views::page_size, Listing, ListingKind, ListingStatus, Marketplace, MarketplaceExt, DISPUTE_HOLD_DURATION,
+    MAX_MODERATION_REASON_LEN,
+};
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+pub struct DisputeView {
+    pub listing_id: String,
+    pub opened_by: String,
+    pub reason: String,
+    pub ft_token_id: Option<String>, // None when held in NEAR
+    pub held: HashMap<String, String>,
+    pub opened_at: String,
+    pub expires_at: String,
+}
+
+/// A dispute over a listing. Royalties, seller proceeds and rent the listing
+/// settles while the dispute is open are held instead of paid out.
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
+pub struct Dispute {
+    /// The disputed listing
+    pub listing_id: U128,
+    /// The moderator who opened the dispute
+    pub opened_by: AccountId,
+    /// Why the listing is disputed
+    pub reason: String,
+    /// The currency of the listing, `None` for NEAR
+    pub ft_token_id: Option<AccountId>,
+    /// What each receiver would have been paid
+    pub held: HashMap<AccountId, U128>,
+    /// The timestamp when the dispute was opened
+    pub opened_at: U64,
+    /// The time the held funds are released to their receivers if no
+    /// moderator resolved the dispute, in nanoseconds
+    pub expires_at: U64,
+}
+
+impl Dispute {
+    /// The amount held in escrow
+    pub fn held_total(&self) -> u128 {
+        self.held.values().map(|amount| amount.0).sum()
+    }
+}
+
+#[near_bindgen]
+impl Marketplace {
+    /// Lets an account moderate the marketplace (owner only).
+    pub fn add_moderator(&mut self, account_id: AccountId) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "Only owner can manage moderators");
+        self.moderators.insert(&account_id);
+        log!("Added moderator {}", account_id);
+    }
+
+    /// Revokes an account's moderator role (owner only).
+    pub fn remove_moderator(&mut self, account_id: AccountId) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "Only owner can manage moderators");
+        self.moderators.remove(&account_id);
+        log!("Removed moderator {}", account_id);
+    }
+
+    /// Stops all trading (moderators only). Listings can still be cancelled,
+    /// sealed bids revealed, rentals settled and refunds withdrawn.
+    pub fn pause(&mut self) {
+        self.assert_moderator();
+        self.paused = true;
+        log!("Marketplace paused by {}", env::predecessor_account_id());
+    }
+
+    /// Resumes trading (moderators only).
+    pub fn unpause(&mut self) {
+        self.assert_moderator();
+        self.paused = false;
+        log!("Marketplace unpaused by {}", env::predecessor_account_id());
+    }
+
+    /// Blocklists an account or NFT contract (moderators only). A blocked
+    /// account cannot list, buy, bid, rent or make offers; the listings of
+    /// a blocked seller or NFT contract can no longer be traded.
+    pub fn block_account(&mut self, account_id: AccountId, reason: String) {
+        self.assert_moderator();
+        assert_reason(&reason);
+        assert_ne!(account_id, self.owner_id, "Cannot block the owner");
+        self.blocklist.insert(&account_id);
+        log!("Blocked {}: {}", account_id, reason);
+    }
+
+    /// Removes an account or NFT contract from the blocklist (moderators only).
+    pub fn unblock_account(&mut self, account_id: AccountId) {
+        self.assert_moderator();
+        self.blocklist.remove(&account_id);
+        log!("Unblocked {}", account_id);
+    }
+
+    /// Takes an active listing out of trading until it is unfrozen
+    /// (moderators only). The seller can still cancel it.
+    pub fn freeze_listing(&mut self, listing_id: U128, reason: String) {
+        self.assert_moderator();
+        assert_reason(&reason);
+        let listing = self.listings.get(&listing_id).expect("Listing not found");
+        assert_eq!(listing.status, ListingStatus::Active, "Listing is not active");
+        assert!(self.frozen_listings.insert(&listing_id), "Listing is already frozen");
+
+        events::emit_listing_frozen(listing_id.0, &env::predecessor_account_id(), &reason);
+    }
+
+    /// Lets a frozen listing be traded again (moderators only).
+    pub fn unfreeze_listing(&mut self, listing_id: U128) {
+        self.assert_moderator();
+        assert!(self.frozen_listings.remove(&listing_id), "Listing is not frozen");
+
+        events::emit_listing_unfrozen(listing_id.0, &env::predecessor_account_id());
+    }
+
+    /// Opens a dispute over an active listing (moderators only). Until it is
+    /// resolved, or `DISPUTE_HOLD_DURATION` has passed, what the listing
+    /// settles to its seller and royalty receivers is held in escrow.
+    pub fn open_dispute(&mut self, listing_id: U128, reason: String) {
+        self.assert_moderator();
+        assert_reason(&reason);
+        let listing = self.listings.get(&listing_id).expect("Listing not found");
+        assert_eq!(listing.status, ListingStatus::Active, "Listing is not active");
+        assert!(self.disputes.get(&listing_id).is_none(), "Listing is already disputed");
+
+        let now = env::block_timestamp();
+        let dispute = Dispute {
+            listing_id,
+            opened_by: env::predecessor_account_id(),
+            reason,
+            ft_token_id: listing.ft_token_id,
+            held: HashMap::new(),
+            opened_at: U64(now / 1_000_000),
+            expires_at: U64(now + DISPUTE_HOLD_DURATION),
+        };
+        self.disputes.insert(&listing_id, &dispute);
+
+        events::emit_dispute_opened(&dispute);
+    }
+
+    /// Closes a dispute (moderators only). The held funds go to `payee_id`,
+    /// such as the rightful owner of a stolen token, or to the receivers
+    /// they were held from when none is given.
+    pub fn resolve_dispute(&mut self, listing_id: U128, payee_id: Option<AccountId>) {
+        self.assert_moderator();
+        let dispute = self.disputes.remove(&listing_id).expect("Listing is not disputed");
+
+        events::emit_dispute_resolved(&dispute, payee_id.as_ref(), false);
+
+        match payee_id {
+            Some(payee_id) => self.internal_pay(payee_id, &dispute.ft_token_id, dispute.held_total()),
+            None => self.internal_release_held(&dispute),
+        }
+    }
+
+    /// Releases the funds of a dispute no moderator resolved in time to
+    /// their receivers, anyone can call it.
+    pub fn release_dispute(&mut self, listing_id: U128) {
+        let dispute = self.disputes.get(&listing_id).expect("Listing is not disputed");
+        assert!(env::block_timestamp() >= dispute.expires_at.0, "Dispute has not timed out yet");
+        self.disputes.remove(&listing_id);
+
+        events::emit_dispute_resolved(&dispute, None, true);
+
+        self.internal_release_held(&dispute);
+    }
+
+    /// Gets the moderators, the owner moderates as well.
+    pub fn get_moderators(&self) -> Vec<String> {
+        self.moderators.iter().map(|account_id| account_id.to_string()).collect()
+    }
+
+    /// Whether trading is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Whether an account or NFT contract is blocklisted.
+    pub fn is_blocked(&self, account_id: AccountId) -> bool {
+        self.blocklist.contains(&account_id)
+    }
+
+    /// Gets a page of the blocklisted accounts and NFT contracts.
+    pub fn get_blocklist(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<String> {
+        self.blocklist
+            .iter()
+            .skip(from_index.map_or(0, |index| index.0 as usize))
+            .take(page_size(limit))
+            .map(|account_id| account_id.to_string())
+            .collect()
+    }
+
+    /// Gets the open dispute over a listing.
+    pub fn get_dispute(&self, listing_id: U128) -> Option<DisputeView> {
+        self.disputes.get(&listing_id).map(|dispute| DisputeView {
+            listing_id: dispute.listing_id.0.to_string(),
+            opened_by: dispute.opened_by.to_string(),
+            reason: dispute.reason,
+            ft_token_id: dispute.ft_token_id.map(|id| id.to_string()),
+            held: dispute.held.into_iter().map(|(receiver_id, amount)| (receiver_id.to_string(), amount.0.to_string())).collect(),
+            opened_at: dispute.opened_at.0.to_string(),
+            expires_at: dispute.expires_at.0.to_string(),
+        })
+    }
+}
+
+impl Marketplace {
+    pub(crate) fn assert_moderator(&self) {
+        let account_id = env::predecessor_account_id();
+        assert!(account_id == self.owner_id || self.moderators.contains(&account_id), "Only moderators can moderate the marketplace");
+    }
+
+    pub(crate) fn assert_not_paused(&self) {
+        assert!(!self.paused, "Marketplace is paused");
+    }
+
+    pub(crate) fn assert_not_blocked(&self, account_id: &AccountId) {
+        assert!(!self.blocklist.contains(account_id), "{} is blocklisted", account_id);
+    }
+
+    /// Checks that `account_id` can trade `listing`: the marketplace is not
+    /// paused, the listing is not frozen, and neither the account, the
+    /// seller nor the NFT contract are blocklisted
+    pub(crate) fn assert_can_trade(&self, listing: &Listing, account_id: &AccountId) {
+        self.assert_not_paused();
+        assert!(!self.frozen_listings.contains(&listing.id), "Listing is frozen");
+        self.assert_not_blocked(account_id);
+        if let Some(blocked_id) = self.blocked_party(listing) {
+            env::panic_str(&format!("{} is blocklisted", blocked_id));
+        }
+    }
+
+    /// Whether anyone can trade `listing` right now, for listings picked on
+    /// behalf of the buyer
+    pub(crate) fn is_tradable(&self, listing: &Listing) -> bool {
+        !self.frozen_listings.contains(&listing.id) && self.blocked_party(listing).is_none()
+    }
+
+    /// The seller or an NFT contract of `listing` when blocklisted
+    fn blocked_party<'a>(&self, listing: &'a Listing) -> Option<&'a AccountId> {
+        let bundle_contracts: Vec<&AccountId> = match &listing.kind {
+            ListingKind::Bundle { items, .. } => items.iter().map(|item| &item.nft_contract_id).collect(),
+            _ => Vec::new(),
+        };
+        [&listing.seller_id, &listing.nft_contract_id]
+            .into_iter()
+            .chain(bundle_contracts)
+            .find(|account_id| self.blocklist.contains(account_id))
+    }
+
+    /// Pays part of a listing's settlement, or holds it while the listing is
+    /// under a dispute that has not timed out
+    pub(crate) fn internal_pay_or_hold(&mut self, listing: &Listing, account_id: AccountId, amount: u128) {
+        match self.disputes.get(&listing.id).filter(|dispute| env::block_timestamp() < dispute.expires_at.0) {
+            Some(mut dispute) if amount > 0 => {
+                log!("Holding {} for {} while listing {} is disputed", amount, account_id, listing.id.0);
+                let held = dispute.held.get(&account_id).map_or(0, |held| held.0);
+                dispute.held.insert(account_id, U128(held + amount));
+                self.disputes.insert(&listing.id, &dispute);
+            }
+            _ => self.internal_pay(account_id, &listing.ft_token_id, amount),
+        }
+    }
+
+    fn internal_release_held(&self, dispute: &Dispute) {
+        for (receiver_id, amount) in dispute.held.iter() {
+            self.internal_pay(receiver_id.clone(), &dispute.ft_token_id, amount.0);
+        }
+    }
+}
+
+pub(crate) fn assert_reason(reason: &str) {
+    assert!(reason.len() <= MAX_MODERATION_REASON_LEN, "Reason is longer than {} bytes", MAX_MODERATION_REASON_LEN);
+}
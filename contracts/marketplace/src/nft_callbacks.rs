@@ -0,0 +1,267 @@
+//! NEP-178 approval receiver, the entry point for listing an NFT
+
+use near_sdk::{
+    env, log, near_bindgen, AccountId, PromiseOrValue,
+    json_types::{U128, U64},
+    serde::{Deserialize, Serialize},
+};
+
+use crate::{
+    bundles::BundleItemArgs, BundleItem, Listing, ListingKind, ListingStatus, Marketplace, MarketplaceExt, SealedBidPricing,
+    LISTING_DURATION, MAX_BUNDLE_SIZE, MAX_EXTENSION_WINDOW, MAX_MIN_INCREMENT_BPS, MAX_RENTAL_DAYS, MIN_PRICE,
+    MIN_REVEAL_WINDOW,
+};
+
+/// Arguments passed as `msg` to `nft_approve` on the NFT contract, e.g.
+/// `{"type": "sale", "price": "...", "ft_token_id": "voice-token.near"}`
+#[derive(Serialize, Deserialize)]
+pub struct ListingMsg {
+    #[serde(flatten)]
+    pub listing: ListingArgs,
+    /// Accepted NEP-141 token the prices are in, NEAR when omitted
+    #[serde(default)]
+    pub ft_token_id: Option<AccountId>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ListingArgs {
+    /// List the token at a fixed price
+    Sale { price: U128 },
+    /// Auction the token to the highest bidder between `start_time` and `end_time` (nanoseconds)
+    Auction {
+        start_price: U128,
+        start_time: U64,
+        end_time: U64,
+        /// No sale if the highest bid ends below it
+        #[serde(default)]
+        reserve_price: Option<U128>,
+        /// Keep the reserve price out of views, bidders only see whether it is met
+        #[serde(default)]
+        reserve_hidden: bool,
+        /// Basis points a bid must exceed the highest bid by
+        #[serde(default)]
+        min_increment_bps: u32,
+        /// Nanoseconds before the end within which a bid extends the auction
+        #[serde(default)]
+        extension_window: U64,
+    },
+    /// Auction the token with sealed bids, committed between `start_time`
+    /// and `commit_end` and revealed until `reveal_end` (nanoseconds)
+    SealedAuction {
+        start_price: U128,
+        start_time: U64,
+        commit_end: U64,
+        reveal_end: U64,
+        /// `"first_price"` (the default) or `"second_price"`
+        #[serde(default)]
+        pricing: SealedBidPricing,
+        /// Keep the deposits of bids not revealed in time as fees instead of refunding them
+        #[serde(default)]
+        slash_unrevealed: bool,
+    },
+    /// Offer the token at a price falling from `start_price` to `end_price`
+    /// between `start_time` and `end_time` (nanoseconds)
+    DutchAuction {
+        start_price: U128,
+        end_price: U128,
+        start_time: U64,
+        end_time: U64,
+    },
+    /// Sell several tokens together at a fixed price, the approved token
+    /// first. The others join the bundle once approved with `bundle_item`.
+    Bundle { price: U128, items: Vec<BundleItemArgs> },
+    /// Add the token to an active bundle of the owner
+    BundleItem { listing_id: U128 },
+    /// Rent the use of the token out by the day, the owner keeps it
+    Rental {
+        price_per_day: U128,
+        max_days: u32,
+        /// Escrowed by each renter on top of the rent, none when omitted
+        #[serde(default)]
+        collateral: U128,
+    },
+    /// Sell the token to an open offer for the offered amount
+    AcceptOffer { offer_id: U128 },
+    /// Answer an open offer with a higher price the buyer can accept
+    CounterOffer { offer_id: U128, price: U128 },
+    /// Sell the token to a collection offer it matches for the offered price
+    AcceptCollectionOffer { offer_id: U128 },
+}
+
+#[near_bindgen]
+impl Marketplace {
+    /// Called by an NFT contract once the owner approved the marketplace for
+    /// `token_id`. Lists the token as described by `msg`; approving the same
+    /// token again cancels its active listing and replaces it. The approval
+    /// also lets the owner accept or counter an offer on the token.
+    pub fn nft_on_approve(&mut self, token_id: String, owner_id: AccountId, approval_id: u64, msg: String) -> PromiseOrValue<U128> {
+        let nft_contract_id = env::predecessor_account_id();
+
+        // The approval must have been started by the owner, not relayed by another contract
+        assert_eq!(env::signer_account_id(), owner_id, "Only the token owner can list it");
+        assert_ne!(nft_contract_id, owner_id, "nft_on_approve must be called by the NFT contract");
+        self.assert_not_paused();
+        self.assert_not_blocked(&owner_id);
+        self.assert_not_blocked(&nft_contract_id);
+
+        let ListingMsg { listing, ft_token_id } = near_sdk::serde_json::from_str(&msg).expect("Invalid listing arguments");
+
+        // Token amounts have their own decimals, the minimum price is in NEAR
+        let min_price = match &ft_token_id {
+            Some(ft_token_id) => {
+                assert!(self.accepted_ft_tokens.contains(ft_token_id), "Token is not accepted");
+                assert!(!matches!(listing, ListingArgs::Auction { .. } | ListingArgs::SealedAuction { .. }), "Auctions are priced in NEAR");
+                assert!(!matches!(listing, ListingArgs::Rental { .. }), "Rentals are priced in NEAR");
+                1
+            }
+            None => MIN_PRICE,
+        };
+
+        let kind = match listing {
+            ListingArgs::Sale { price } => {
+                assert!(price.0 >= min_price, "Price is below the minimum");
+                ListingKind::Sale { price }
+            }
+            ListingArgs::Auction {
+                start_price,
+                start_time,
+                end_time,
+                reserve_price,
+                reserve_hidden,
+                min_increment_bps,
+                extension_window,
+            } => {
+                assert!(start_price.0 >= min_price, "Start price is below the minimum");
+                assert!(start_time.0 < end_time.0, "Auction must end after it starts");
+                assert!(end_time.0 > env::block_timestamp(), "Auction end time is in the past");
+                if let Some(reserve_price) = reserve_price {
+                    assert!(reserve_price.0 >= start_price.0, "Reserve price is below the start price");
+                }
+                assert!(min_increment_bps <= MAX_MIN_INCREMENT_BPS, "Minimum increment is too high");
+                assert!(extension_window.0 <= MAX_EXTENSION_WINDOW, "Extension window is too long");
+                ListingKind::Auction {
+                    start_price,
+                    start_time,
+                    end_time,
+                    highest_bidder_id: None,
+                    highest_bid: None,
+                    reserve_price,
+                    reserve_hidden: reserve_hidden && reserve_price.is_some(),
+                    min_increment_bps,
+                    extension_window,
+                }
+            }
+            ListingArgs::SealedAuction { start_price, start_time, commit_end, reveal_end, pricing, slash_unrevealed } => {
+                assert!(start_price.0 >= min_price, "Start price is below the minimum");
+                assert!(start_time.0 < commit_end.0, "Auction must end after it starts");
+                assert!(commit_end.0 > env::block_timestamp(), "Auction end time is in the past");
+                assert!(reveal_end.0 >= commit_end.0 + MIN_REVEAL_WINDOW, "Reveal phase is too short");
+                ListingKind::SealedAuction {
+                    start_price,
+                    start_time,
+                    commit_end,
+                    reveal_end,
+                    pricing,
+                    slash_unrevealed,
+                    bid_count: 0,
+                    highest_bid_id: None,
+                    highest_bidder_id: None,
+                    highest_bid: None,
+                    second_bid: None,
+                }
+            }
+            ListingArgs::DutchAuction { start_price, end_price, start_time, end_time } => {
+                assert!(end_price.0 >= min_price, "End price is below the minimum");
+                assert!(start_price.0 >= end_price.0, "Price of a Dutch auction cannot rise");
+                assert!(start_time.0 < end_time.0, "Auction must end after it starts");
+                assert!(end_time.0 > env::block_timestamp(), "Auction end time is in the past");
+                ListingKind::DutchAuction { start_price, end_price, start_time, end_time }
+            }
+            ListingArgs::Bundle { price, items } => {
+                assert!(price.0 >= min_price, "Price is below the minimum");
+                assert!(items.len() >= 2 && items.len() <= MAX_BUNDLE_SIZE, "A bundle holds 2 to {} tokens", MAX_BUNDLE_SIZE);
+                assert!(
+                    items[0].nft_contract_id == nft_contract_id && items[0].token_id == token_id,
+                    "The approved token must come first in the bundle"
+                );
+                let items: Vec<BundleItem> = items
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, item)| BundleItem {
+                        approval_id: (index == 0).then_some(approval_id),
+                        weight: item.weight.unwrap_or(1),
+                        nft_contract_id: item.nft_contract_id,
+                        token_id: item.token_id,
+                    })
+                    .collect();
+                assert!(items.iter().all(|item| item.weight > 0), "Weights must be positive");
+                for (index, item) in items.iter().enumerate() {
+                    assert!(
+                        !items[..index].iter().any(|other| other.nft_contract_id == item.nft_contract_id && other.token_id == item.token_id),
+                        "Token {} is in the bundle twice",
+                        item.token_id
+                    );
+                }
+                ListingKind::Bundle { price, items }
+            }
+            ListingArgs::Rental { price_per_day, max_days, collateral } => {
+                assert!(price_per_day.0 >= min_price, "Price is below the minimum");
+                assert!((1..=MAX_RENTAL_DAYS).contains(&max_days), "A rental lasts 1 to {} days", MAX_RENTAL_DAYS);
+                ListingKind::Rental { price_per_day, max_days, collateral, rental: None }
+            }
+            ListingArgs::BundleItem { listing_id } => {
+                self.internal_approve_bundle_item(listing_id, &nft_contract_id, &token_id, &owner_id, approval_id);
+                return PromiseOrValue::Value(listing_id);
+            }
+            // Offers are in NEAR, the owner's approval is what lets them be settled
+            ListingArgs::AcceptOffer { offer_id } => {
+                assert!(ft_token_id.is_none(), "Offers are in NEAR");
+                return self.internal_accept_offer(offer_id, &nft_contract_id, &token_id, owner_id, approval_id).into();
+            }
+            ListingArgs::AcceptCollectionOffer { offer_id } => {
+                assert!(ft_token_id.is_none(), "Offers are in NEAR");
+                return self.internal_accept_collection_offer(offer_id, &nft_contract_id, &token_id, owner_id, approval_id).into();
+            }
+            ListingArgs::CounterOffer { offer_id, price } => {
+                assert!(ft_token_id.is_none(), "Offers are in NEAR");
+                self.internal_counter_offer(offer_id, &nft_contract_id, &token_id, owner_id, approval_id, price);
+                return PromiseOrValue::Value(offer_id);
+            }
+        };
+
+        self.internal_cancel_token_listing(&nft_contract_id, &token_id);
+        let storage_deposit = self.internal_lock_storage(&owner_id);
+
+        // Sales expire after `LISTING_DURATION`, auctions at their end time
+        // (after the reveal phase for sealed bids)
+        let expires_at = match &kind {
+            ListingKind::Sale { .. } | ListingKind::Bundle { .. } | ListingKind::Rental { .. } => U64(env::block_timestamp() + LISTING_DURATION),
+            ListingKind::Auction { end_time, .. } | ListingKind::DutchAuction { end_time, .. } => *end_time,
+            ListingKind::SealedAuction { reveal_end, .. } => *reveal_end,
+        };
+
+        self.total_listings.0 += 1;
+        let listing = Listing {
+            id: self.total_listings,
+            seller_id: owner_id,
+            nft_contract_id,
+            token_id,
+            approval_id,
+            ft_token_id,
+            kind,
+            status: ListingStatus::Active,
+            buyer_id: None,
+            expires_at,
+            storage_deposit,
+            created_at: U64(env::block_timestamp() / 1_000_000),
+            updated_at: U64(env::block_timestamp() / 1_000_000),
+        };
+
+        self.internal_add_listing(&listing);
+
+        log!("Listed token {} of {} as listing {}", listing.token_id, listing.nft_contract_id, listing.id.0);
+
+        PromiseOrValue::Value(listing.id)
+    }
+}
@@ -0,0 +1,343 @@
+//! Offers on any token, listed or not, with the offered NEAR held in escrow
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::{
+    env, log, near_bindgen, AccountId, Promise, PromiseError,
+    json_types::{U128, U64},
+    serde::{Deserialize, Serialize},
+};
+
+use crate::{
+    add_to_set, contract_and_token_id, ext_nft_contract, external::Token, paginate_ids, remove_from_set, Listing, ListingKind,
+    ListingStatus, Marketplace, MarketplaceExt, StorageKey, GAS_FOR_NFT_TOKEN, GAS_FOR_RESOLVE_REJECT_OFFER,
+    MAX_OFFER_DURATION, MIN_PRICE,
+};
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+pub struct OfferView {
+    pub id: String,
+    pub buyer_id: String,
+    pub nft_contract_id: String,
+    pub token_id: String,
+    pub amount: String,
+    pub expires_at: String,
+    pub counter_seller_id: Option<String>,
+    pub counter_price: Option<String>,
+    pub created_at: String,
+}
+
+/// The token owner's answer to an offer, a higher price the buyer can accept
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
+pub struct CounterOffer {
+    /// The owner who made the counter-offer
+    pub seller_id: AccountId,
+    /// The price asked in yoctoNEAR
+    pub price: U128,
+    /// The NEP-178 approval ID the marketplace transfers the token with
+    pub approval_id: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
+pub struct Offer {
+    /// The ID of the offer
+    pub id: U128,
+    /// The account ID of the buyer
+    pub buyer_id: AccountId,
+    /// The NFT contract of the token
+    pub nft_contract_id: AccountId,
+    /// The token the offer is for
+    pub token_id: String,
+    /// The offered amount in yoctoNEAR, held in escrow
+    pub amount: U128,
+    /// The time the offer can no longer be accepted, in nanoseconds
+    pub expires_at: U64,
+    /// The latest counter-offer of the token owner
+    pub counter: Option<CounterOffer>,
+    /// The timestamp when the offer was made
+    pub created_at: U64,
+}
+
+#[near_bindgen]
+impl Marketplace {
+    /// Offers the attached deposit for a token, listed or not, until
+    /// `expires_at` (nanoseconds). The deposit is held in escrow until the
+    /// owner accepts or rejects the offer. An earlier offer of the caller on
+    /// the same token is superseded and refunded, as are the token's expired
+    /// offers.
+    #[payable]
+    pub fn make_offer(&mut self, nft_contract_id: AccountId, token_id: String, expires_at: U64) -> U128 {
+        let buyer_id = env::predecessor_account_id();
+        let amount = env::attached_deposit().as_yoctonear();
+        let now = env::block_timestamp();
+
+        self.assert_not_paused();
+        self.assert_not_blocked(&buyer_id);
+        self.assert_not_blocked(&nft_contract_id);
+        assert!(amount >= MIN_PRICE, "Offer is below the minimum price");
+        assert!(expires_at.0 > now, "Offer expiry is in the past");
+        assert!(expires_at.0 <= now + MAX_OFFER_DURATION, "Offer expiry is too far out");
+
+        let token_key = contract_and_token_id(&nft_contract_id, &token_id);
+        self.internal_refund_expired_offers(&token_key);
+
+        let superseded = self.offers_by_token.get(&token_key).and_then(|set| {
+            set.iter().find(|id| self.offers.get(id).is_some_and(|offer| offer.buyer_id == buyer_id))
+        });
+        if let Some(superseded_id) = superseded {
+            log!("Offer {} superseded, refunding {}", superseded_id.0, buyer_id);
+            self.internal_refund_offer(&superseded_id);
+        }
+
+        self.total_offers.0 += 1;
+        let offer = Offer {
+            id: self.total_offers,
+            buyer_id: buyer_id.clone(),
+            nft_contract_id,
+            token_id,
+            amount: U128(amount),
+            expires_at,
+            counter: None,
+            created_at: U64(now / 1_000_000),
+        };
+        self.offers.insert(&offer.id, &offer);
+
+        add_to_set(&mut self.offers_by_token, &token_key, &offer.id, StorageKey::OffersPerToken {
+            token_hash: env::sha256_array(token_key.as_bytes()),
+        });
+        add_to_set(&mut self.offers_by_buyer, &buyer_id, &offer.id, StorageKey::OffersPerBuyer {
+            account_hash: env::sha256_array(buyer_id.as_bytes()),
+        });
+
+        log!("Offer {} of {} for token {} of {}", offer.id.0, amount, offer.token_id, offer.nft_contract_id);
+
+        offer.id
+    }
+
+    /// Withdraws an open offer and refunds its escrow (buyer only).
+    pub fn cancel_offer(&mut self, offer_id: U128) -> Promise {
+        let offer = self.offers.get(&offer_id).expect("Offer not found");
+        assert_eq!(env::predecessor_account_id(), offer.buyer_id, "Only the buyer can cancel an offer");
+
+        log!("Offer {} cancelled", offer_id.0);
+
+        self.internal_refund_offer(&offer_id)
+    }
+
+    /// Rejects an offer on a token the caller owns and refunds the buyer.
+    /// Ownership is checked with the NFT contract in `resolve_reject_offer`.
+    pub fn reject_offer(&mut self, offer_id: U128) -> Promise {
+        let offer = self.offers.get(&offer_id).expect("Offer not found");
+
+        ext_nft_contract::ext(offer.nft_contract_id)
+            .with_static_gas(GAS_FOR_NFT_TOKEN)
+            .nft_token(offer.token_id)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_REJECT_OFFER)
+                    .resolve_reject_offer(offer_id, env::predecessor_account_id()),
+            )
+    }
+
+    /// Refunds a rejected offer once the NFT contract confirmed that
+    /// `account_id` owns the token.
+    #[private]
+    pub fn resolve_reject_offer(
+        &mut self,
+        offer_id: U128,
+        account_id: AccountId,
+        #[callback_result] token: Result<Option<Token>, PromiseError>,
+    ) -> bool {
+        // Accepted or withdrawn in the meantime
+        if self.offers.get(&offer_id).is_none() {
+            return false;
+        }
+
+        let owner_id = token.ok().flatten().map(|token| token.owner_id);
+        if owner_id.as_ref() != Some(&account_id) {
+            env::panic_str("Only the token owner can reject an offer");
+        }
+
+        log!("Offer {} rejected", offer_id.0);
+        self.internal_refund_offer(&offer_id);
+
+        true
+    }
+
+    /// Accepts the owner's counter-offer. The buyer attaches the difference
+    /// between the counter price and the escrowed offer.
+    #[payable]
+    pub fn accept_counter_offer(&mut self, offer_id: U128) -> Promise {
+        let offer = self.offers.get(&offer_id).expect("Offer not found");
+
+        assert_eq!(env::predecessor_account_id(), offer.buyer_id, "Only the buyer can accept a counter-offer");
+        assert!(env::block_timestamp() < offer.expires_at.0, "Offer has expired");
+        let counter = offer.counter.clone().expect("Offer has no counter-offer");
+        let deposit = offer.amount.0 + env::attached_deposit().as_yoctonear();
+        assert!(deposit >= counter.price.0, "Insufficient deposit");
+
+        self.internal_settle_offer(offer, counter.seller_id, counter.approval_id, counter.price, deposit)
+    }
+
+    /// Refunds the expired offers on a token, anyone can call it.
+    /// Returns how many offers were refunded.
+    pub fn refund_expired_offers(&mut self, nft_contract_id: AccountId, token_id: String) -> u32 {
+        self.internal_refund_expired_offers(&contract_and_token_id(&nft_contract_id, &token_id))
+    }
+
+    /// Gets the details of an open offer.
+    pub fn get_offer(&self, offer_id: U128) -> Option<OfferView> {
+        self.offers.get(&offer_id).map(|offer| self.offer_to_view(offer))
+    }
+
+    /// Gets a page of the open offer IDs on a token.
+    pub fn get_offers_by_token(&self, nft_contract_id: AccountId, token_id: String, from_index: Option<U128>, limit: Option<u64>) -> Vec<String> {
+        let token_key = contract_and_token_id(&nft_contract_id, &token_id);
+        self.offers_by_token.get(&token_key).map_or_else(Vec::new, |set| paginate_ids(&set, from_index, limit))
+    }
+
+    /// Gets a page of the open offer IDs made by an account.
+    pub fn get_offers_by_bidder(&self, bidder_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<String> {
+        self.offers_by_buyer.get(&bidder_id).map_or_else(Vec::new, |set| paginate_ids(&set, from_index, limit))
+    }
+
+    /// Gets the total number of offers made.
+    pub fn get_total_offers(&self) -> String {
+        self.total_offers.0.to_string()
+    }
+}
+
+impl Marketplace {
+    /// Accepts an offer for the token owner, who approved the marketplace
+    /// with `approval_id`, and sells the token for the escrowed amount
+    pub(crate) fn internal_accept_offer(
+        &mut self,
+        offer_id: U128,
+        nft_contract_id: &AccountId,
+        token_id: &str,
+        owner_id: AccountId,
+        approval_id: u64,
+    ) -> Promise {
+        let offer = self.internal_get_offer_for(offer_id, nft_contract_id, token_id);
+        assert_ne!(offer.buyer_id, owner_id, "Cannot accept your own offer");
+
+        let amount = offer.amount;
+        self.internal_settle_offer(offer, owner_id, approval_id, amount, amount.0)
+    }
+
+    /// Answers an offer with a higher price the buyer can accept until the
+    /// offer expires. A new counter-offer replaces the previous one.
+    pub(crate) fn internal_counter_offer(
+        &mut self,
+        offer_id: U128,
+        nft_contract_id: &AccountId,
+        token_id: &str,
+        owner_id: AccountId,
+        approval_id: u64,
+        price: U128,
+    ) {
+        let mut offer = self.internal_get_offer_for(offer_id, nft_contract_id, token_id);
+        assert!(price.0 > offer.amount.0, "Counter-offer must be above the offer");
+
+        offer.counter = Some(CounterOffer { seller_id: owner_id, price, approval_id });
+        self.offers.insert(&offer_id, &offer);
+
+        log!("Counter-offer of {} on offer {}", price.0, offer_id.0);
+    }
+
+    /// An open, unexpired offer on the given token
+    fn internal_get_offer_for(&self, offer_id: U128, nft_contract_id: &AccountId, token_id: &str) -> Offer {
+        let offer = self.offers.get(&offer_id).expect("Offer not found");
+        assert!(offer.nft_contract_id == *nft_contract_id && offer.token_id == token_id, "Offer is for another token");
+        assert!(env::block_timestamp() < offer.expires_at.0, "Offer has expired");
+        offer
+    }
+
+    /// Closes an offer and sells the token like a fixed-price sale, recorded
+    /// as a sold listing, for `price` out of the buyer's `deposit`
+    fn internal_settle_offer(&mut self, offer: Offer, seller_id: AccountId, approval_id: u64, price: U128, deposit: u128) -> Promise {
+        self.assert_not_paused();
+        self.assert_not_blocked(&offer.buyer_id);
+        self.internal_remove_offer(&offer);
+        let listing = self.internal_add_offer_sale(offer.nft_contract_id, offer.token_id, seller_id, approval_id, offer.buyer_id.clone(), price);
+
+        log!("Offer {} accepted as listing {}", offer.id.0, listing.id.0);
+
+        self.internal_settle(&listing, offer.buyer_id, price, U128(deposit))
+    }
+
+    /// Records the sale of a token to an accepted offer as a sold listing,
+    /// replacing the token's active listing
+    pub(crate) fn internal_add_offer_sale(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: String,
+        seller_id: AccountId,
+        approval_id: u64,
+        buyer_id: AccountId,
+        price: U128,
+    ) -> Listing {
+        self.internal_cancel_token_listing(&nft_contract_id, &token_id);
+
+        self.total_listings.0 += 1;
+        let listing = Listing {
+            id: self.total_listings,
+            seller_id,
+            nft_contract_id,
+            token_id,
+            approval_id,
+            ft_token_id: None,
+            kind: ListingKind::Sale { price },
+            status: ListingStatus::Sold,
+            buyer_id: Some(buyer_id),
+            expires_at: U64(env::block_timestamp()),
+            storage_deposit: U128(0),
+            created_at: U64(env::block_timestamp() / 1_000_000),
+            updated_at: U64(env::block_timestamp() / 1_000_000),
+        };
+        self.internal_add_listing(&listing);
+
+        listing
+    }
+
+    /// Closes an offer and refunds its escrow to the buyer
+    fn internal_refund_offer(&mut self, offer_id: &U128) -> Promise {
+        let offer = self.offers.get(offer_id).expect("Offer not found");
+        self.internal_remove_offer(&offer);
+        self.internal_send(offer.buyer_id, None, offer.amount.0)
+    }
+
+    fn internal_refund_expired_offers(&mut self, token_key: &String) -> u32 {
+        let Some(set) = self.offers_by_token.get(token_key) else {
+            return 0;
+        };
+
+        let now = env::block_timestamp();
+        let expired: Vec<U128> = set.iter().filter(|id| self.offers.get(id).is_some_and(|offer| offer.expires_at.0 <= now)).collect();
+        for offer_id in expired.iter() {
+            log!("Offer {} expired, refunding", offer_id.0);
+            self.internal_refund_offer(offer_id);
+        }
+
+        expired.len() as u32
+    }
+
+    fn internal_remove_offer(&mut self, offer: &Offer) {
+        self.offers.remove(&offer.id);
+        remove_from_set(&mut self.offers_by_token, &contract_and_token_id(&offer.nft_contract_id, &offer.token_id), &offer.id);
+        remove_from_set(&mut self.offers_by_buyer, &offer.buyer_id, &offer.id);
+    }
+
+    fn offer_to_view(&self, offer: Offer) -> OfferView {
+        OfferView {
+            id: offer.id.0.to_string(),
+            buyer_id: offer.buyer_id.to_string(),
+            nft_contract_id: offer.nft_contract_id.to_string(),
+            token_id: offer.token_id,
+            amount: offer.amount.0.to_string(),
+            expires_at: offer.expires_at.0.to_string(),
+            counter_seller_id: offer.counter.as_ref().map(|counter| counter.seller_id.to_string()),
+            counter_price: offer.counter.map(|counter| counter.price.0.to_string()),
+            created_at: offer.created_at.0.to_string(),
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! Rentals: time-limited use of a token through the NFT contract's user
+//! role, the owner keeps the token and the escrowed rent is streamed to them
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::{
+    env, log, near_bindgen, AccountId, NearToken, Promise, PromiseResult,
+    json_types::{U128, U64},
+    serde::{Deserialize, Serialize},
+};
+use std::collections::HashMap;
+
+use crate::{
+    events, ext_nft_contract, moderation::assert_reason, mul_div, payout_royalties, split_price, Listing, ListingKind,
+    ListingStatus, Marketplace, MarketplaceExt, Payout, PayoutBreakdown, DISPUTE_HOLD_DURATION, GAS_FOR_NFT_PAYOUT,
+    GAS_FOR_NFT_SET_USER, GAS_FOR_RESOLVE_RENT, MAX_LEN_PAYOUT, RENTAL_CLAIM_WINDOW, RENTAL_DAY,
+};
+
+/// The current rental of a rental listing
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
+pub struct Rental {
+    /// The account using the token
+    pub renter_id: AccountId,
+    /// The start of the rental
+    pub starts_at: U64,
+    /// The end of the rental, when the user role on the NFT contract expires
+    pub ends_at: U64,
+    /// The escrowed rent for the whole rental in yoctoNEAR
+    pub rent: U128,
+    /// The escrowed collateral, returned to the renter at the end unless the
+    /// seller claims it
+    pub collateral: U128,
+    /// Whether the NFT contract granted the user role, the rent is only
+    /// streamed once it did
+    pub confirmed: bool,
+    /// The marketplace fee out of the whole rent
+    pub marketplace_fee: U128,
+    /// Royalties out of the whole rent
+    pub royalties: HashMap<AccountId, U128>,
+    /// The seller's proceeds out of the whole rent
+    pub seller_proceeds: U128,
+    /// The rent is paid out up to this time
+    pub streamed_until: U64,
+}
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+pub struct CollateralClaimView {
+    pub listing_id: String,
+    pub renter_id: String,
+    pub amount: String,
+    pub reason: String,
+    pub opened_at: String,
+    pub expires_at: String,
+}
+
+/// The collateral of an ended rental claimed by the seller, escrowed until
+/// a moderator decides who gets it
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
+pub struct CollateralClaim {
+    /// The renter the collateral was taken from
+    pub renter_id: AccountId,
+    /// The escrowed collateral in yoctoNEAR
+    pub amount: U128,
+    /// How the renter misused the token
+    pub reason: String,
+    /// The timestamp when the seller claimed the collateral
+    pub opened_at: U64,
+    /// The time the collateral goes back to the renter if no moderator
+    /// resolved the claim, in nanoseconds
+    pub expires_at: U64,
+}
+
+#[near_bindgen]
+impl Marketplace {
+    /// Rents a token for `days`, paying the rent and the collateral of the
+    /// listing up front; anything above is refunded. The renter becomes the
+    /// token's user on the NFT contract until the rental ends.
+    #[payable]
+    pub fn rent(&mut self, listing_id: U128, days: u32) -> Promise {
+        let renter_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit().as_yoctonear();
+        let now = env::block_timestamp();
+        let mut listing = self.listings.get(&listing_id).expect("Listing not found");
+
+        assert_eq!(listing.status, ListingStatus::Active, "Listing is not active");
+        self.assert_can_trade(&listing, &renter_id);
+        assert!(now < listing.expires_at.0, "Listing has expired");
+        assert_ne!(renter_id, listing.seller_id, "Cannot rent your own listing");
+
+        // The previous rental is settled before the token is rented again,
+        // once the seller had the chance to claim its collateral
+        if let ListingKind::Rental { rental: Some(rental), .. } = &listing.kind {
+            assert!(rental.confirmed && now >= rental.ends_at.0 + RENTAL_CLAIM_WINDOW, "Token is already rented");
+            let rental = self.internal_end_rental(&mut listing);
+            self.internal_pay(rental.renter_id, &None, rental.collateral.0);
+        }
+
+        let ListingKind::Rental { price_per_day, max_days, collateral, .. } = listing.kind else {
+            env::panic_str("Listing is not for rent");
+        };
+        assert!((1..=max_days).contains(&days), "A rental lasts 1 to {} days", max_days);
+        let rent = price_per_day.0 * days as u128;
+        assert!(deposit >= rent + collateral.0, "Insufficient deposit, the rent and collateral come to {}", rent + collateral.0);
+
+        // The NFT contract splits what is left after the marketplace fee
+        let marketplace_fee = self.internal_marketplace_fee(&listing.seller_id, rent);
+        let ends_at = now + days as u64 * RENTAL_DAY;
+
+        // Recorded before the NFT contract answers so the token cannot be rented twice
+        if let ListingKind::Rental { rental, .. } = &mut listing.kind {
+            *rental = Some(Rental {
+                renter_id: renter_id.clone(),
+                starts_at: U64(now),
+                ends_at: U64(ends_at),
+                rent: U128(rent),
+                collateral,
+                confirmed: false,
+                marketplace_fee: U128(marketplace_fee),
+                royalties: HashMap::new(),
+                seller_proceeds: U128(0),
+                streamed_until: U64(now),
+            });
+        }
+        listing.updated_at = U64(now / 1_000_000);
+        self.listings.insert(&listing_id, &listing);
+
+        ext_nft_contract::ext(listing.nft_contract_id.clone())
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .with_static_gas(GAS_FOR_NFT_SET_USER)
+            .nft_set_user(listing.token_id.clone(), renter_id.clone(), U64(ends_at), Some(listing.approval_id))
+            .and(
+                ext_nft_contract::ext(listing.nft_contract_id.clone())
+                    .with_static_gas(GAS_FOR_NFT_PAYOUT)
+                    .nft_payout(listing.token_id.clone(), U128(rent - marketplace_fee), Some(MAX_LEN_PAYOUT)),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_RENT)
+                    .resolve_rent(listing_id, renter_id, U128(deposit)),
+            )
+    }
+
+    /// Confirms a rental once the NFT contract granted the user role, with
+    /// the rent split like a sale by the token's payout. When the role could
+    /// not be granted the rental is dropped and the deposit refunded.
+    #[private]
+    pub fn resolve_rent(&mut self, listing_id: U128, renter_id: AccountId, deposit: U128) -> bool {
+        let mut listing = self.listings.get(&listing_id).expect("Listing not found");
+        let user_set = matches!(env::promise_result(0), PromiseResult::Successful(_));
+        let payout = match env::promise_result(1) {
+            PromiseResult::Successful(value) => near_sdk::serde_json::from_slice::<Payout>(&value).ok(),
+            PromiseResult::Failed => None,
+        };
+
+        let seller_id = listing.seller_id.clone();
+        let token_id = listing.token_id.clone();
+        let ListingKind::Rental { rental, .. } = &mut listing.kind else {
+            env::panic_str("Listing is not for rent");
+        };
+        let mut rental = rental.take().expect("Listing is not rented");
+
+        if !user_set {
+            log!("Could not grant {} the use of token {}, refunding {}", renter_id, token_id, deposit.0);
+            self.listings.insert(&listing_id, &listing);
+            self.internal_send(renter_id, None, deposit.0);
+            return false;
+        }
+
+        let royalties = payout.map_or_else(HashMap::new, |payout| {
+            payout_royalties(payout, rental.rent.0 - rental.marketplace_fee.0, &seller_id, &token_id)
+        });
+        let breakdown = split_price(rental.rent.0, rental.marketplace_fee.0, royalties);
+        rental.confirmed = true;
+        rental.marketplace_fee = U128(breakdown.marketplace_fee);
+        rental.royalties = breakdown.royalties.into_iter().map(|(receiver_id, amount)| (receiver_id, U128(amount))).collect();
+        rental.seller_proceeds = U128(breakdown.seller_proceeds);
+
+        let surplus = deposit.0 - rental.rent.0 - rental.collateral.0;
+        events::emit_rental_started(&listing, &rental);
+        log!("Rented token {} to {} until {}", token_id, renter_id, rental.ends_at.0);
+
+        if let ListingKind::Rental { rental: current, .. } = &mut listing.kind {
+            *current = Some(rental);
+        }
+        self.listings.insert(&listing_id, &listing);
+
+        if surplus > 0 {
+            log!("Refunding {} paid above the rent to {}", surplus, renter_id);
+            self.internal_send(renter_id, None, surplus);
+        }
+
+        true
+    }
+
+    /// Pays out the rent earned so far, anyone can call it. Returns the
+    /// amount of rent streamed.
+    pub fn claim_rent(&mut self, listing_id: U128) -> String {
+        let mut listing = self.listings.get(&listing_id).expect("Listing not found");
+        let streamed = self.internal_stream_rent(&mut listing);
+        self.listings.insert(&listing_id, &listing);
+
+        streamed.to_string()
+    }
+
+    /// Settles a rental that is over: the rest of the rent is paid out and
+    /// the collateral goes back to the renter. The seller can call it once
+    /// the rental ended, waiving a claim on the collateral, anyone else once
+    /// `RENTAL_CLAIM_WINDOW` has passed. The listing stays open for the next
+    /// renter.
+    pub fn end_rental(&mut self, listing_id: U128) {
+        let mut listing = self.listings.get(&listing_id).expect("Listing not found");
+        let ListingKind::Rental { rental: Some(rental), .. } = &listing.kind else {
+            env::panic_str("Listing is not rented");
+        };
+        let now = env::block_timestamp();
+        assert!(rental.confirmed && now >= rental.ends_at.0, "Rental has not ended yet");
+        assert!(
+            env::predecessor_account_id() == listing.seller_id || now >= rental.ends_at.0 + RENTAL_CLAIM_WINDOW,
+            "The seller can claim the collateral until {}",
+            rental.ends_at.0 + RENTAL_CLAIM_WINDOW
+        );
+
+        let rental = self.internal_end_rental(&mut listing);
+        self.internal_pay(rental.renter_id, &None, rental.collateral.0);
+    }
+
+    /// Claims the collateral of a rental that ended less than
+    /// `RENTAL_CLAIM_WINDOW` ago (seller only), for a token the renter
+    /// misused. The rental is settled and the collateral is escrowed on its
+    /// own: a moderator awards it to the seller or returns it to the renter
+    /// through `resolve_collateral_claim`, and it goes back to the renter if
+    /// none did within `DISPUTE_HOLD_DURATION`. A listing has one claim open
+    /// at a time.
+    pub fn claim_collateral(&mut self, listing_id: U128, reason: String) {
+        let mut listing = self.listings.get(&listing_id).expect("Listing not found");
+        let ListingKind::Rental { rental: Some(rental), .. } = &listing.kind else {
+            env::panic_str("Listing is not rented");
+        };
+        assert_eq!(env::predecessor_account_id(), listing.seller_id, "Only the seller can claim the collateral");
+        assert_reason(&reason);
+        let now = env::block_timestamp();
+        assert!(rental.confirmed && now >= rental.ends_at.0, "Rental has not ended yet");
+        assert!(now < rental.ends_at.0 + RENTAL_CLAIM_WINDOW, "The claim window is over");
+        assert!(self.collateral_claims.get(&listing_id).is_none(), "The collateral of a previous rental is still claimed");
+
+        let rental = self.internal_end_rental(&mut listing);
+        let claim = CollateralClaim {
+            renter_id: rental.renter_id,
+            amount: rental.collateral,
+            reason,
+            opened_at: U64(now / 1_000_000),
+            expires_at: U64(now + DISPUTE_HOLD_DURATION),
+        };
+        self.collateral_claims.insert(&listing_id, &claim);
+
+        log!("{} claims the collateral {} of {}: {}", listing.seller_id, claim.amount.0, claim.renter_id, claim.reason);
+    }
+
+    /// Closes a collateral claim (moderators only), paying the collateral to
+    /// the seller when `award_to_seller`, back to the renter otherwise.
+    pub fn resolve_collateral_claim(&mut self, listing_id: U128, award_to_seller: bool) {
+        self.assert_moderator();
+        let claim = self.collateral_claims.remove(&listing_id).expect("No collateral is claimed");
+        let listing = self.listings.get(&listing_id).expect("Listing not found");
+
+        let receiver_id = if award_to_seller { listing.seller_id } else { claim.renter_id };
+        log!("Collateral {} claimed on listing {} goes to {}", claim.amount.0, listing_id.0, receiver_id);
+        self.internal_pay(receiver_id, &None, claim.amount.0);
+    }
+
+    /// Returns the collateral of a claim no moderator resolved in time to
+    /// the renter, anyone can call it.
+    pub fn release_collateral_claim(&mut self, listing_id: U128) {
+        let claim = self.collateral_claims.get(&listing_id).expect("No collateral is claimed");
+        assert!(env::block_timestamp() >= claim.expires_at.0, "Claim has not timed out yet");
+        self.collateral_claims.remove(&listing_id);
+
+        log!("Claim on listing {} timed out, returning the collateral {} to {}", listing_id.0, claim.amount.0, claim.renter_id);
+        self.internal_pay(claim.renter_id, &None, claim.amount.0);
+    }
+
+    /// Gets the open collateral claim of a rental listing.
+    pub fn get_collateral_claim(&self, listing_id: U128) -> Option<CollateralClaimView> {
+        self.collateral_claims.get(&listing_id).map(|claim| CollateralClaimView {
+            listing_id: listing_id.0.to_string(),
+            renter_id: claim.renter_id.to_string(),
+            amount: claim.amount.0.to_string(),
+            reason: claim.reason,
+            opened_at: claim.opened_at.0.to_string(),
+            expires_at: claim.expires_at.0.to_string(),
+        })
+    }
+}
+
+impl Marketplace {
+    /// Pays out the rent earned since the last stream, split by the same
+    /// shares as the whole rent. Returns the amount streamed.
+    pub(crate) fn internal_stream_rent(&mut self, listing: &mut Listing) -> u128 {
+        let ListingKind::Rental { rental: Some(rental), .. } = &mut listing.kind else {
+            env::panic_str("Listing is not rented");
+        };
+        assert!(rental.confirmed, "Rental is not confirmed yet");
+
+        let until = env::block_timestamp().min(rental.ends_at.0);
+        if until <= rental.streamed_until.0 {
+            return 0;
+        }
+
+        // Shares earned by `until` less those already paid, exact at the end
+        let duration = (rental.ends_at.0 - rental.starts_at.0) as u128;
+        let (from, to) = ((rental.streamed_until.0 - rental.starts_at.0) as u128, (until - rental.starts_at.0) as u128);
+        let due = |amount: u128| mul_div(amount, to, duration) - mul_div(amount, from, duration);
+
+        let amount = due(rental.rent.0);
+        let breakdown = PayoutBreakdown {
+            marketplace_fee: due(rental.marketplace_fee.0),
+            royalties: rental.royalties.iter().map(|(receiver_id, amount)| (receiver_id.clone(), due(amount.0))).collect(),
+            seller_proceeds: due(rental.seller_proceeds.0),
+        };
+        rental.streamed_until = U64(until);
+
+        self.internal_accrue_fee(&None, breakdown.marketplace_fee);
+        self.internal_record_volume(&listing.seller_id, &None, amount);
+        for (receiver_id, amount) in breakdown.royalties.iter() {
+            self.internal_pay_or_hold(listing, receiver_id.clone(), *amount);
+        }
+        self.internal_pay_or_hold(listing, listing.seller_id.clone(), breakdown.seller_proceeds);
+
+        events::emit_rent_streamed(listing, amount, &breakdown);
+
+        amount
+    }
+
+    /// Streams the rest of the rent of a rental that is over and clears it,
+    /// returning the rental whose collateral is still to be paid out
+    pub(crate) fn internal_end_rental(&mut self, listing: &mut Listing) -> Rental {
+        self.internal_stream_rent(listing);
+
+        let ListingKind::Rental { rental, .. } = &mut listing.kind else {
+            env::panic_str("Listing is not for rent");
+        };
+        let rental = rental.take().expect("Listing is not rented");
+        listing.updated_at = U64(env::block_timestamp() / 1_000_000);
+        self.listings.insert(&listing.id, listing);
+
+        events::emit_rental_ended(listing, &rental);
+        log!("Rental of token {} by {} ended", listing.token_id, rental.renter_id);
+
+        rental
+    }
+}
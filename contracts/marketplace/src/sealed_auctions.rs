@@ -0,0 +1,262 @@
+//! Sealed-bid auctions: bids are committed as hashes with a deposit covering
+//! them and only revealed once bidding closed, so nobody can react to them
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::{
+    env, log, near_bindgen, AccountId, CryptoHash, Promise,
+    json_types::{Base58CryptoHash, U128, U64},
+    serde::{Deserialize, Serialize},
+};
+
+use crate::{
+    add_to_set, events, remove_from_set, Listing, ListingKind, ListingStatus, Marketplace, MarketplaceExt, StorageKey,
+};
+
+/// What the winner of a sealed-bid auction pays
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SealedBidPricing {
+    /// Their own bid
+    #[default]
+    FirstPrice,
+    /// The second highest revealed bid, at least the start price
+    SecondPrice,
+}
+
+impl SealedBidPricing {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SealedBidPricing::FirstPrice => "first_price",
+            SealedBidPricing::SecondPrice => "second_price",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+pub struct SealedBidView {
+    pub id: String,
+    pub bidder_id: String,
+    pub auction_id: String,
+    pub commitment: String,
+    pub deposit: String,
+    pub amount: Option<String>, // None until revealed
+    pub created_at: String,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
+pub struct SealedBid {
+    /// The ID of the bid, counted with the open bids
+    pub id: U128,
+    /// The account ID of the bidder
+    pub bidder_id: AccountId,
+    /// The sealed-bid auction the bid is for
+    pub auction_id: U128,
+    /// SHA-256 of the bid, see `sealed_bid_commitment`
+    pub commitment: CryptoHash,
+    /// The NEAR held in escrow, at least the bid amount
+    pub deposit: U128,
+    /// The bid amount, once revealed
+    pub amount: Option<U128>,
+    /// The timestamp when the bid was committed
+    pub created_at: U64,
+}
+
+#[near_bindgen]
+impl Marketplace {
+    /// Commits a sealed bid on an auction during its bidding phase. The
+    /// commitment is the base58 SHA-256 of `"{auction_id}:{bidder_id}:{amount}:{salt}"`,
+    /// the attached deposit must cover the amount and is held in escrow.
+    /// Depositing more than the bid keeps the amount hidden.
+    #[payable]
+    pub fn commit_bid(&mut self, auction_id: U128, commitment: Base58CryptoHash) -> U128 {
+        let bidder_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit().as_yoctonear();
+        let mut listing = self.listings.get(&auction_id).expect("Auction not found");
+
+        assert_eq!(listing.status, ListingStatus::Active, "Auction is not active");
+        self.assert_can_trade(&listing, &bidder_id);
+        assert_ne!(bidder_id, listing.seller_id, "Cannot bid on your own auction");
+        let ListingKind::SealedAuction { start_price, start_time, commit_end, bid_count, .. } = &mut listing.kind else {
+            env::panic_str("Listing does not take sealed bids");
+        };
+
+        let now = env::block_timestamp();
+        assert!(now >= start_time.0, "Auction has not started");
+        assert!(now <= commit_end.0, "Bidding has closed");
+        assert!(deposit >= start_price.0, "Deposit must be at least {}", start_price.0);
+
+        *bid_count += 1;
+        listing.updated_at = U64(now / 1_000_000);
+        self.listings.insert(&auction_id, &listing);
+
+        self.total_bids.0 += 1;
+        let bid = SealedBid {
+            id: self.total_bids,
+            bidder_id: bidder_id.clone(),
+            auction_id,
+            commitment: commitment.into(),
+            deposit: U128(deposit),
+            amount: None,
+            created_at: U64(now / 1_000_000),
+        };
+        self.sealed_bids.insert(&bid.id, &bid);
+        events::emit_sealed_bid_committed(&bid);
+
+        // Unrevealed bids are settled once the reveal phase is over
+        add_to_set(&mut self.unrevealed_bids, &auction_id, &bid.id, StorageKey::UnrevealedBidsPerAuction {
+            auction_hash: env::sha256_array(&auction_id.0.to_le_bytes()),
+        });
+        add_to_set(&mut self.bids_by_bidder, &bidder_id, &bid.id, StorageKey::BidsPerBidder {
+            account_hash: env::sha256_array(bidder_id.as_bytes()),
+        });
+
+        bid.id
+    }
+
+    /// Reveals a sealed bid after bidding closed and before the reveal
+    /// phase ends (bidder only). A bid that cannot win any more, below the
+    /// start price or the highest revealed bid, is refunded right away, as is
+    /// the highest bid it outbids.
+    pub fn reveal_bid(&mut self, bid_id: U128, amount: U128, salt: String) {
+        let mut bid = self.sealed_bids.get(&bid_id).expect("Bid not found");
+        let mut listing = self.listings.get(&bid.auction_id).expect("Auction not found");
+
+        assert_eq!(env::predecessor_account_id(), bid.bidder_id, "Only the bidder can reveal a bid");
+        assert!(bid.amount.is_none(), "Bid is already revealed");
+        assert_eq!(listing.status, ListingStatus::Active, "Auction is not active");
+        let ListingKind::SealedAuction { start_price, commit_end, reveal_end, highest_bid_id, highest_bidder_id, highest_bid, second_bid, .. } =
+            &mut listing.kind
+        else {
+            env::panic_str("Listing does not take sealed bids");
+        };
+
+        let now = env::block_timestamp();
+        assert!(now > commit_end.0, "Bidding has not closed yet");
+        assert!(now <= reveal_end.0, "Reveal phase is over");
+        assert_eq!(
+            sealed_bid_hash(&bid.auction_id, &bid.bidder_id, amount.0, &salt),
+            bid.commitment,
+            "Amount and salt do not match the commitment"
+        );
+        assert!(amount.0 <= bid.deposit.0, "Bid is higher than its deposit");
+
+        // Only the highest revealed bid stays in escrow
+        let refund = if amount.0 < start_price.0 {
+            Some((bid.bidder_id.clone(), bid.deposit.0))
+        } else if highest_bid.is_none_or(|highest_bid| amount.0 > highest_bid.0) {
+            let outbid = highest_bid_id.replace(bid.id).and_then(|outbid_id| self.sealed_bids.get(&outbid_id));
+            *second_bid = *highest_bid;
+            *highest_bidder_id = Some(bid.bidder_id.clone());
+            *highest_bid = Some(amount);
+            outbid.map(|outbid| (outbid.bidder_id, outbid.deposit.0))
+        } else {
+            *second_bid = Some(amount.max(second_bid.unwrap_or(U128(0))));
+            Some((bid.bidder_id.clone(), bid.deposit.0))
+        };
+
+        listing.updated_at = U64(now / 1_000_000);
+        self.internal_update_listing(&listing);
+        bid.amount = Some(amount);
+        self.sealed_bids.insert(&bid_id, &bid);
+        remove_from_set(&mut self.unrevealed_bids, &bid.auction_id, &bid_id);
+        events::emit_sealed_bid_revealed(&bid, amount.0);
+
+        if let Some((bidder_id, deposit)) = refund {
+            log!("Sealed bid of {} on auction {} cannot win, refunding {}", bidder_id, listing.id.0, deposit);
+            events::emit_bid_refunded(&listing, &bidder_id, deposit);
+            self.internal_send(bidder_id, None, deposit);
+        }
+    }
+
+    /// Settles up to `limit` bids of a sealed-bid auction that were not
+    /// revealed in time, anyone can call it after the reveal phase. Their
+    /// deposits are refunded, or accrued as fees when the auction slashes
+    /// unrevealed bids. Returns how many bids were settled.
+    pub fn settle_unrevealed_bids(&mut self, auction_id: U128, limit: u32) -> u32 {
+        let listing = self.listings.get(&auction_id).expect("Auction not found");
+        let ListingKind::SealedAuction { reveal_end, slash_unrevealed, .. } = listing.kind else {
+            env::panic_str("Listing does not take sealed bids");
+        };
+        assert!(env::block_timestamp() > reveal_end.0, "Reveal phase is not over yet");
+
+        let Some(unrevealed) = self.unrevealed_bids.get(&auction_id) else {
+            return 0;
+        };
+        let bid_ids: Vec<U128> = unrevealed.iter().take(limit as usize).collect();
+
+        for bid_id in bid_ids.iter() {
+            let bid = self.sealed_bids.get(bid_id).expect("Bid not found");
+            remove_from_set(&mut self.unrevealed_bids, &auction_id, bid_id);
+            if slash_unrevealed {
+                log!("Sealed bid {} was not revealed, slashing {}", bid_id.0, bid.deposit.0);
+                self.internal_accrue_fee(&None, bid.deposit.0);
+                events::emit_sealed_bid_slashed(&bid);
+            } else {
+                log!("Sealed bid {} was not revealed, refunding {}", bid_id.0, bid.deposit.0);
+                events::emit_bid_refunded(&listing, &bid.bidder_id, bid.deposit.0);
+                self.internal_send(bid.bidder_id, None, bid.deposit.0);
+            }
+        }
+
+        bid_ids.len() as u32
+    }
+
+    /// Gets the details of a sealed bid.
+    pub fn get_sealed_bid(&self, bid_id: U128) -> Option<SealedBidView> {
+        self.sealed_bids.get(&bid_id).map(|bid| self.sealed_bid_to_view(bid))
+    }
+
+    /// Gets the commitment of a sealed bid. Anyone watching the calls sees
+    /// the arguments, compute it off-chain before bidding for real.
+    pub fn sealed_bid_commitment(&self, auction_id: U128, bidder_id: AccountId, amount: U128, salt: String) -> Base58CryptoHash {
+        sealed_bid_hash(&auction_id, &bidder_id, amount.0, &salt).into()
+    }
+}
+
+impl Marketplace {
+    /// Ends a sealed-bid auction after its reveal phase. The highest revealed
+    /// bid wins at the auction's pricing and the rest of its deposit is
+    /// refunded with the settlement; without one the auction expires.
+    pub(crate) fn internal_end_sealed_auction(&mut self, listing: &mut Listing) -> Option<Promise> {
+        let ListingKind::SealedAuction { start_price, reveal_end, pricing, highest_bid_id, highest_bid, second_bid, .. } = listing.kind.clone()
+        else {
+            env::panic_str("Listing is not a sealed-bid auction");
+        };
+        assert!(env::block_timestamp() > reveal_end.0, "Auction has not ended yet");
+
+        let Some(winning_bid) = highest_bid_id.and_then(|bid_id| self.sealed_bids.get(&bid_id)) else {
+            self.internal_close_listing(listing, ListingStatus::Expired);
+            events::emit_auction_ended(listing, None);
+            return None;
+        };
+
+        let price = match pricing {
+            SealedBidPricing::FirstPrice => highest_bid.expect("Winning bid is revealed"),
+            SealedBidPricing::SecondPrice => second_bid.unwrap_or(start_price).max(start_price),
+        };
+
+        listing.buyer_id = Some(winning_bid.bidder_id.clone());
+        self.internal_close_listing(listing, ListingStatus::Sold);
+        events::emit_auction_ended(listing, Some((&winning_bid.bidder_id, price.0)));
+
+        Some(self.internal_settle(listing, winning_bid.bidder_id, price, winning_bid.deposit))
+    }
+
+    fn sealed_bid_to_view(&self, bid: SealedBid) -> SealedBidView {
+        SealedBidView {
+            id: bid.id.0.to_string(),
+            bidder_id: bid.bidder_id.to_string(),
+            auction_id: bid.auction_id.0.to_string(),
+            commitment: String::from(&Base58CryptoHash::from(bid.commitment)),
+            deposit: bid.deposit.0.to_string(),
+            amount: bid.amount.map(|amount| amount.0.to_string()),
+            created_at: bid.created_at.0.to_string(),
+        }
+    }
+}
+
+/// SHA-256 a sealed bid commits to. The auction and bidder are part of it so
+/// a commitment cannot be copied to another auction or by another bidder.
+fn sealed_bid_hash(auction_id: &U128, bidder_id: &AccountId, amount: u128, salt: &str) -> CryptoHash {
+    env::sha256_array(format!("{}:{}:{}:{}", auction_id.0, bidder_id, amount, salt).as_bytes())
+}
@@ -0,0 +1,177 @@
+//! Trading statistics recorded as sales settle: volume, trade counts, last
+//! and highest prices per account, NFT contract and token, and the rolling
+//! volume of the whole marketplace
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::{
+    env, near_bindgen, AccountId,
+    json_types::{U128, U64},
+    serde::{Deserialize, Serialize},
+};
+
+use crate::{contract_and_token_id, Listing, ListingKind, Marketplace, MarketplaceExt, STATS_BUCKET_DURATION, STATS_BUCKETS};
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+pub struct TradeStatsView {
+    pub volume: String,
+    pub trades: u64,
+    pub last_sale_price: Option<String>,
+    pub last_sale_at: Option<String>,
+    pub all_time_high: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+pub struct AccountStatsView {
+    pub sold: TradeStatsView,
+    pub bought: TradeStatsView,
+}
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+pub struct MarketStatsView {
+    pub total: TradeStatsView,
+    pub volume_24h: String,
+    pub trades_24h: u64,
+    pub volume_7d: String,
+    pub trades_7d: u64,
+}
+
+/// Sales settled in NEAR, token amounts are not comparable
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct TradeStats {
+    /// Total of the sale prices in yoctoNEAR
+    pub volume: U128,
+    /// The number of sales
+    pub trades: u64,
+    /// The price of the latest sale
+    pub last_sale_price: U128,
+    /// The timestamp of the latest sale
+    pub last_sale_at: U64,
+    /// The highest sale price
+    pub all_time_high: U128,
+}
+
+impl TradeStats {
+    fn record(&mut self, price: u128, timestamp: U64) {
+        self.volume.0 += price;
+        self.trades += 1;
+        self.last_sale_price = U128(price);
+        self.last_sale_at = timestamp;
+        self.all_time_high = self.all_time_high.max(U128(price));
+    }
+
+    fn to_view(&self) -> TradeStatsView {
+        let traded = self.trades > 0;
+        TradeStatsView {
+            volume: self.volume.0.to_string(),
+            trades: self.trades,
+            last_sale_price: traded.then(|| self.last_sale_price.0.to_string()),
+            last_sale_at: traded.then(|| self.last_sale_at.0.to_string()),
+            all_time_high: traded.then(|| self.all_time_high.0.to_string()),
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct AccountStats {
+    /// Sales of the account's listings
+    pub sold: TradeStats,
+    /// Listings the account bought or won
+    pub bought: TradeStats,
+}
+
+/// The sales of one `STATS_BUCKET_DURATION`, kept in a ring of
+/// `STATS_BUCKETS` slots overwritten as time goes round
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct VolumeBucket {
+    /// The block timestamp divided by `STATS_BUCKET_DURATION`
+    pub period: u64,
+    /// Total of the sale prices in yoctoNEAR
+    pub volume: U128,
+    /// The number of sales
+    pub trades: u64,
+}
+
+#[near_bindgen]
+impl Marketplace {
+    /// Gets what an account sold and bought, in NEAR.
+    pub fn get_account_stats(&self, account_id: AccountId) -> AccountStatsView {
+        let stats = self.account_stats.get(&account_id).unwrap_or_default();
+        AccountStatsView { sold: stats.sold.to_view(), bought: stats.bought.to_view() }
+    }
+
+    /// Gets the sales of the tokens of an NFT contract, in NEAR. Bundles
+    /// count towards the NFT contract of their first token.
+    pub fn get_collection_stats(&self, nft_contract_id: AccountId) -> TradeStatsView {
+        self.collection_stats.get(&nft_contract_id).unwrap_or_default().to_view()
+    }
+
+    /// Gets the sales of a token, in NEAR. Sales in a bundle are not counted.
+    pub fn get_token_stats(&self, nft_contract_id: AccountId, token_id: String) -> TradeStatsView {
+        self.token_stats.get(&contract_and_token_id(&nft_contract_id, &token_id)).unwrap_or_default().to_view()
+    }
+
+    /// Gets the lifetime sales of the marketplace, in NEAR, and the volume of
+    /// the last 24 hours and 7 days, to the hour.
+    pub fn get_market_stats(&self) -> MarketStatsView {
+        let (volume_24h, trades_24h) = self.internal_rolling_volume(24);
+        let (volume_7d, trades_7d) = self.internal_rolling_volume(STATS_BUCKETS);
+
+        MarketStatsView {
+            total: self.market_stats.to_view(),
+            volume_24h: volume_24h.to_string(),
+            trades_24h,
+            volume_7d: volume_7d.to_string(),
+            trades_7d,
+        }
+    }
+}
+
+impl Marketplace {
+    /// Records a settled sale of `listing` to `buyer_id` for `price`
+    pub(crate) fn internal_record_trade(&mut self, listing: &Listing, buyer_id: &AccountId, price: u128) {
+        if listing.ft_token_id.is_some() {
+            return;
+        }
+        let now = env::block_timestamp();
+        let timestamp = U64(now / 1_000_000);
+
+        self.market_stats.record(price, timestamp);
+
+        let mut seller_stats = self.account_stats.get(&listing.seller_id).unwrap_or_default();
+        seller_stats.sold.record(price, timestamp);
+        self.account_stats.insert(&listing.seller_id, &seller_stats);
+
+        let mut buyer_stats = self.account_stats.get(buyer_id).unwrap_or_default();
+        buyer_stats.bought.record(price, timestamp);
+        self.account_stats.insert(buyer_id, &buyer_stats);
+
+        let mut collection_stats = self.collection_stats.get(&listing.nft_contract_id).unwrap_or_default();
+        collection_stats.record(price, timestamp);
+        self.collection_stats.insert(&listing.nft_contract_id, &collection_stats);
+
+        // The price of a bundle says nothing about any one of its tokens
+        if !matches!(listing.kind, ListingKind::Bundle { .. }) {
+            let token_key = contract_and_token_id(&listing.nft_contract_id, &listing.token_id);
+            let mut token_stats = self.token_stats.get(&token_key).unwrap_or_default();
+            token_stats.record(price, timestamp);
+            self.token_stats.insert(&token_key, &token_stats);
+        }
+
+        let period = now / STATS_BUCKET_DURATION;
+        let slot = period % STATS_BUCKETS;
+        let mut bucket = self.volume_buckets.get(&slot).filter(|bucket| bucket.period == period).unwrap_or_default();
+        bucket.period = period;
+        bucket.volume.0 += price;
+        bucket.trades += 1;
+        self.volume_buckets.insert(&slot, &bucket);
+    }
+
+    /// The volume and number of sales of the last `periods` buckets,
+    /// the current one included
+    fn internal_rolling_volume(&self, periods: u64) -> (u128, u64) {
+        let current = env::block_timestamp() / STATS_BUCKET_DURATION;
+        (current.saturating_sub(periods - 1)..=current)
+            .filter_map(|period| self.volume_buckets.get(&(period % STATS_BUCKETS)).filter(|bucket| bucket.period == period))
+            .fold((0, 0), |(volume, trades), bucket| (volume + bucket.volume.0, trades + bucket.trades))
+    }
+}
@@ -0,0 +1,129 @@
+//! Floor sweeps: buying the cheapest listings matching a filter in one call
+
+use near_sdk::{
+    env, log, near_bindgen, AccountId, NearToken, Promise, PromiseResult,
+    json_types::U128,
+};
+use std::collections::HashMap;
+
+use crate::{
+    ext_nft_contract, payout_royalties, ListingFilter, ListingKind, ListingStatus, Marketplace, MarketplaceExt, Payout,
+    GAS_FOR_RESOLVE_SWEEP_LISTING, GAS_FOR_SWEEP_PAYMENT, GAS_FOR_SWEEP_TRANSFER, MAX_LEN_PAYOUT, MAX_SWEEP_SIZE,
+};
+
+#[near_bindgen]
+impl Marketplace {
+    /// Buys the `count` cheapest active listings matching `filter` that can
+    /// be bought in NEAR right now, fixed-price sales and running Dutch
+    /// auctions, each at no more than `max_price`. The attached deposit must
+    /// cover them all, anything above is refunded. Returns a promise of the
+    /// refund of listings whose token could not be transferred.
+    #[payable]
+    pub fn sweep(&mut self, max_price: U128, count: u32, filter: Option<ListingFilter>) -> Promise {
+        let buyer_id = env::predecessor_account_id();
+        let deposit = env::attached_deposit().as_yoctonear();
+        let now = env::block_timestamp();
+        assert!((1..=MAX_SWEEP_SIZE).contains(&count), "A sweep buys 1 to {} listings", MAX_SWEEP_SIZE);
+        self.assert_not_paused();
+        self.assert_not_blocked(&buyer_id);
+
+        let filter = filter.unwrap_or_default();
+        let max_price = filter.max_price.map_or(max_price.0, |price| price.0.min(max_price.0));
+        let filter = ListingFilter { currency: Some("near".to_string()), status: None, max_price: Some(U128(max_price)), ..filter };
+        let listings: Vec<(u128, _)> = self
+            .internal_listings_by_price(&filter, false)
+            .filter(|(_, listing)| listing.seller_id != buyer_id && self.is_tradable(listing))
+            .filter(|(_, listing)| match &listing.kind {
+                ListingKind::Sale { .. } => true,
+                ListingKind::DutchAuction { start_time, end_time, .. } => start_time.0 <= now && now <= end_time.0,
+                _ => false,
+            })
+            .take(count as usize)
+            .collect();
+        assert!(!listings.is_empty(), "No listing matches");
+
+        let total: u128 = listings.iter().map(|(price, _)| price).sum();
+        assert!(deposit >= total, "Insufficient deposit, the listings come to {}", total);
+
+        // Closed before the transfers so no listing can be bought twice
+        let mut transfers = Vec::with_capacity(listings.len());
+        let (mut listing_ids, mut prices, mut marketplace_fees) = (vec![], vec![], vec![]);
+        for (price, mut listing) in listings {
+            let marketplace_fee = self.internal_marketplace_fee(&listing.seller_id, price);
+            listing.buyer_id = Some(buyer_id.clone());
+            self.internal_close_listing(&mut listing, ListingStatus::Sold);
+
+            transfers.push(
+                ext_nft_contract::ext(listing.nft_contract_id.clone())
+                    .with_attached_deposit(NearToken::from_yoctonear(1))
+                    .with_static_gas(GAS_FOR_SWEEP_TRANSFER)
+                    .nft_transfer_payout(
+                        buyer_id.clone(),
+                        listing.token_id.clone(),
+                        Some(listing.approval_id),
+                        Some(format!("Marketplace listing {}", listing.id.0)),
+                        U128(price - marketplace_fee),
+                        Some(MAX_LEN_PAYOUT),
+                    ),
+            );
+            listing_ids.push(listing.id);
+            prices.push(U128(price));
+            marketplace_fees.push(U128(marketplace_fee));
+        }
+
+        // Every listing may pay out to as many receivers as a purchase settles
+        let resolve_gas = GAS_FOR_RESOLVE_SWEEP_LISTING
+            .saturating_add(GAS_FOR_SWEEP_PAYMENT.saturating_mul(MAX_LEN_PAYOUT as u64))
+            .saturating_mul(listing_ids.len() as u64);
+
+        let surplus = deposit - total;
+        if surplus > 0 {
+            log!("Refunding {} paid above the swept listings to {}", surplus, buyer_id);
+            self.internal_send(buyer_id.clone(), None, surplus);
+        }
+
+        transfers
+            .into_iter()
+            .reduce(|transfers, transfer| transfers.and(transfer))
+            .expect("Sweep has no listings")
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(resolve_gas)
+                    .resolve_sweep_purchase(buyer_id, listing_ids, prices, marketplace_fees),
+            )
+    }
+
+    /// Settles each listing of a sweep like a purchase: paid out once its
+    /// token was transferred, cancelled and refunded to the buyer otherwise.
+    /// Returns the amount refunded.
+    #[private]
+    pub fn resolve_sweep_purchase(
+        &mut self,
+        buyer_id: AccountId,
+        listing_ids: Vec<U128>,
+        prices: Vec<U128>,
+        marketplace_fees: Vec<U128>,
+    ) -> U128 {
+        let mut refunded = 0;
+        for (index, listing_id) in listing_ids.iter().enumerate() {
+            let mut listing = self.listings.get(listing_id).expect("Listing not found");
+            let (price, marketplace_fee) = (prices[index].0, marketplace_fees[index].0);
+
+            match env::promise_result(index as u64) {
+                PromiseResult::Successful(value) => {
+                    // The token was delivered, a payout that cannot be read only loses the royalties
+                    let royalties = near_sdk::serde_json::from_slice::<Payout>(&value).ok().map_or_else(HashMap::new, |payout| {
+                        payout_royalties(payout, price - marketplace_fee, &listing.seller_id, &listing.token_id)
+                    });
+                    self.internal_pay_out(&listing, buyer_id.clone(), price, price, marketplace_fee, royalties);
+                }
+                PromiseResult::Failed => {
+                    log!("Transfer of token {} failed, refunding {}", listing.token_id, buyer_id);
+                    refunded += self.internal_refund_purchase(&mut listing, buyer_id.clone(), price).0;
+                }
+            }
+        }
+
+        U128(refunded)
+    }
+}
@@ -0,0 +1,198 @@
+//! Paginated views to browse listings, filtered and sorted
+
+use near_sdk::{
+    env, near_bindgen, AccountId,
+    collections::UnorderedSet,
+    json_types::U128,
+    serde::{Deserialize, Serialize},
+};
+
+use crate::{is_expired, Listing, ListingStatus, ListingView, Marketplace, MarketplaceExt, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+
+/// Criteria a listing must meet to be returned, every one is optional
+#[derive(Serialize, Deserialize, Default)]
+pub struct ListingFilter {
+    pub seller_id: Option<AccountId>,
+    pub nft_contract_id: Option<AccountId>,
+    /// Lowest current price, in the currency of the listing
+    pub min_price: Option<U128>,
+    /// Highest current price, in the currency of the listing
+    pub max_price: Option<U128>,
+    /// `"near"` or the NEP-141 token the listing is priced in
+    pub currency: Option<String>,
+    /// `"sale"`, `"auction"`, `"dutch_auction"`, `"sealed_auction"`, `"bundle"` or `"rental"`
+    pub listing_type: Option<String>,
+    /// `"active"` (the default), `"sold"`, `"cancelled"` or `"expired"`
+    pub status: Option<String>,
+}
+
+impl ListingFilter {
+    fn matches(&self, listing: &Listing, timestamp: u64) -> bool {
+        let price = listing.kind.price_at(timestamp);
+        let currency = listing.ft_token_id.as_ref().map_or("near", |ft_token_id| ft_token_id.as_str());
+
+        self.seller_id.as_ref().is_none_or(|seller_id| *seller_id == listing.seller_id)
+            && self.nft_contract_id.as_ref().is_none_or(|nft_contract_id| *nft_contract_id == listing.nft_contract_id)
+            && self.min_price.is_none_or(|min_price| price >= min_price.0)
+            && self.max_price.is_none_or(|max_price| price <= max_price.0)
+            && self.currency.as_deref().is_none_or(|wanted| wanted == currency)
+            && self.listing_type.as_deref().is_none_or(|listing_type| listing_type == listing.kind.as_str())
+    }
+}
+
+#[near_bindgen]
+impl Marketplace {
+    /// Gets listings matching `filter`, active ones when no status is given.
+    /// `from_index` counts matching listings, in the order of the index of
+    /// their status.
+    pub fn get_listings(&self, from_index: Option<U128>, limit: Option<u64>, filter: Option<ListingFilter>) -> Vec<ListingView> {
+        let filter = filter.unwrap_or_default();
+        self.internal_filter_listings(&filter)
+            .skip(from_index.map_or(0, |index| index.0 as usize))
+            .take(page_size(limit))
+            .map(|listing| self.listing_to_view(listing))
+            .collect()
+    }
+
+    /// Gets the active listings matching `filter` sorted by their current
+    /// price, the cheapest first unless `descending`. Prices in different
+    /// currencies are not comparable, filter by currency to get a meaningful
+    /// order.
+    pub fn get_listings_by_price(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+        filter: Option<ListingFilter>,
+        descending: Option<bool>,
+    ) -> Vec<ListingView> {
+        let filter = ListingFilter { status: None, ..filter.unwrap_or_default() };
+        self.internal_listings_by_price(&filter, descending.unwrap_or(false))
+            .skip(from_index.map_or(0, |index| index.0 as usize))
+            .take(page_size(limit))
+            .map(|(_, listing)| self.listing_to_view(listing))
+            .collect()
+    }
+
+    /// Gets the active auctions and Dutch auctions matching `filter` that
+    /// have not ended yet, the first to end first. Sealed-bid auctions count
+    /// until bidding closes.
+    pub fn get_auctions_ending_soon(&self, from_index: Option<U128>, limit: Option<u64>, filter: Option<ListingFilter>) -> Vec<ListingView> {
+        let filter = filter.unwrap_or_default();
+        let timestamp = env::block_timestamp();
+        self.auctions_by_end_time
+            .iter_from((timestamp, u128::MAX))
+            .filter_map(|((_, listing_id), _)| self.listings.get(&U128(listing_id)))
+            .filter(|listing| filter.matches(listing, timestamp))
+            .skip(from_index.map_or(0, |index| index.0 as usize))
+            .take(page_size(limit))
+            .map(|listing| self.listing_to_view(listing))
+            .collect()
+    }
+}
+
+impl Marketplace {
+    /// Listings matching `filter`. Active ones are read from the index of
+    /// active listings, leaving out those past their expiry; the others from
+    /// the index of their status.
+    pub(crate) fn internal_filter_listings<'a>(&'a self, filter: &'a ListingFilter) -> Box<dyn Iterator<Item = Listing> + 'a> {
+        let timestamp = env::block_timestamp();
+        let status = match filter.status.as_deref().unwrap_or(ListingStatus::Active.as_str()) {
+            "active" => {
+                return Box::new(
+                    self.active_listings
+                        .iter()
+                        .filter_map(|listing_id| self.listings.get(&listing_id))
+                        .filter(move |listing| !is_expired(listing, timestamp) && filter.matches(listing, timestamp)),
+                )
+            }
+            "sold" => ListingStatus::Sold,
+            "cancelled" => ListingStatus::Cancelled,
+            "expired" => ListingStatus::Expired,
+            _ => return Box::new(std::iter::empty()),
+        };
+
+        let Some(listing_ids) = self.listings_by_status.get(&status) else {
+            return Box::new(std::iter::empty());
+        };
+        Box::new(
+            (0..listing_ids.len())
+                .filter_map(move |index| listing_ids.as_vector().get(index))
+                .filter_map(|listing_id| self.listings.get(&listing_id))
+                .filter(move |listing| filter.matches(listing, timestamp)),
+        )
+    }
+
+    /// Active listings matching `filter` with their current price, by price
+    /// and ID. The price index is walked from `min_price` or `max_price` on
+    /// and the running Dutch auctions, whose price falls with time, are
+    /// merged into it.
+    pub(crate) fn internal_listings_by_price<'a>(
+        &'a self,
+        filter: &'a ListingFilter,
+        descending: bool,
+    ) -> impl Iterator<Item = (u128, Listing)> + 'a {
+        let timestamp = env::block_timestamp();
+        let min_price = filter.min_price.map_or(0, |price| price.0);
+        let max_price = filter.max_price.map_or(u128::MAX, |price| price.0);
+
+        // Listing IDs start at 1 and never reach `u128::MAX`, the exclusive bounds hold no listing
+        let indexed: Box<dyn Iterator<Item = (u128, u128)> + 'a> = if descending {
+            Box::new(
+                self.listings_by_price
+                    .iter_rev_from((max_price, u128::MAX))
+                    .map(|(key, _)| key)
+                    .take_while(move |(price, _)| *price >= min_price),
+            )
+        } else {
+            Box::new(
+                self.listings_by_price
+                    .iter_from((min_price, 0))
+                    .map(|(key, _)| key)
+                    .take_while(move |(price, _)| *price <= max_price),
+            )
+        };
+        let mut dutch_auctions: Vec<(u128, u128)> = self
+            .dutch_auctions
+            .iter()
+            .filter_map(|listing_id| self.listings.get(&listing_id))
+            .map(|listing| (listing.kind.price_at(timestamp), listing.id.0))
+            .filter(|(price, _)| (min_price..=max_price).contains(price))
+            .collect();
+        dutch_auctions.sort();
+        if descending {
+            dutch_auctions.reverse();
+        }
+
+        let mut indexed = indexed.peekable();
+        let mut dutch_auctions = dutch_auctions.into_iter().peekable();
+        std::iter::from_fn(move || {
+            let next_is_dutch = match (indexed.peek(), dutch_auctions.peek()) {
+                (Some(indexed), Some(dutch_auction)) => (dutch_auction < indexed) != descending,
+                (None, Some(_)) => true,
+                (_, None) => false,
+            };
+            if next_is_dutch {
+                dutch_auctions.next()
+            } else {
+                indexed.next()
+            }
+        })
+        .filter_map(|(price, listing_id)| self.listings.get(&U128(listing_id)).map(|listing| (price, listing)))
+        .filter(move |(_, listing)| !is_expired(listing, timestamp) && filter.matches(listing, timestamp))
+    }
+}
+
+/// The number of items a page holds, `DEFAULT_PAGE_SIZE` unless `limit` is
+/// given, at most `MAX_PAGE_SIZE`
+pub(crate) fn page_size(limit: Option<u64>) -> usize {
+    limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE) as usize
+}
+
+/// A page of the IDs in `set`
+pub(crate) fn paginate_ids(set: &UnorderedSet<U128>, from_index: Option<U128>, limit: Option<u64>) -> Vec<String> {
+    set.iter()
+        .skip(from_index.map_or(0, |index| index.0 as usize))
+        .take(page_size(limit))
+        .map(|id| id.0.to_string())
+        .collect()
+}
@@ -0,0 +1,223 @@
+use near_workspaces::{network::Sandbox, types::NearToken, Account, Contract, Worker};
+use serde_json::{json, Value};
+
+const START_PRICE: NearToken = NearToken::from_near(2);
+const TOKEN_ID: &str = "voice-1";
+const STORAGE_DEPOSIT: NearToken = NearToken::from_millinear(10);
+const AUCTION_DURATION: u64 = 30 * 1_000_000_000; // 30 seconds in nanoseconds
+// Upper bound on what a transaction costs in gas, to tell refunds apart from fees
+const MAX_TX_COST: NearToken = NearToken::from_millinear(100);
+
+struct Setup {
+    marketplace: Contract,
+    nft: Contract,
+    alice: Account,
+    bob: Account,
+    carol: Account,
+}
+
+/// Deploys both contracts and lists a token for auction, `config` is merged
+/// into the auction arguments
+async fn setup(config: Value) -> Result<(Worker<Sandbox>, Setup), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+    let carol = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = nft
+        .call("nft_mint")
+        .args_json(json!({
+            "token_id": TOKEN_ID,
+            "receiver_id": alice.id(),
+            "metadata": {"title": "Recording voice-1", "language": "en", "voice_type": "narration"},
+        }))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Storage for the listing, refunded when it closes
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // List the token for auction, starting now
+    let now = sandbox.view_block().await?.timestamp();
+    let mut msg = json!({
+        "type": "auction",
+        "start_price": START_PRICE.as_yoctonear().to_string(),
+        "start_time": now.to_string(),
+        "end_time": (now + AUCTION_DURATION).to_string(),
+    });
+    msg.as_object_mut().unwrap().extend(config.as_object().unwrap().clone());
+    let outcome = alice
+        .call(nft.id(), "nft_approve")
+        .args_json(json!({"token_id": TOKEN_ID, "account_id": marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    Ok((sandbox, Setup { marketplace, nft, alice, bob, carol }))
+}
+
+async fn bid(setup: &Setup, bidder: &Account, amount: NearToken) -> Result<String, Box<dyn std::error::Error>> {
+    let outcome = bidder
+        .call(setup.marketplace.id(), "place_bid")
+        .args_json(json!({"auction_id": "1"}))
+        .deposit(amount)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    Ok(outcome.json()?)
+}
+
+#[tokio::test]
+async fn test_bids_are_escrowed_and_refunded_when_outbid() -> Result<(), Box<dyn std::error::Error>> {
+    let (_sandbox, setup) = setup(json!({})).await?;
+
+    let alice_before = setup.alice.view_account().await?.balance;
+    let bob_before = setup.bob.view_account().await?.balance;
+
+    let bob_bid_id = bid(&setup, &setup.bob, START_PRICE).await?;
+    let bob_bid: Value = setup.marketplace.view("get_bid").args_json(json!({"bid_id": bob_bid_id})).await?.json()?;
+    assert_eq!(bob_bid["bidder_id"], setup.bob.id().as_str());
+    assert_eq!(bob_bid["amount"], START_PRICE.as_yoctonear().to_string());
+
+    let carol_bid = NearToken::from_near(3);
+    bid(&setup, &setup.carol, carol_bid).await?;
+
+    // Bob got his bid back, only gas was spent
+    let bob_after = setup.bob.view_account().await?.balance;
+    assert!(bob_before.as_yoctonear() - bob_after.as_yoctonear() < MAX_TX_COST.as_yoctonear());
+
+    // Nothing reaches the seller before the auction ends
+    assert_eq!(setup.alice.view_account().await?.balance, alice_before);
+
+    let listing: Value = setup.marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(listing["highest_bidder_id"], setup.carol.id().as_str());
+    assert_eq!(listing["highest_bid"], carol_bid.as_yoctonear().to_string());
+
+    let outcome = setup
+        .bob
+        .call(setup.marketplace.id(), "place_bid")
+        .args_json(json!({"auction_id": "1"}))
+        .deposit(carol_bid)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure(), "A bid equal to the highest bid must be rejected");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_end_auction_releases_funds_to_seller() -> Result<(), Box<dyn std::error::Error>> {
+    let (sandbox, setup) = setup(json!({})).await?;
+
+    let winning_bid = NearToken::from_near(3);
+    bid(&setup, &setup.bob, START_PRICE).await?;
+    bid(&setup, &setup.carol, winning_bid).await?;
+
+    let outcome = setup.alice.call(setup.marketplace.id(), "end_auction").args_json(json!({"auction_id": "1"})).max_gas().transact().await?;
+    assert!(outcome.is_failure(), "The auction must not end early");
+
+    sandbox.fast_forward(100).await?;
+
+    let alice_before = setup.alice.view_account().await?.balance;
+
+    let outcome = setup.bob.call(setup.marketplace.id(), "end_auction").args_json(json!({"auction_id": "1"})).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let token: Value = setup.nft.view("nft_token").args_json(json!({"token_id": TOKEN_ID})).await?.json()?;
+    assert_eq!(token["owner_id"], setup.carol.id().as_str());
+
+    let listing: Value = setup.marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(listing["status"], "sold");
+    assert_eq!(listing["buyer_id"], setup.carol.id().as_str());
+
+    // The seller receives the winning bid minus the 2.5% marketplace fee, and the storage deposit back
+    let alice_after = setup.alice.view_account().await?.balance;
+    let fee = winning_bid.as_yoctonear() * 250 / 10_000;
+    assert_eq!(
+        alice_after.as_yoctonear() - alice_before.as_yoctonear(),
+        winning_bid.as_yoctonear() - fee + STORAGE_DEPOSIT.as_yoctonear()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unmet_reserve_expires_and_refunds() -> Result<(), Box<dyn std::error::Error>> {
+    let reserve = NearToken::from_near(5);
+    let (sandbox, setup) = setup(json!({"reserve_price": reserve.as_yoctonear().to_string(), "reserve_hidden": true})).await?;
+
+    let listing: Value = setup.marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert!(listing["reserve_price"].is_null(), "A hidden reserve must not be shown");
+    assert_eq!(listing["reserve_met"], false);
+
+    let bob_before = setup.bob.view_account().await?.balance;
+    bid(&setup, &setup.bob, NearToken::from_near(3)).await?;
+
+    sandbox.fast_forward(100).await?;
+
+    let outcome = setup.alice.call(setup.marketplace.id(), "end_auction").args_json(json!({"auction_id": "1"})).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let listing: Value = setup.marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(listing["status"], "expired");
+
+    let token: Value = setup.nft.view("nft_token").args_json(json!({"token_id": TOKEN_ID})).await?.json()?;
+    assert_eq!(token["owner_id"], setup.alice.id().as_str());
+
+    let bob_after = setup.bob.view_account().await?.balance;
+    assert!(bob_before.as_yoctonear() - bob_after.as_yoctonear() < MAX_TX_COST.as_yoctonear());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_min_increment_and_late_bid_extension() -> Result<(), Box<dyn std::error::Error>> {
+    // Every bid lands within the window, so each one pushes the end back
+    let (_sandbox, setup) = setup(json!({"min_increment_bps": 1000, "extension_window": (2 * AUCTION_DURATION).to_string()})).await?;
+
+    bid(&setup, &setup.bob, START_PRICE).await?;
+
+    let listing: Value = setup.marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    let min_next_bid = START_PRICE.as_yoctonear() * 11 / 10;
+    assert_eq!(listing["min_next_bid"], min_next_bid.to_string());
+    let end_time: u64 = listing["end_time"].as_str().unwrap().parse()?;
+    let start_time: u64 = listing["start_time"].as_str().unwrap().parse()?;
+    assert!(end_time > start_time + AUCTION_DURATION, "The late bid must extend the auction");
+
+    // Below the 10% increment
+    let outcome = setup
+        .carol
+        .call(setup.marketplace.id(), "place_bid")
+        .args_json(json!({"auction_id": "1"}))
+        .deposit(NearToken::from_yoctonear(min_next_bid - 1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure(), "A bid below the minimum increment must be rejected");
+
+    bid(&setup, &setup.carol, NearToken::from_yoctonear(min_next_bid)).await?;
+
+    Ok(())
+}
@@ -0,0 +1,220 @@
+use near_workspaces::types::NearToken;
+use serde_json::{json, Value};
+
+const PRICE: NearToken = NearToken::from_near(6);
+const TOKEN_IDS: [&str; 3] = ["voice-1", "voice-2", "voice-3"];
+const STORAGE_DEPOSIT: NearToken = NearToken::from_millinear(10);
+
+#[tokio::test]
+async fn test_buy_bundle_with_weighted_royalties() -> Result<(), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let creator = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Only the last token pays its creator a 10% royalty
+    for token_id in TOKEN_IDS {
+        let royalty = if token_id == "voice-3" { json!({creator.id().to_string(): 1000}) } else { json!({}) };
+        let outcome = nft
+            .call("nft_mint")
+            .args_json(json!({
+                "token_id": token_id,
+                "receiver_id": alice.id(),
+                "metadata": {"title": format!("Recording {}", token_id)},
+                "royalty": royalty,
+                "creator_id": creator.id(),
+            }))
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    }
+
+    // Storage for the listing, refunded when it closes
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // The last token is worth half of the bundle
+    let msg = json!({
+        "type": "bundle",
+        "price": PRICE.as_yoctonear().to_string(),
+        "items": [
+            {"nft_contract_id": nft.id(), "token_id": "voice-1"},
+            {"nft_contract_id": nft.id(), "token_id": "voice-2"},
+            {"nft_contract_id": nft.id(), "token_id": "voice-3", "weight": 2},
+        ],
+    });
+    let outcome = alice
+        .call(nft.id(), "nft_approve")
+        .args_json(json!({"token_id": "voice-1", "account_id": marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let listing: Value = marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(listing["listing_type"], "bundle");
+    assert_eq!(listing["items"][0]["approved"], true);
+    assert_eq!(listing["items"][1]["approved"], false);
+
+    // The bundle cannot be bought until every token is approved
+    let outcome = bob
+        .call(marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": "1"}))
+        .deposit(PRICE)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure());
+
+    for token_id in &TOKEN_IDS[1..] {
+        let msg = json!({"type": "bundle_item", "listing_id": "1"});
+        let outcome = alice
+            .call(nft.id(), "nft_approve")
+            .args_json(json!({"token_id": token_id, "account_id": marketplace.id(), "msg": msg.to_string()}))
+            .deposit(NearToken::from_yoctonear(1))
+            .max_gas()
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    }
+
+    let listing: Value = marketplace
+        .view("get_listing_by_token")
+        .args_json(json!({"nft_contract_id": nft.id(), "token_id": "voice-3"}))
+        .await?
+        .json()?;
+    assert_eq!(listing["id"], "1");
+
+    let alice_before = alice.view_account().await?.balance;
+    let creator_before = creator.view_account().await?.balance;
+
+    let outcome = bob
+        .call(marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": "1"}))
+        .deposit(PRICE)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    for token_id in TOKEN_IDS {
+        let token: Value = nft.view("nft_token").args_json(json!({"token_id": token_id})).await?.json()?;
+        assert_eq!(token["owner_id"], bob.id().as_str());
+    }
+
+    // The royalty is 10% of the last token's half of the proceeds
+    let fee = PRICE.as_yoctonear() * 250 / 10_000;
+    let royalty = (PRICE.as_yoctonear() - fee) / 2 / 10;
+    let alice_gain = alice.view_account().await?.balance.as_yoctonear() - alice_before.as_yoctonear() - STORAGE_DEPOSIT.as_yoctonear();
+    let creator_gain = creator.view_account().await?.balance.as_yoctonear() - creator_before.as_yoctonear();
+    assert_eq!(creator_gain, royalty);
+    assert_eq!(alice_gain, PRICE.as_yoctonear() - fee - royalty);
+
+    let listing: Value = marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(listing["status"], "sold");
+    assert_eq!(listing["buyer_id"], bob.id().as_str());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bundle_with_unavailable_token_is_refunded() -> Result<(), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    for token_id in TOKEN_IDS {
+        let outcome = nft
+            .call("nft_mint")
+            .args_json(json!({"token_id": token_id, "receiver_id": alice.id(), "metadata": {"title": format!("Recording {}", token_id)}}))
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    }
+
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let items: Vec<Value> = TOKEN_IDS.iter().map(|token_id| json!({"nft_contract_id": nft.id(), "token_id": token_id})).collect();
+    for (index, token_id) in TOKEN_IDS.iter().enumerate() {
+        let msg = if index == 0 {
+            json!({"type": "bundle", "price": PRICE.as_yoctonear().to_string(), "items": items})
+        } else {
+            json!({"type": "bundle_item", "listing_id": "1"})
+        };
+        let outcome = alice
+            .call(nft.id(), "nft_approve")
+            .args_json(json!({"token_id": token_id, "account_id": marketplace.id(), "msg": msg.to_string()}))
+            .deposit(NearToken::from_yoctonear(1))
+            .max_gas()
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    }
+
+    // The marketplace is not told when an approval is revoked
+    let outcome = alice
+        .call(nft.id(), "nft_revoke")
+        .args_json(json!({"token_id": "voice-3", "account_id": marketplace.id()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let bob_before = bob.view_account().await?.balance;
+    let outcome = bob
+        .call(marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": "1"}))
+        .deposit(PRICE)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Nothing is delivered and the buyer gets the whole deposit back
+    for token_id in TOKEN_IDS {
+        let token: Value = nft.view("nft_token").args_json(json!({"token_id": token_id})).await?.json()?;
+        assert_eq!(token["owner_id"], alice.id().as_str());
+    }
+    let bob_spent = bob_before.as_yoctonear() - bob.view_account().await?.balance.as_yoctonear();
+    assert!(bob_spent < NearToken::from_millinear(100).as_yoctonear());
+
+    let listing: Value = marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(listing["status"], "cancelled");
+    assert_eq!(listing["buyer_id"], Value::Null);
+
+    Ok(())
+}
@@ -0,0 +1,247 @@
+use near_workspaces::{network::Sandbox, types::NearToken, Account, Contract, Worker};
+use serde_json::{json, Value};
+
+const PRICE: NearToken = NearToken::from_near(2);
+const STORAGE_DEPOSIT: NearToken = NearToken::from_millinear(30);
+const OFFER_DURATION: u64 = 24 * 60 * 60 * 1_000_000_000; // 1 day in nanoseconds
+const MAX_SWEEP_SIZE: u32 = 2;
+// Royalty receivers besides the owner, the payout of a purchase holds at most 10
+const MAX_ROYALTY_RECEIVERS: usize = 9;
+
+struct Setup {
+    marketplace: Contract,
+    nft: Contract,
+    alice: Account,
+    bob: Account,
+    carol: Account,
+}
+
+/// Deploys both contracts and mints Alice an English narration, an English
+/// song and a French narration
+async fn setup() -> Result<(Worker<Sandbox>, Setup), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+    let carol = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    for (token_id, language, voice_type) in [("voice-1", "en", "narration"), ("voice-2", "en", "song"), ("voice-3", "fr", "narration")] {
+        let outcome = nft
+            .call("nft_mint")
+            .args_json(json!({
+                "token_id": token_id,
+                "receiver_id": alice.id(),
+                "metadata": {"title": format!("Recording {}", token_id), "language": language, "voice_type": voice_type},
+            }))
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    }
+
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    Ok((sandbox, Setup { marketplace, nft, alice, bob, carol }))
+}
+
+async fn approve(setup: &Setup, token_id: &str, msg: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let outcome = setup
+        .alice
+        .call(setup.nft.id(), "nft_approve")
+        .args_json(json!({"token_id": token_id, "account_id": setup.marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    Ok(())
+}
+
+async fn owner_of(setup: &Setup, token_id: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let token: Value = setup.nft.view("nft_token").args_json(json!({"token_id": token_id})).await?.json()?;
+    Ok(token["owner_id"].as_str().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn test_collection_offer_takes_matching_tokens() -> Result<(), Box<dyn std::error::Error>> {
+    let (sandbox, setup) = setup().await?;
+
+    // Bob takes any two English narrations
+    let now = sandbox.view_block().await?.timestamp();
+    let outcome = setup
+        .bob
+        .call(setup.marketplace.id(), "make_collection_offer")
+        .args_json(json!({
+            "criteria": {"nft_contract_id": setup.nft.id(), "language": "EN", "voice_type": "narration"},
+            "price": PRICE.as_yoctonear().to_string(),
+            "quantity": 2,
+            "expires_at": (now + OFFER_DURATION).to_string(),
+        }))
+        .deposit(PRICE.saturating_mul(2))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let offer_id: String = outcome.json()?;
+
+    let offers: Vec<String> =
+        setup.marketplace.view("get_collection_offers").args_json(json!({"nft_contract_id": setup.nft.id()})).await?.json()?;
+    assert_eq!(offers, vec![offer_id.clone()]);
+
+    // A song and a French narration do not match, the reserved token goes back to the offer
+    approve(&setup, "voice-2", json!({"type": "accept_collection_offer", "offer_id": offer_id})).await?;
+    approve(&setup, "voice-3", json!({"type": "accept_collection_offer", "offer_id": offer_id})).await?;
+    assert_eq!(owner_of(&setup, "voice-2").await?, setup.alice.id().as_str());
+    assert_eq!(owner_of(&setup, "voice-3").await?, setup.alice.id().as_str());
+    let offer: Value = setup.marketplace.view("get_collection_offer").args_json(json!({"offer_id": offer_id})).await?.json()?;
+    assert_eq!(offer["remaining"], 2);
+
+    let alice_before = setup.alice.view_account().await?.balance;
+    approve(&setup, "voice-1", json!({"type": "accept_collection_offer", "offer_id": offer_id})).await?;
+    assert_eq!(owner_of(&setup, "voice-1").await?, setup.bob.id().as_str());
+    assert!(setup.alice.view_account().await?.balance > alice_before, "The seller is paid out of the escrow");
+
+    let offer: Value = setup.marketplace.view("get_collection_offer").args_json(json!({"offer_id": offer_id})).await?.json()?;
+    assert_eq!(offer["remaining"], 1);
+    assert_eq!(offer["filled"], 1);
+
+    // The buyer gets the escrow of the token still wanted back
+    let bob_before = setup.bob.view_account().await?.balance;
+    let outcome = setup.bob.call(setup.marketplace.id(), "cancel_collection_offer").args_json(json!({"offer_id": offer_id})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    assert!(setup.bob.view_account().await?.balance.as_yoctonear() > bob_before.as_yoctonear() + PRICE.as_yoctonear() * 9 / 10);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sweep_buys_the_cheapest_listings() -> Result<(), Box<dyn std::error::Error>> {
+    let (_sandbox, setup) = setup().await?;
+
+    for (token_id, price) in [("voice-1", 3), ("voice-2", 2), ("voice-3", 5)] {
+        let price = NearToken::from_near(price).as_yoctonear().to_string();
+        approve(&setup, token_id, json!({"type": "sale", "price": price})).await?;
+    }
+
+    let outcome = setup
+        .carol
+        .call(setup.marketplace.id(), "sweep")
+        .args_json(json!({"max_price": NearToken::from_near(4).as_yoctonear().to_string(), "count": 3}))
+        .deposit(NearToken::from_near(10))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure(), "A sweep buys at most 2 listings");
+
+    let outcome = setup
+        .carol
+        .call(setup.marketplace.id(), "sweep")
+        .args_json(json!({"max_price": NearToken::from_near(4).as_yoctonear().to_string(), "count": 2}))
+        .deposit(NearToken::from_near(4))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure(), "The deposit must cover every listing swept");
+
+    // The two listings at most 4 NEAR are bought, the extra NEAR comes back
+    let carol_before = setup.carol.view_account().await?.balance;
+    let outcome = setup
+        .carol
+        .call(setup.marketplace.id(), "sweep")
+        .args_json(json!({"max_price": NearToken::from_near(4).as_yoctonear().to_string(), "count": 2}))
+        .deposit(NearToken::from_near(6))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let spent = carol_before.as_yoctonear() - setup.carol.view_account().await?.balance.as_yoctonear();
+    assert!(spent >= NearToken::from_near(5).as_yoctonear() && spent < NearToken::from_millinear(5100).as_yoctonear());
+
+    assert_eq!(owner_of(&setup, "voice-1").await?, setup.carol.id().as_str());
+    assert_eq!(owner_of(&setup, "voice-2").await?, setup.carol.id().as_str());
+    assert_eq!(owner_of(&setup, "voice-3").await?, setup.alice.id().as_str());
+
+    let settled = outcome.logs().iter().filter(|log| log.contains("\"event\":\"sale_settled\"")).count();
+    assert_eq!(settled, 2);
+
+    // The listing left is above the maximum price
+    let outcome = setup
+        .carol
+        .call(setup.marketplace.id(), "sweep")
+        .args_json(json!({"max_price": NearToken::from_near(4).as_yoctonear().to_string(), "count": 1}))
+        .deposit(NearToken::from_near(6))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure(), "No listing matches");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_full_sweep_settles_the_maximum_royalties() -> Result<(), Box<dyn std::error::Error>> {
+    let (sandbox, setup) = setup().await?;
+
+    // Every token pays royalties to as many receivers as a purchase settles
+    let mut receivers = vec![];
+    for _ in 0..MAX_ROYALTY_RECEIVERS {
+        receivers.push(sandbox.dev_create_account().await?);
+    }
+    let royalty: serde_json::Map<String, Value> = receivers.iter().map(|receiver| (receiver.id().to_string(), json!(100))).collect();
+
+    let token_ids: Vec<String> = (1..=MAX_SWEEP_SIZE).map(|index| format!("voice-royalty-{}", index)).collect();
+    for token_id in &token_ids {
+        let outcome = setup
+            .nft
+            .call("nft_mint")
+            .args_json(json!({
+                "token_id": token_id,
+                "receiver_id": setup.alice.id(),
+                "metadata": {"title": format!("Recording {}", token_id)},
+                "royalty": royalty,
+            }))
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+        approve(&setup, token_id, json!({"type": "sale", "price": PRICE.as_yoctonear().to_string()})).await?;
+    }
+
+    let receiver_before = receivers[0].view_account().await?.balance;
+    let outcome = setup
+        .carol
+        .call(setup.marketplace.id(), "sweep")
+        .args_json(json!({"max_price": PRICE.as_yoctonear().to_string(), "count": MAX_SWEEP_SIZE}))
+        .deposit(PRICE.saturating_mul(MAX_SWEEP_SIZE as u128))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    assert!(outcome.receipt_failures().is_empty(), "{:#?}", outcome.receipt_failures());
+
+    for token_id in &token_ids {
+        assert_eq!(owner_of(&setup, token_id).await?, setup.carol.id().as_str());
+    }
+    let settled = outcome.logs().iter().filter(|log| log.contains("\"event\":\"sale_settled\"")).count();
+    assert_eq!(settled, MAX_SWEEP_SIZE as usize);
+
+    // Each receiver is paid its 1% of both sales, after the marketplace fee
+    let royalty_gain = receivers[0].view_account().await?.balance.as_yoctonear() - receiver_before.as_yoctonear();
+    let proceeds = PRICE.as_yoctonear() - PRICE.as_yoctonear() * 250 / 10_000;
+    assert_eq!(royalty_gain, proceeds / 100 * MAX_SWEEP_SIZE as u128);
+
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use near_workspaces::types::NearToken;
+use serde_json::{json, Value};
+
+const START_PRICE: NearToken = NearToken::from_near(4);
+const END_PRICE: NearToken = NearToken::from_near(2);
+const TOKEN_ID: &str = "voice-1";
+const STORAGE_DEPOSIT: NearToken = NearToken::from_millinear(10);
+const AUCTION_DURATION: u64 = 60 * 1_000_000_000; // 60 seconds in nanoseconds
+
+#[tokio::test]
+async fn test_dutch_auction_price_decays_and_first_buyer_wins() -> Result<(), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+    let carol = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = nft
+        .call("nft_mint")
+        .args_json(json!({"token_id": TOKEN_ID, "receiver_id": alice.id(), "metadata": {"title": "Recording voice-1"}}))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Storage for the listing, refunded when it closes
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let now = sandbox.view_block().await?.timestamp();
+    let msg = json!({
+        "type": "dutch_auction",
+        "start_price": START_PRICE.as_yoctonear().to_string(),
+        "end_price": END_PRICE.as_yoctonear().to_string(),
+        "start_time": now.to_string(),
+        "end_time": (now + AUCTION_DURATION).to_string(),
+    });
+    let outcome = alice
+        .call(nft.id(), "nft_approve")
+        .args_json(json!({"token_id": TOKEN_ID, "account_id": marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let listing: Value = marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(listing["listing_type"], "dutch_auction");
+
+    let first_price: String = marketplace.view("current_price").args_json(json!({"auction_id": "1"})).await?.json()?;
+    let first_price: u128 = first_price.parse()?;
+    assert!(first_price <= START_PRICE.as_yoctonear() && first_price >= END_PRICE.as_yoctonear());
+
+    sandbox.fast_forward(20).await?;
+
+    let later_price: String = marketplace.view("current_price").args_json(json!({"auction_id": "1"})).await?.json()?;
+    let later_price: u128 = later_price.parse()?;
+    assert!(later_price < first_price, "The price must fall over time");
+    assert!(later_price >= END_PRICE.as_yoctonear());
+
+    // Bids are for English auctions only
+    let outcome = carol
+        .call(marketplace.id(), "place_bid")
+        .args_json(json!({"auction_id": "1"}))
+        .deposit(START_PRICE)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure());
+
+    let alice_before = alice.view_account().await?.balance;
+
+    let outcome = bob
+        .call(marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": "1"}))
+        .deposit(NearToken::from_yoctonear(later_price))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let token: Value = nft.view("nft_token").args_json(json!({"token_id": TOKEN_ID})).await?.json()?;
+    assert_eq!(token["owner_id"], bob.id().as_str());
+
+    let listing: Value = marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(listing["status"], "sold");
+
+    // The seller got the price at the time of purchase, at most what bob sent, minus the fee
+    let alice_gain = alice.view_account().await?.balance.as_yoctonear() - alice_before.as_yoctonear() - STORAGE_DEPOSIT.as_yoctonear();
+    assert!(alice_gain <= later_price * 9750 / 10_000);
+    assert!(alice_gain >= END_PRICE.as_yoctonear() * 9750 / 10_000);
+
+    // Only the first buyer wins
+    let outcome = carol
+        .call(marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": "1"}))
+        .deposit(START_PRICE)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dutch_auction_over_days() -> Result<(), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = nft
+        .call("nft_mint")
+        .args_json(json!({"token_id": TOKEN_ID, "receiver_id": alice.id(), "metadata": {"title": "Recording voice-1"}}))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // A 10 NEAR drop times the nanoseconds of a day is past u128::MAX
+    let start_price = NearToken::from_near(20).as_yoctonear();
+    let end_price = NearToken::from_near(10).as_yoctonear();
+    let day = 24 * 60 * 60 * 1_000_000_000;
+    let now = sandbox.view_block().await?.timestamp();
+    let msg = json!({
+        "type": "dutch_auction",
+        "start_price": start_price.to_string(),
+        "end_price": end_price.to_string(),
+        "start_time": now.to_string(),
+        "end_time": (now + 3 * day).to_string(),
+    });
+    let outcome = alice
+        .call(nft.id(), "nft_approve")
+        .args_json(json!({"token_id": TOKEN_ID, "account_id": marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    sandbox.fast_forward(24 * 60 * 60).await?;
+
+    let price: String = marketplace.view("current_price").args_json(json!({"auction_id": "1"})).await?.json()?;
+    let price: u128 = price.parse()?;
+    assert!(price < start_price && price > end_price, "The price falls over the days of the auction");
+
+    let listings: Vec<Value> = marketplace.view("get_listings_by_price").args_json(json!({})).await?.json()?;
+    assert_eq!(listings.len(), 1);
+
+    let outcome = bob
+        .call(marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": "1"}))
+        .deposit(NearToken::from_yoctonear(price))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let token: Value = nft.view("nft_token").args_json(json!({"token_id": TOKEN_ID})).await?.json()?;
+    assert_eq!(token["owner_id"], bob.id().as_str());
+
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use near_workspaces::types::NearToken;
+use serde_json::{json, Value};
+
+const PRICE: NearToken = NearToken::from_near(2);
+const STORAGE_DEPOSIT: NearToken = NearToken::from_millinear(10);
+
+#[tokio::test]
+async fn test_tiered_fees_are_swept_to_the_dao() -> Result<(), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+    let dao_wasm = near_workspaces::compile_project("../dao").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let dao = sandbox.dev_deploy(&dao_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let governance = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = dao.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // The owner hands fee governance over and routes fees to the DAO treasury
+    let outcome = owner.call(marketplace.id(), "set_treasury").args_json(json!({"treasury_id": dao.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = owner.call(marketplace.id(), "set_governance").args_json(json!({"governance_id": governance.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // 3% until a seller sold for PRICE, 1% after
+    let schedule = json!({"base_fee_bps": 300, "tiers": [{"min_volume": PRICE.as_yoctonear().to_string(), "fee_bps": 100}]});
+    let outcome = owner.call(marketplace.id(), "set_fee_schedule").args_json(schedule.clone()).transact().await?;
+    assert!(outcome.is_failure(), "Only governance can change fees");
+    let outcome = governance.call(marketplace.id(), "set_fee_schedule").args_json(schedule).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Storage for both listings
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT.saturating_mul(2))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let mut total_fees = 0;
+    for (token_id, fee_bps) in [("voice-1", 300), ("voice-2", 100)] {
+        let fee_now: u32 = marketplace.view("get_fee_bps").args_json(json!({"seller_id": alice.id()})).await?.json()?;
+        assert_eq!(fee_now, fee_bps);
+
+        let outcome = nft
+            .call("nft_mint")
+            .args_json(json!({"token_id": token_id, "receiver_id": alice.id(), "metadata": {"title": "Recording"}}))
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+        let msg = json!({"type": "sale", "price": PRICE.as_yoctonear().to_string()});
+        let outcome = alice
+            .call(nft.id(), "nft_approve")
+            .args_json(json!({"token_id": token_id, "account_id": marketplace.id(), "msg": msg.to_string()}))
+            .deposit(NearToken::from_yoctonear(1))
+            .max_gas()
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+        let listing: Value = marketplace
+            .view("get_listing_by_token")
+            .args_json(json!({"nft_contract_id": nft.id(), "token_id": token_id}))
+            .await?
+            .json()?;
+        let outcome = bob
+            .call(marketplace.id(), "buy_item")
+            .args_json(json!({"listing_id": listing["id"]}))
+            .deposit(PRICE)
+            .max_gas()
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+        total_fees += PRICE.as_yoctonear() * fee_bps as u128 / 10_000;
+    }
+
+    let accrued: String = marketplace.view("get_accrued_fees").args_json(json!({"ft_token_id": null})).await?.json()?;
+    assert_eq!(accrued, total_fees.to_string());
+
+    let outcome = bob.call(marketplace.id(), "sweep_fees").args_json(json!({"ft_token_id": null})).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let treasury: String = dao.view("get_treasury_balance").await?.json()?;
+    assert_eq!(treasury, total_fees.to_string());
+    let accrued: String = marketplace.view("get_accrued_fees").args_json(json!({"ft_token_id": null})).await?.json()?;
+    assert_eq!(accrued, "0");
+
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use near_workspaces::types::NearToken;
+use serde_json::{json, Value};
+
+const PRICE: u128 = 500 * 10u128.pow(24); // 500 VOICE
+const TOKEN_ID: &str = "voice-1";
+const STORAGE_DEPOSIT: NearToken = NearToken::from_millinear(10);
+
+#[tokio::test]
+async fn test_buy_with_voice_tokens() -> Result<(), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+    let ft_wasm = near_workspaces::compile_project("../voice_token").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let ft = sandbox.dev_deploy(&ft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = ft.call("new").args_json(json!({"owner_id": ft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = ft
+        .call("ft_transfer")
+        .args_json(json!({"receiver_id": bob.id(), "amount": (2 * PRICE).to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = nft
+        .call("nft_mint")
+        .args_json(json!({"token_id": TOKEN_ID, "receiver_id": alice.id(), "metadata": {"title": "Recording voice-1"}}))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Storage for the listing, refunded when it closes
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Listing in a token the marketplace does not accept fails
+    let msg = json!({"type": "sale", "price": PRICE.to_string(), "ft_token_id": ft.id()});
+    let outcome = alice
+        .call(nft.id(), "nft_approve")
+        .args_json(json!({"token_id": TOKEN_ID, "account_id": marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    assert!(!outcome.receipt_failures().is_empty(), "Listing in a token that is not accepted must fail");
+
+    let outcome = owner.call(marketplace.id(), "add_accepted_ft_token").args_json(json!({"ft_token_id": ft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = alice
+        .call(nft.id(), "nft_approve")
+        .args_json(json!({"token_id": TOKEN_ID, "account_id": marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let listing: Value = marketplace
+        .view("get_listing_by_token")
+        .args_json(json!({"nft_contract_id": nft.id(), "token_id": TOKEN_ID}))
+        .await?
+        .json()?;
+    assert_eq!(listing["ft_token_id"], ft.id().as_str());
+    let listing_id = listing["id"].as_str().unwrap().to_string();
+
+    // NEAR is not accepted for a listing priced in VOICE
+    let outcome = bob
+        .call(marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": listing_id}))
+        .deposit(NearToken::from_near(2))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure());
+
+    // Listings already priced in VOICE can still be bought once it is no longer accepted
+    let outcome = owner.call(marketplace.id(), "remove_accepted_ft_token").args_json(json!({"ft_token_id": ft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let transfer_args = json!({
+        "receiver_id": marketplace.id(),
+        "amount": PRICE.to_string(),
+        "msg": json!({"listing_id": listing_id}).to_string(),
+    });
+    let outcome = bob.call(ft.id(), "ft_transfer_call").args_json(&transfer_args).max_gas().transact().await?;
+    assert!(outcome.is_failure(), "The transfer requires 1 yoctoNEAR");
+
+    // Bob pays more than the price, the rest is refunded by the token contract
+    let outcome = bob
+        .call(ft.id(), "ft_transfer_call")
+        .args_json(json!({
+            "receiver_id": marketplace.id(),
+            "amount": (PRICE + PRICE / 2).to_string(),
+            "msg": json!({"listing_id": listing_id}).to_string(),
+        }))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let used: String = outcome.json()?;
+    assert_eq!(used, PRICE.to_string());
+
+    let token: Value = nft.view("nft_token").args_json(json!({"token_id": TOKEN_ID})).await?.json()?;
+    assert_eq!(token["owner_id"], bob.id().as_str());
+
+    let balance = |account_id: near_workspaces::AccountId| {
+        let ft = ft.clone();
+        async move {
+            let balance: String = ft.view("ft_balance_of").args_json(json!({"account_id": account_id})).await?.json()?;
+            Ok::<u128, Box<dyn std::error::Error>>(balance.parse()?)
+        }
+    };
+
+    let fee = PRICE * 250 / 10_000;
+    assert_eq!(balance(bob.id().clone()).await?, PRICE);
+    assert_eq!(balance(alice.id().clone()).await?, PRICE - fee);
+    assert_eq!(balance(marketplace.id().clone()).await?, fee);
+
+    // The fee is accrued in VOICE until it is swept to the treasury, the owner by default
+    let accrued: String = marketplace.view("get_accrued_fees").args_json(json!({"ft_token_id": ft.id()})).await?.json()?;
+    assert_eq!(accrued, fee.to_string());
+    let outcome = bob.call(marketplace.id(), "sweep_fees").args_json(json!({"ft_token_id": ft.id()})).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    assert_eq!(balance(owner.id().clone()).await?, fee);
+    assert_eq!(balance(marketplace.id().clone()).await?, 0);
+
+    Ok(())
+}
@@ -0,0 +1,324 @@
+use near_workspaces::{network::Sandbox, types::NearToken, Account, Contract, Worker};
+use serde_json::{json, Value};
+
+const PRICE: NearToken = NearToken::from_near(2);
+const TOKEN_ID: &str = "voice-1";
+const STORAGE_DEPOSIT: NearToken = NearToken::from_millinear(10);
+
+struct Setup {
+    marketplace: Contract,
+    nft: Contract,
+    alice: Account,
+    bob: Account,
+}
+
+async fn setup() -> Result<(Worker<Sandbox>, Setup), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = nft
+        .call("nft_mint")
+        .args_json(json!({
+            "token_id": TOKEN_ID,
+            "receiver_id": alice.id(),
+            "metadata": {"title": "Recording voice-1", "language": "en", "voice_type": "narration"},
+        }))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Storage for the listing, refunded when it closes
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    Ok((sandbox, Setup { marketplace, nft, alice, bob }))
+}
+
+async fn list(setup: &Setup, msg: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let outcome = setup
+        .alice
+        .call(setup.nft.id(), "nft_approve")
+        .args_json(json!({"token_id": TOKEN_ID, "account_id": setup.marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    Ok(())
+}
+
+async fn listing_by_token(setup: &Setup) -> Result<Value, Box<dyn std::error::Error>> {
+    Ok(setup
+        .marketplace
+        .view("get_listing_by_token")
+        .args_json(json!({"nft_contract_id": setup.nft.id(), "token_id": TOKEN_ID}))
+        .await?
+        .json()?)
+}
+
+async fn token_owner(setup: &Setup) -> Result<String, Box<dyn std::error::Error>> {
+    let token: Value = setup.nft.view("nft_token").args_json(json!({"token_id": TOKEN_ID})).await?.json()?;
+    Ok(token["owner_id"].as_str().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn test_list_and_buy() -> Result<(), Box<dyn std::error::Error>> {
+    let (_sandbox, setup) = setup().await?;
+
+    list(&setup, json!({"type": "sale", "price": PRICE.as_yoctonear().to_string()})).await?;
+
+    let listing = listing_by_token(&setup).await?;
+    assert_eq!(listing["status"], "active");
+    assert_eq!(listing["listing_type"], "sale");
+    assert_eq!(listing["seller_id"], setup.alice.id().as_str());
+    let listing_id = listing["id"].as_str().unwrap().to_string();
+
+    let alice_before = setup.alice.view_account().await?.balance;
+
+    let outcome = setup
+        .bob
+        .call(setup.marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": listing_id}))
+        .deposit(PRICE)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let unused: String = outcome.json()?;
+    assert_eq!(unused, "0");
+
+    assert_eq!(token_owner(&setup).await?, setup.bob.id().as_str());
+
+    let listing: Value = setup.marketplace.view("get_listing").args_json(json!({"listing_id": listing_id})).await?.json()?;
+    assert_eq!(listing["status"], "sold");
+    assert_eq!(listing["buyer_id"], setup.bob.id().as_str());
+    assert!(listing_by_token(&setup).await?.is_null());
+
+    let bought: Vec<String> = setup.marketplace.view("get_listings_by_buyer").args_json(json!({"buyer_id": setup.bob.id()})).await?.json()?;
+    assert_eq!(bought, vec![listing_id]);
+
+    // The seller receives the price minus the 2.5% marketplace fee, and the storage deposit back
+    let alice_after = setup.alice.view_account().await?.balance;
+    let fee = PRICE.as_yoctonear() * 250 / 10_000;
+    assert_eq!(
+        alice_after.as_yoctonear() - alice_before.as_yoctonear(),
+        PRICE.as_yoctonear() - fee + STORAGE_DEPOSIT.as_yoctonear()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_purchase_of_revoked_listing_refunds_buyer() -> Result<(), Box<dyn std::error::Error>> {
+    let (_sandbox, setup) = setup().await?;
+
+    list(&setup, json!({"type": "sale", "price": PRICE.as_yoctonear().to_string()})).await?;
+    let listing_id = listing_by_token(&setup).await?["id"].as_str().unwrap().to_string();
+
+    let outcome = setup
+        .alice
+        .call(setup.nft.id(), "nft_revoke")
+        .args_json(json!({"token_id": TOKEN_ID, "account_id": setup.marketplace.id()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let bob_before = setup.bob.view_account().await?.balance;
+
+    let outcome = setup
+        .bob
+        .call(setup.marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": listing_id}))
+        .deposit(PRICE)
+        .max_gas()
+        .transact()
+        .await?;
+    let unused: String = outcome.json()?;
+    assert_eq!(unused, PRICE.as_yoctonear().to_string());
+
+    assert_eq!(token_owner(&setup).await?, setup.alice.id().as_str());
+
+    let listing: Value = setup.marketplace.view("get_listing").args_json(json!({"listing_id": listing_id})).await?.json()?;
+    assert_eq!(listing["status"], "cancelled");
+    assert!(listing["buyer_id"].is_null());
+
+    // Only gas is spent, the price comes back
+    let bob_after = setup.bob.view_account().await?.balance;
+    assert!(bob_before.as_yoctonear() - bob_after.as_yoctonear() < NearToken::from_millinear(100).as_yoctonear());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_relisting_replaces_active_listing() -> Result<(), Box<dyn std::error::Error>> {
+    let (_sandbox, setup) = setup().await?;
+
+    list(&setup, json!({"type": "sale", "price": PRICE.as_yoctonear().to_string()})).await?;
+    let first_id = listing_by_token(&setup).await?["id"].as_str().unwrap().to_string();
+
+    let new_price = NearToken::from_near(3);
+    list(&setup, json!({"type": "sale", "price": new_price.as_yoctonear().to_string()})).await?;
+    let listing = listing_by_token(&setup).await?;
+    assert_ne!(listing["id"].as_str().unwrap(), first_id);
+    assert_eq!(listing["price"], new_price.as_yoctonear().to_string());
+
+    let first: Value = setup.marketplace.view("get_listing").args_json(json!({"listing_id": first_id})).await?.json()?;
+    assert_eq!(first["status"], "cancelled");
+
+    let listings: Vec<String> = setup.marketplace.view("get_listings_by_seller").args_json(json!({"seller_id": setup.alice.id()})).await?.json()?;
+    assert_eq!(listings.len(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_update_price_and_cancel() -> Result<(), Box<dyn std::error::Error>> {
+    let (_sandbox, setup) = setup().await?;
+
+    list(&setup, json!({"type": "sale", "price": PRICE.as_yoctonear().to_string()})).await?;
+    let listing = listing_by_token(&setup).await?;
+    let listing_id = listing["id"].as_str().unwrap().to_string();
+    assert_eq!(listing["storage_deposit"], STORAGE_DEPOSIT.as_yoctonear().to_string());
+
+    let new_price = NearToken::from_near(3);
+    let outcome = setup
+        .bob
+        .call(setup.marketplace.id(), "update_price")
+        .args_json(json!({"listing_id": listing_id, "price": new_price.as_yoctonear().to_string()}))
+        .transact()
+        .await?;
+    assert!(outcome.is_failure(), "Only the seller can update the price");
+
+    let outcome = setup
+        .alice
+        .call(setup.marketplace.id(), "update_price")
+        .args_json(json!({"listing_id": listing_id, "price": new_price.as_yoctonear().to_string()}))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    assert_eq!(listing_by_token(&setup).await?["price"], new_price.as_yoctonear().to_string());
+
+    // The old price no longer buys the token
+    let outcome = setup
+        .bob
+        .call(setup.marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": listing_id}))
+        .deposit(PRICE)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure());
+
+    let alice_before = setup.alice.view_account().await?.balance;
+    let outcome = setup.alice.call(setup.marketplace.id(), "cancel_listing").args_json(json!({"listing_id": listing_id})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let listing: Value = setup.marketplace.view("get_listing").args_json(json!({"listing_id": listing_id})).await?.json()?;
+    assert_eq!(listing["status"], "cancelled");
+    assert!(listing_by_token(&setup).await?.is_null());
+
+    // The storage deposit came back, less the gas of the call
+    let alice_after = setup.alice.view_account().await?.balance;
+    assert!(alice_after.as_yoctonear() + NearToken::from_millinear(5).as_yoctonear() > alice_before.as_yoctonear() + STORAGE_DEPOSIT.as_yoctonear());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_purge_expired_listings() -> Result<(), Box<dyn std::error::Error>> {
+    let (sandbox, setup) = setup().await?;
+
+    let now = sandbox.view_block().await?.timestamp();
+    list(
+        &setup,
+        json!({
+            "type": "dutch_auction",
+            "start_price": NearToken::from_near(4).as_yoctonear().to_string(),
+            "end_price": PRICE.as_yoctonear().to_string(),
+            "start_time": now.to_string(),
+            "end_time": (now + 30 * 1_000_000_000).to_string(),
+        }),
+    )
+    .await?;
+    let listing_id = listing_by_token(&setup).await?["id"].as_str().unwrap().to_string();
+
+    let purged: u32 = setup.bob.call(setup.marketplace.id(), "purge_expired").args_json(json!({"limit": 10})).transact().await?.json()?;
+    assert_eq!(purged, 0);
+
+    sandbox.fast_forward(100).await?;
+
+    // Only the window of the index from `from_index` on is looked at
+    let purged: u32 = setup
+        .bob
+        .call(setup.marketplace.id(), "purge_expired")
+        .args_json(json!({"from_index": "1", "limit": 10}))
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(purged, 0);
+
+    let alice_before = setup.alice.view_account().await?.balance;
+    let purged: u32 = setup.bob.call(setup.marketplace.id(), "purge_expired").args_json(json!({"limit": 10})).transact().await?.json()?;
+    assert_eq!(purged, 1);
+
+    let listing: Option<Value> = setup.marketplace.view("get_listing").args_json(json!({"listing_id": listing_id})).await?.json()?;
+    assert!(listing.is_none());
+    assert!(listing_by_token(&setup).await?.is_null());
+    let listings: Vec<String> = setup.marketplace.view("get_listings_by_seller").args_json(json!({"seller_id": setup.alice.id()})).await?.json()?;
+    assert!(listings.is_empty());
+
+    let alice_after = setup.alice.view_account().await?.balance;
+    assert_eq!(alice_after.as_yoctonear() - alice_before.as_yoctonear(), STORAGE_DEPOSIT.as_yoctonear());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_overpayment_is_refunded() -> Result<(), Box<dyn std::error::Error>> {
+    let (_sandbox, setup) = setup().await?;
+
+    list(&setup, json!({"type": "sale", "price": PRICE.as_yoctonear().to_string()})).await?;
+    let listing_id = listing_by_token(&setup).await?["id"].as_str().unwrap().to_string();
+
+    let bob_before = setup.bob.view_account().await?.balance;
+    let surplus = NearToken::from_near(1);
+
+    let outcome = setup
+        .bob
+        .call(setup.marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": listing_id}))
+        .deposit(PRICE.saturating_add(surplus))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let unused: String = outcome.json()?;
+    assert_eq!(unused, surplus.as_yoctonear().to_string());
+
+    assert_eq!(token_owner(&setup).await?, setup.bob.id().as_str());
+
+    // Bob paid the price and gas, nothing more
+    let bob_after = setup.bob.view_account().await?.balance;
+    let spent = bob_before.as_yoctonear() - bob_after.as_yoctonear();
+    assert!(spent < PRICE.as_yoctonear() + NearToken::from_millinear(100).as_yoctonear());
+
+    Ok(())
+}
@@ -0,0 +1,350 @@
+use near_workspaces::{operations::Function, types::NearToken};
+use serde_json::{json, Value};
+
+// Release build of the originally deployed contract, with separate sale and auction maps
+const V1_WASM_PATH: &str = "tests/res/marketplace_v1.wasm";
+// Release build of the first contract with a single listing map and escrowed bids
+const V2_WASM_PATH: &str = "tests/res/marketplace_v2.wasm";
+// Release build of the first contract with listing currencies, expiry and storage deposits
+const V3_WASM_PATH: &str = "tests/res/marketplace_v3.wasm";
+// Upper bound on what a transaction costs in gas, to tell refunds apart from fees
+const MAX_TX_COST: NearToken = NearToken::from_millinear(100);
+
+#[tokio::test]
+async fn test_migrate_v1_state() -> Result<(), Box<dyn std::error::Error>> {
+    let v1_wasm = std::fs::read(V1_WASM_PATH)?;
+    let contract_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&v1_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+    let price = NearToken::from_near(2);
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": marketplace.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Populate the V1 state. Every item of V1 is written under ID 1, so
+    // Bob's sale overwrites Alice's, and the auction takes the same ID
+    for seller in [&alice, &bob] {
+        let outcome = seller
+            .call(marketplace.id(), "create_sale")
+            .args_json(json!({"price": price.as_yoctonear().to_string()}))
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+        assert_eq!(outcome.json::<String>()?, "1");
+    }
+
+    let now = sandbox.view_block().await?.timestamp();
+    let outcome = alice
+        .call(marketplace.id(), "create_auction")
+        .args_json(json!({
+            "start_price": price.as_yoctonear().to_string(),
+            "end_price": price.as_yoctonear().to_string(),
+            "start_time": now.to_string(),
+            "end_time": (now + 60 * 60 * 1_000_000_000).to_string(),
+        }))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let sale: Value = marketplace.view("get_sale").args_json(json!({"sale_id": "1"})).await?.json()?;
+    assert_eq!(sale["seller_id"], bob.id().as_str());
+
+    // Upgrade and migrate in one batch so the new code never runs on the old layout
+    let outcome = marketplace
+        .batch()
+        .deploy(&contract_wasm)
+        .call(Function::new("migrate").max_gas())
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let version: String = marketplace.view("get_state_version").args_json(json!({})).await?.json()?;
+    assert_eq!(version, "V5");
+
+    // V1 items had no token, nothing is listed and the legacy ID is not reused
+    let total: String = marketplace.view("get_total_listings").args_json(json!({})).await?.json()?;
+    assert_eq!(total, "1");
+    let listing: Option<Value> = marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert!(listing.is_none());
+    let listings: Vec<String> = marketplace.view("get_listings_by_seller").args_json(json!({"seller_id": bob.id()})).await?.json()?;
+    assert!(listings.is_empty());
+
+    // The migrated marketplace lists and sells tokens
+    let outcome = nft
+        .call("nft_mint")
+        .args_json(json!({"token_id": "voice-1", "receiver_id": alice.id(), "metadata": {"title": "Recording voice-1"}}))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(NearToken::from_millinear(20))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = alice
+        .call(nft.id(), "nft_approve")
+        .args_json(json!({
+            "token_id": "voice-1",
+            "account_id": marketplace.id(),
+            "msg": json!({"type": "sale", "price": price.as_yoctonear().to_string()}).to_string(),
+        }))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let listing: Value = marketplace
+        .view("get_listing_by_token")
+        .args_json(json!({"nft_contract_id": nft.id(), "token_id": "voice-1"}))
+        .await?
+        .json()?;
+    assert_eq!(listing["id"], "2");
+
+    let outcome = bob
+        .call(marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": "2"}))
+        .deposit(price)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let token: Value = nft.view("nft_token").args_json(json!({"token_id": "voice-1"})).await?.json()?;
+    assert_eq!(token["owner_id"], bob.id().as_str());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_migrate_v2_state() -> Result<(), Box<dyn std::error::Error>> {
+    let v2_wasm = std::fs::read(V2_WASM_PATH)?;
+    let contract_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&v2_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+    let carol = sandbox.dev_create_account().await?;
+    let price = NearToken::from_near(2);
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": marketplace.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Populate the V2 state with a sale and an auction holding an escrowed bid
+    let now = sandbox.view_block().await?.timestamp();
+    let end_time = now + 60 * 60 * 1_000_000_000;
+    let listings = [
+        ("voice-1", json!({"type": "sale", "price": price.as_yoctonear().to_string()})),
+        (
+            "voice-2",
+            json!({
+                "type": "auction",
+                "start_price": price.as_yoctonear().to_string(),
+                "end_price": price.as_yoctonear().to_string(),
+                "start_time": now.to_string(),
+                "end_time": end_time.to_string(),
+            }),
+        ),
+    ];
+    for (token_id, msg) in listings {
+        let outcome = nft
+            .call("nft_mint")
+            .args_json(json!({"token_id": token_id, "receiver_id": alice.id(), "metadata": {"title": format!("Recording {}", token_id)}}))
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+        let outcome = alice
+            .call(nft.id(), "nft_approve")
+            .args_json(json!({"token_id": token_id, "account_id": marketplace.id(), "msg": msg.to_string()}))
+            .deposit(NearToken::from_yoctonear(1))
+            .max_gas()
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    }
+
+    let outcome = carol
+        .call(marketplace.id(), "place_bid")
+        .args_json(json!({"auction_id": "2"}))
+        .deposit(NearToken::from_near(3))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let balance_before = marketplace.view_account().await?.balance;
+
+    let outcome = marketplace
+        .batch()
+        .deploy(&contract_wasm)
+        .call(Function::new("migrate").max_gas())
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let version: String = marketplace.view("get_state_version").args_json(json!({})).await?.json()?;
+    assert_eq!(version, "V5");
+    assert!(marketplace.view_account().await?.balance >= balance_before);
+
+    let sale: Value = marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(sale["status"], "active");
+    assert_eq!(sale["price"], price.as_yoctonear().to_string());
+    assert_eq!(sale["ft_token_id"], Value::Null);
+
+    let auction: Value = marketplace.view("get_listing").args_json(json!({"listing_id": "2"})).await?.json()?;
+    assert_eq!(auction["highest_bidder_id"], carol.id().as_str());
+    assert_eq!(auction["highest_bid"], NearToken::from_near(3).as_yoctonear().to_string());
+    assert_eq!(auction["expires_at"], end_time.to_string());
+
+    let active: Vec<Value> = marketplace.view("get_listings").args_json(json!({"filter": {"status": "active"}})).await?.json()?;
+    assert_eq!(active.len(), 2);
+
+    // Outbidding refunds Carol's escrowed bid
+    let carol_before = carol.view_account().await?.balance;
+    let outcome = bob
+        .call(marketplace.id(), "place_bid")
+        .args_json(json!({"auction_id": "2"}))
+        .deposit(NearToken::from_near(4))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let carol_after = carol.view_account().await?.balance;
+    assert_eq!(carol_after.as_yoctonear() - carol_before.as_yoctonear(), NearToken::from_near(3).as_yoctonear());
+
+    // The migrated sale can be bought
+    let bob_before = bob.view_account().await?.balance;
+    let outcome = bob
+        .call(marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": "1"}))
+        .deposit(price)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let token: Value = nft.view("nft_token").args_json(json!({"token_id": "voice-1"})).await?.json()?;
+    assert_eq!(token["owner_id"], bob.id().as_str());
+    let bob_after = bob.view_account().await?.balance;
+    assert!(bob_before.as_yoctonear() - bob_after.as_yoctonear() < price.as_yoctonear() + MAX_TX_COST.as_yoctonear());
+
+    // Upgrading the migrated contract keeps the state as it is
+    let outcome = marketplace
+        .call("upgrade")
+        .args_borsh(contract_wasm.clone())
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    assert!(outcome.logs().iter().any(|log| log.starts_with("State is already at version")));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_migrate_v3_state() -> Result<(), Box<dyn std::error::Error>> {
+    let v3_wasm = std::fs::read(V3_WASM_PATH)?;
+    let contract_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&v3_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": marketplace.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(NearToken::from_millinear(30))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Populate the V3 state with three sales, the most expensive one sold
+    let prices = [("voice-1", 3), ("voice-2", 2), ("voice-3", 4)];
+    for (token_id, price) in prices {
+        let outcome = nft
+            .call("nft_mint")
+            .args_json(json!({"token_id": token_id, "receiver_id": alice.id(), "metadata": {"title": format!("Recording {}", token_id)}}))
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+        let msg = json!({"type": "sale", "price": NearToken::from_near(price).as_yoctonear().to_string()});
+        let outcome = alice
+            .call(nft.id(), "nft_approve")
+            .args_json(json!({"token_id": token_id, "account_id": marketplace.id(), "msg": msg.to_string()}))
+            .deposit(NearToken::from_yoctonear(1))
+            .max_gas()
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    }
+
+    let outcome = bob
+        .call(marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": "3"}))
+        .deposit(NearToken::from_near(4))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = marketplace
+        .batch()
+        .deploy(&contract_wasm)
+        .call(Function::new("migrate").max_gas())
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let version: String = marketplace.view("get_state_version").args_json(json!({})).await?.json()?;
+    assert_eq!(version, "V5");
+
+    // The migrated listings are indexed by price and by status
+    let by_price: Vec<Value> = marketplace.view("get_listings_by_price").args_json(json!({})).await?.json()?;
+    let ids: Vec<&str> = by_price.iter().map(|listing| listing["id"].as_str().unwrap()).collect();
+    assert_eq!(ids, ["2", "1"]);
+
+    let sold: Vec<Value> = marketplace.view("get_listings").args_json(json!({"filter": {"status": "sold"}})).await?.json()?;
+    assert_eq!(sold.len(), 1);
+    assert_eq!(sold[0]["id"], "3");
+
+    // A migrated sale leaves the index once bought
+    let outcome = bob
+        .call(marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": "2"}))
+        .deposit(NearToken::from_near(2))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let by_price: Vec<Value> = marketplace.view("get_listings_by_price").args_json(json!({})).await?.json()?;
+    assert_eq!(by_price.len(), 1);
+    assert_eq!(by_price[0]["id"], "1");
+
+    Ok(())
+}
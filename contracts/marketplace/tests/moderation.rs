@@ -0,0 +1,169 @@
+use near_workspaces::{types::NearToken, Account, Contract};
+use serde_json::{json, Value};
+
+const PRICE: NearToken = NearToken::from_near(2);
+const STORAGE_DEPOSIT: NearToken = NearToken::from_millinear(20);
+
+struct Setup {
+    marketplace: Contract,
+    nft: Contract,
+    owner: Account,
+    moderator: Account,
+    alice: Account,
+    bob: Account,
+    carol: Account,
+}
+
+/// Deploys both contracts, makes an account moderator and lists two of
+/// Alice's tokens for sale
+async fn setup() -> Result<Setup, Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let moderator = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+    let carol = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = owner.call(marketplace.id(), "add_moderator").args_json(json!({"account_id": moderator.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    for token_id in ["voice-1", "voice-2"] {
+        let outcome = nft
+            .call("nft_mint")
+            .args_json(json!({
+                "token_id": token_id,
+                "receiver_id": alice.id(),
+                "metadata": {"title": format!("Recording {}", token_id)},
+            }))
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+        let msg = json!({"type": "sale", "price": PRICE.as_yoctonear().to_string()});
+        let outcome = alice
+            .call(nft.id(), "nft_approve")
+            .args_json(json!({"token_id": token_id, "account_id": marketplace.id(), "msg": msg.to_string()}))
+            .deposit(NearToken::from_yoctonear(1))
+            .max_gas()
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    }
+
+    Ok(Setup { marketplace, nft, owner, moderator, alice, bob, carol })
+}
+
+async fn buy(setup: &Setup, buyer: &Account, listing_id: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    let outcome = buyer
+        .call(setup.marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": listing_id}))
+        .deposit(PRICE)
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(outcome.is_success())
+}
+
+async fn moderate(setup: &Setup, method: &str, args: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let outcome = setup.moderator.call(setup.marketplace.id(), method).args_json(args).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pause_freeze_and_blocklist_stop_trading() -> Result<(), Box<dyn std::error::Error>> {
+    let setup = setup().await?;
+
+    let outcome = setup.bob.call(setup.marketplace.id(), "pause").transact().await?;
+    assert!(outcome.is_failure(), "Only moderators can pause the marketplace");
+
+    moderate(&setup, "pause", json!({})).await?;
+    assert!(setup.marketplace.view("is_paused").await?.json::<bool>()?);
+    assert!(!buy(&setup, &setup.bob, "1").await?, "Nothing can be bought while paused");
+    moderate(&setup, "unpause", json!({})).await?;
+
+    moderate(&setup, "freeze_listing", json!({"listing_id": "1", "reason": "Reported as stolen"})).await?;
+    let listing: Value = setup.marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(listing["frozen"], true);
+    assert!(!buy(&setup, &setup.bob, "1").await?, "A frozen listing cannot be bought");
+    moderate(&setup, "unfreeze_listing", json!({"listing_id": "1"})).await?;
+    assert!(buy(&setup, &setup.bob, "1").await?);
+
+    // A blocked buyer cannot trade, nor can anyone trade the listings of a blocked NFT contract
+    moderate(&setup, "block_account", json!({"account_id": setup.carol.id(), "reason": "Fraud"})).await?;
+    assert!(!buy(&setup, &setup.carol, "2").await?);
+    moderate(&setup, "block_account", json!({"account_id": setup.nft.id(), "reason": "Infringing collection"})).await?;
+    assert!(!buy(&setup, &setup.bob, "2").await?);
+
+    let blocklist: Vec<String> = setup.marketplace.view("get_blocklist").args_json(json!({})).await?.json()?;
+    assert_eq!(blocklist.len(), 2);
+
+    let token: Value = setup.nft.view("nft_token").args_json(json!({"token_id": "voice-2"})).await?.json()?;
+    assert_eq!(token["owner_id"], setup.alice.id().as_str());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dispute_holds_the_proceeds() -> Result<(), Box<dyn std::error::Error>> {
+    let setup = setup().await?;
+
+    let outcome = setup
+        .bob
+        .call(setup.marketplace.id(), "open_dispute")
+        .args_json(json!({"listing_id": "1", "reason": "Stolen"}))
+        .transact()
+        .await?;
+    assert!(outcome.is_failure(), "Only moderators can open disputes");
+    moderate(&setup, "open_dispute", json!({"listing_id": "1", "reason": "Recording claimed by Carol"})).await?;
+
+    // The sale goes through, the seller is not paid
+    let alice_before = setup.alice.view_account().await?.balance;
+    assert!(buy(&setup, &setup.bob, "1").await?);
+    let token: Value = setup.nft.view("nft_token").args_json(json!({"token_id": "voice-1"})).await?.json()?;
+    assert_eq!(token["owner_id"], setup.bob.id().as_str());
+    assert!(setup.alice.view_account().await?.balance <= alice_before);
+
+    let dispute: Value = setup.marketplace.view("get_dispute").args_json(json!({"listing_id": "1"})).await?.json()?;
+    let held: u128 = dispute["held"][setup.alice.id().as_str()].as_str().unwrap().parse()?;
+    assert_eq!(held, PRICE.as_yoctonear() * 975 / 1000);
+
+    let outcome = setup.alice.call(setup.marketplace.id(), "release_dispute").args_json(json!({"listing_id": "1"})).transact().await?;
+    assert!(outcome.is_failure(), "The dispute has not timed out");
+
+    // The moderator awards the proceeds to the rightful owner
+    let carol_before = setup.carol.view_account().await?.balance;
+    let outcome = setup
+        .owner
+        .call(setup.marketplace.id(), "resolve_dispute")
+        .args_json(json!({"listing_id": "1", "payee_id": setup.carol.id()}))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    assert!(outcome.logs().iter().any(|log| log.contains("\"event\":\"dispute_resolved\"")));
+    assert_eq!(setup.carol.view_account().await?.balance.as_yoctonear() - carol_before.as_yoctonear(), held);
+
+    let dispute: Option<Value> = setup.marketplace.view("get_dispute").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert!(dispute.is_none());
+
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use near_workspaces::{network::Sandbox, types::NearToken, Account, Contract, Worker};
+use serde_json::{json, Value};
+
+const TOKEN_ID: &str = "voice-1";
+const OFFER_DURATION: u64 = 60 * 1_000_000_000; // 60 seconds in nanoseconds
+// Upper bound on what a transaction costs in gas, to tell refunds apart from fees
+const MAX_TX_COST: NearToken = NearToken::from_millinear(100);
+
+struct Setup {
+    marketplace: Contract,
+    nft: Contract,
+    alice: Account,
+    bob: Account,
+    carol: Account,
+}
+
+/// Deploys both contracts and mints a token to alice, without listing it
+async fn setup() -> Result<(Worker<Sandbox>, Setup), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+    let carol = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = nft
+        .call("nft_mint")
+        .args_json(json!({"token_id": TOKEN_ID, "receiver_id": alice.id(), "metadata": {"title": "Recording voice-1"}}))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    Ok((sandbox, Setup { marketplace, nft, alice, bob, carol }))
+}
+
+async fn make_offer(sandbox: &Worker<Sandbox>, setup: &Setup, buyer: &Account, amount: NearToken) -> Result<String, Box<dyn std::error::Error>> {
+    let now = sandbox.view_block().await?.timestamp();
+    let outcome = buyer
+        .call(setup.marketplace.id(), "make_offer")
+        .args_json(json!({"nft_contract_id": setup.nft.id(), "token_id": TOKEN_ID, "expires_at": (now + OFFER_DURATION).to_string()}))
+        .deposit(amount)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    Ok(outcome.json()?)
+}
+
+/// The owner answers an offer through `nft_approve`, like listing the token
+async fn approve(setup: &Setup, msg: Value) -> Result<(), Box<dyn std::error::Error>> {
+    let outcome = setup
+        .alice
+        .call(setup.nft.id(), "nft_approve")
+        .args_json(json!({"token_id": TOKEN_ID, "account_id": setup.marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    Ok(())
+}
+
+async fn token_owner(setup: &Setup) -> Result<String, Box<dyn std::error::Error>> {
+    let token: Value = setup.nft.view("nft_token").args_json(json!({"token_id": TOKEN_ID})).await?.json()?;
+    Ok(token["owner_id"].as_str().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn test_accept_offer_on_unlisted_token() -> Result<(), Box<dyn std::error::Error>> {
+    let (sandbox, setup) = setup().await?;
+
+    let bob_before = setup.bob.view_account().await?.balance;
+    let first_id = make_offer(&sandbox, &setup, &setup.bob, NearToken::from_near(2)).await?;
+    let carol_id = make_offer(&sandbox, &setup, &setup.carol, NearToken::from_near(3)).await?;
+
+    // A second offer of bob supersedes his first one, which is refunded
+    let bob_id = make_offer(&sandbox, &setup, &setup.bob, NearToken::from_near(4)).await?;
+    let first: Option<Value> = setup.marketplace.view("get_offer").args_json(json!({"offer_id": first_id})).await?.json()?;
+    assert!(first.is_none());
+    let bob_after = setup.bob.view_account().await?.balance;
+    let spent = bob_before.as_yoctonear() - bob_after.as_yoctonear();
+    assert!(spent < NearToken::from_near(4).as_yoctonear() + MAX_TX_COST.as_yoctonear());
+
+    let offers: Vec<String> = setup
+        .marketplace
+        .view("get_offers_by_token")
+        .args_json(json!({"nft_contract_id": setup.nft.id(), "token_id": TOKEN_ID}))
+        .await?
+        .json()?;
+    assert_eq!(offers.len(), 2);
+    let page: Vec<String> = setup
+        .marketplace
+        .view("get_offers_by_token")
+        .args_json(json!({"nft_contract_id": setup.nft.id(), "token_id": TOKEN_ID, "from_index": "1", "limit": 10}))
+        .await?
+        .json()?;
+    assert_eq!(page, offers[1..]);
+    let offers: Vec<String> = setup.marketplace.view("get_offers_by_bidder").args_json(json!({"bidder_id": setup.bob.id()})).await?.json()?;
+    assert_eq!(offers, vec![bob_id.clone()]);
+
+    // Only the owner of the token can reject an offer
+    let outcome = setup.bob.call(setup.marketplace.id(), "reject_offer").args_json(json!({"offer_id": carol_id})).max_gas().transact().await?;
+    assert!(outcome.is_failure());
+    let outcome = setup.alice.call(setup.marketplace.id(), "reject_offer").args_json(json!({"offer_id": carol_id})).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let rejected: Option<Value> = setup.marketplace.view("get_offer").args_json(json!({"offer_id": carol_id})).await?.json()?;
+    assert!(rejected.is_none());
+
+    let alice_before = setup.alice.view_account().await?.balance;
+    approve(&setup, json!({"type": "accept_offer", "offer_id": bob_id})).await?;
+
+    assert_eq!(token_owner(&setup).await?, setup.bob.id().as_str());
+
+    // The sale is recorded as a sold listing and paid out like one
+    let bought: Vec<String> = setup.marketplace.view("get_listings_by_buyer").args_json(json!({"buyer_id": setup.bob.id()})).await?.json()?;
+    assert_eq!(bought.len(), 1);
+    let listing: Value = setup.marketplace.view("get_listing").args_json(json!({"listing_id": bought[0]})).await?.json()?;
+    assert_eq!(listing["status"], "sold");
+    assert_eq!(listing["price"], NearToken::from_near(4).as_yoctonear().to_string());
+
+    let alice_after = setup.alice.view_account().await?.balance;
+    let fee = NearToken::from_near(4).as_yoctonear() * 250 / 10_000;
+    let alice_gain = alice_after.as_yoctonear() + MAX_TX_COST.as_yoctonear() - alice_before.as_yoctonear();
+    assert!(alice_gain >= NearToken::from_near(4).as_yoctonear() - fee);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_counter_offer() -> Result<(), Box<dyn std::error::Error>> {
+    let (sandbox, setup) = setup().await?;
+
+    let offer_id = make_offer(&sandbox, &setup, &setup.bob, NearToken::from_near(2)).await?;
+    let counter_price = NearToken::from_near(3);
+    approve(&setup, json!({"type": "counter_offer", "offer_id": offer_id, "price": counter_price.as_yoctonear().to_string()})).await?;
+
+    let offer: Value = setup.marketplace.view("get_offer").args_json(json!({"offer_id": offer_id})).await?.json()?;
+    assert_eq!(offer["counter_price"], counter_price.as_yoctonear().to_string());
+    assert_eq!(offer["counter_seller_id"], setup.alice.id().as_str());
+
+    // The escrowed offer counts towards the counter price
+    let outcome = setup
+        .bob
+        .call(setup.marketplace.id(), "accept_counter_offer")
+        .args_json(json!({"offer_id": offer_id}))
+        .deposit(NearToken::from_millinear(999))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure());
+
+    let outcome = setup
+        .bob
+        .call(setup.marketplace.id(), "accept_counter_offer")
+        .args_json(json!({"offer_id": offer_id}))
+        .deposit(NearToken::from_near(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    assert_eq!(token_owner(&setup).await?, setup.bob.id().as_str());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_expired_offers_are_refunded() -> Result<(), Box<dyn std::error::Error>> {
+    let (sandbox, setup) = setup().await?;
+
+    let bob_before = setup.bob.view_account().await?.balance;
+    let offer_id = make_offer(&sandbox, &setup, &setup.bob, NearToken::from_near(2)).await?;
+
+    sandbox.fast_forward(200).await?;
+
+    let outcome = setup
+        .alice
+        .call(setup.nft.id(), "nft_approve")
+        .args_json(json!({
+            "token_id": TOKEN_ID,
+            "account_id": setup.marketplace.id(),
+            "msg": json!({"type": "accept_offer", "offer_id": offer_id}).to_string(),
+        }))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(!outcome.receipt_failures().is_empty(), "An expired offer cannot be accepted");
+    assert_eq!(token_owner(&setup).await?, setup.alice.id().as_str());
+
+    let refunded: u32 = setup
+        .carol
+        .call(setup.marketplace.id(), "refund_expired_offers")
+        .args_json(json!({"nft_contract_id": setup.nft.id(), "token_id": TOKEN_ID}))
+        .max_gas()
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(refunded, 1);
+
+    let bob_after = setup.bob.view_account().await?.balance;
+    assert!(bob_before.as_yoctonear() - bob_after.as_yoctonear() < MAX_TX_COST.as_yoctonear());
+
+    Ok(())
+}
@@ -0,0 +1,374 @@
+use near_workspaces::types::NearToken;
+use serde_json::{json, Value};
+
+const PRICE_PER_DAY: NearToken = NearToken::from_near(1);
+const COLLATERAL: NearToken = NearToken::from_near(3);
+const TOKEN_ID: &str = "voice-1";
+const STORAGE_DEPOSIT: NearToken = NearToken::from_millinear(10);
+const DAY_BLOCKS: u64 = 24 * 60 * 60;
+
+#[tokio::test]
+async fn test_rental_streams_rent_and_returns_collateral() -> Result<(), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let creator = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = nft
+        .call("nft_mint")
+        .args_json(json!({
+            "token_id": TOKEN_ID,
+            "receiver_id": alice.id(),
+            "metadata": {"title": "Recording voice-1"},
+            "royalty": {creator.id().to_string(): 1000},
+            "creator_id": creator.id(),
+        }))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let msg = json!({
+        "type": "rental",
+        "price_per_day": PRICE_PER_DAY.as_yoctonear().to_string(),
+        "max_days": 7,
+        "collateral": COLLATERAL.as_yoctonear().to_string(),
+    });
+    let outcome = alice
+        .call(nft.id(), "nft_approve")
+        .args_json(json!({"token_id": TOKEN_ID, "account_id": marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Longer than the listing allows
+    let outcome = bob
+        .call(marketplace.id(), "rent")
+        .args_json(json!({"listing_id": "1", "days": 8}))
+        .deposit(NearToken::from_near(20))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure(), "A rental cannot exceed the max days");
+
+    let outcome = bob
+        .call(marketplace.id(), "rent")
+        .args_json(json!({"listing_id": "1", "days": 1}))
+        .deposit(PRICE_PER_DAY.saturating_add(COLLATERAL))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let listing: Value = marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(listing["listing_type"], "rental");
+    assert_eq!(listing["status"], "active");
+    assert_eq!(listing["renter_id"], bob.id().as_str());
+
+    // The renter is the token's user, the owner keeps it but cannot move it
+    let user: Value = nft.view("nft_user_of").args_json(json!({"token_id": TOKEN_ID})).await?.json()?;
+    assert_eq!(user["user_id"], bob.id().as_str());
+    let token: Value = nft.view("nft_token").args_json(json!({"token_id": TOKEN_ID})).await?.json()?;
+    assert_eq!(token["owner_id"], alice.id().as_str());
+
+    let outcome = alice
+        .call(nft.id(), "nft_transfer")
+        .args_json(json!({"receiver_id": owner.id(), "token_id": TOKEN_ID}))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?;
+    assert!(outcome.is_failure(), "A rented token cannot be transferred");
+
+    let outcome = alice.call(marketplace.id(), "cancel_listing").args_json(json!({"listing_id": "1"})).transact().await?;
+    assert!(outcome.is_failure(), "A rented listing cannot be cancelled");
+
+    let outcome = bob.call(marketplace.id(), "end_rental").args_json(json!({"listing_id": "1"})).max_gas().transact().await?;
+    assert!(outcome.is_failure(), "The rental has not ended yet");
+
+    // Part of the rent is streamed to the seller and the creator
+    sandbox.fast_forward(100).await?;
+    let creator_before = creator.view_account().await?.balance;
+    let outcome = alice.call(marketplace.id(), "claim_rent").args_json(json!({"listing_id": "1"})).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let streamed: u128 = outcome.json::<String>()?.parse()?;
+    assert!(streamed > 0 && streamed < PRICE_PER_DAY.as_yoctonear(), "Only the rent earned so far is streamed");
+    assert!(creator.view_account().await?.balance > creator_before, "The creator earns royalties on the rent");
+
+    // Once the day is over the rest is streamed and the collateral returned
+    sandbox.fast_forward(DAY_BLOCKS).await?;
+    let creator_before = creator.view_account().await?.balance;
+    let bob_before = bob.view_account().await?.balance;
+    let outcome = alice.call(marketplace.id(), "end_rental").args_json(json!({"listing_id": "1"})).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let ended = outcome
+        .logs()
+        .iter()
+        .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+        .map(|event| serde_json::from_str::<Value>(event).unwrap())
+        .find(|event| event["event"] == "rental_ended")
+        .expect("An ended rental emits an event");
+    assert_eq!(ended["data"][0]["collateral"], COLLATERAL.as_yoctonear().to_string());
+    assert_eq!(
+        bob.view_account().await?.balance.as_yoctonear() - bob_before.as_yoctonear(),
+        COLLATERAL.as_yoctonear()
+    );
+
+    // All of the rent was paid out: 10% royalty after the 2.5% fee
+    let fee = PRICE_PER_DAY.as_yoctonear() * 250 / 10_000;
+    let royalty = (PRICE_PER_DAY.as_yoctonear() - fee) / 10;
+    let streamed_royalty = royalty * streamed / PRICE_PER_DAY.as_yoctonear();
+    let creator_gain = creator.view_account().await?.balance.as_yoctonear() - creator_before.as_yoctonear();
+    assert!(creator_gain.abs_diff(royalty - streamed_royalty) <= 1);
+
+    // The listing is open for the next renter
+    let listing: Value = marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(listing["status"], "active");
+    assert_eq!(listing["renter_id"], Value::Null);
+    let user: Value = nft.view("nft_user_of").args_json(json!({"token_id": TOKEN_ID})).await?.json()?;
+    assert_eq!(user, Value::Null);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_seller_claims_collateral() -> Result<(), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+    let carol = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = nft
+        .call("nft_mint")
+        .args_json(json!({"token_id": TOKEN_ID, "receiver_id": alice.id(), "metadata": {"title": "Recording voice-1"}}))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let msg = json!({
+        "type": "rental",
+        "price_per_day": PRICE_PER_DAY.as_yoctonear().to_string(),
+        "max_days": 7,
+        "collateral": COLLATERAL.as_yoctonear().to_string(),
+    });
+    let outcome = alice
+        .call(nft.id(), "nft_approve")
+        .args_json(json!({"token_id": TOKEN_ID, "account_id": marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = bob
+        .call(marketplace.id(), "rent")
+        .args_json(json!({"listing_id": "1", "days": 1}))
+        .deposit(PRICE_PER_DAY.saturating_add(COLLATERAL))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let reason = json!({"listing_id": "1", "reason": "Voice used without consent"});
+    let outcome = alice.call(marketplace.id(), "claim_collateral").args_json(&reason).max_gas().transact().await?;
+    assert!(outcome.is_failure(), "The rental has not ended yet");
+
+    sandbox.fast_forward(DAY_BLOCKS + 100).await?;
+
+    // The renter cannot take the collateral back while the seller can claim it
+    let outcome = bob.call(marketplace.id(), "end_rental").args_json(json!({"listing_id": "1"})).max_gas().transact().await?;
+    assert!(outcome.is_failure(), "The seller can still claim the collateral");
+    let outcome = bob.call(marketplace.id(), "claim_collateral").args_json(&reason).max_gas().transact().await?;
+    assert!(outcome.is_failure(), "Only the seller can claim the collateral");
+
+    let outcome = alice.call(marketplace.id(), "claim_collateral").args_json(&reason).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // The collateral is escrowed on its own, the listing is not disputed
+    let listing: Value = marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(listing["renter_id"], Value::Null);
+    assert_eq!(listing["disputed"], false);
+    let claim: Value = marketplace.view("get_collateral_claim").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(claim["renter_id"], bob.id().as_str());
+    assert_eq!(claim["amount"], COLLATERAL.as_yoctonear().to_string());
+
+    // The rent of the next rental is paid out while the claim is open
+    let outcome = carol
+        .call(marketplace.id(), "rent")
+        .args_json(json!({"listing_id": "1", "days": 1}))
+        .deposit(PRICE_PER_DAY.saturating_add(COLLATERAL))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    sandbox.fast_forward(100).await?;
+    let alice_before = alice.view_account().await?.balance;
+    let outcome = bob.call(marketplace.id(), "claim_rent").args_json(json!({"listing_id": "1"})).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    assert!(alice.view_account().await?.balance > alice_before, "Rent is not held by the claim");
+
+    let outcome = alice
+        .call(marketplace.id(), "resolve_collateral_claim")
+        .args_json(json!({"listing_id": "1", "award_to_seller": true}))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure(), "Only moderators resolve a claim");
+
+    // A moderator awards the collateral, and only the collateral, to the seller
+    let alice_before = alice.view_account().await?.balance;
+    let outcome = owner
+        .call(marketplace.id(), "resolve_collateral_claim")
+        .args_json(json!({"listing_id": "1", "award_to_seller": true}))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let alice_gain = alice.view_account().await?.balance.as_yoctonear() - alice_before.as_yoctonear();
+    assert_eq!(alice_gain, COLLATERAL.as_yoctonear());
+    let claim: Option<Value> = marketplace.view("get_collateral_claim").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert!(claim.is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_multi_day_rental_streams_all_rent() -> Result<(), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let creator = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = nft
+        .call("nft_mint")
+        .args_json(json!({
+            "token_id": TOKEN_ID,
+            "receiver_id": alice.id(),
+            "metadata": {"title": "Recording voice-1"},
+            "royalty": {creator.id().to_string(): 1000},
+            "creator_id": creator.id(),
+        }))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let msg = json!({
+        "type": "rental",
+        "price_per_day": PRICE_PER_DAY.as_yoctonear().to_string(),
+        "max_days": 7,
+        "collateral": COLLATERAL.as_yoctonear().to_string(),
+    });
+    let outcome = alice
+        .call(nft.id(), "nft_approve")
+        .args_json(json!({"token_id": TOKEN_ID, "account_id": marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // The rent times the nanoseconds of three days is past u128::MAX
+    let days = 3;
+    let rent = PRICE_PER_DAY.as_yoctonear() * days as u128;
+    let outcome = bob
+        .call(marketplace.id(), "rent")
+        .args_json(json!({"listing_id": "1", "days": days}))
+        .deposit(NearToken::from_yoctonear(rent).saturating_add(COLLATERAL))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // The rent is streamed day by day
+    let mut streamed = 0;
+    for _ in 0..days {
+        sandbox.fast_forward(DAY_BLOCKS).await?;
+        let outcome = alice.call(marketplace.id(), "claim_rent").args_json(json!({"listing_id": "1"})).max_gas().transact().await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+        let claimed: u128 = outcome.json::<String>()?.parse()?;
+        assert!(claimed > 0, "Rent is earned every day of the rental");
+        streamed += claimed;
+    }
+
+    // Ending the rental streams what is left and returns the collateral
+    sandbox.fast_forward(DAY_BLOCKS).await?;
+    let bob_before = bob.view_account().await?.balance;
+    let outcome = alice.call(marketplace.id(), "end_rental").args_json(json!({"listing_id": "1"})).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    streamed += outcome
+        .logs()
+        .iter()
+        .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+        .map(|event| serde_json::from_str::<Value>(event).unwrap())
+        .filter(|event| event["event"] == "rent_streamed")
+        .map(|event| event["data"][0]["amount"].as_str().unwrap().parse::<u128>().unwrap())
+        .sum::<u128>();
+    assert_eq!(streamed, rent, "All of the rent is streamed by the end");
+    assert_eq!(
+        bob.view_account().await?.balance.as_yoctonear() - bob_before.as_yoctonear(),
+        COLLATERAL.as_yoctonear()
+    );
+
+    let listing: Value = marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(listing["renter_id"], Value::Null);
+
+    Ok(())
+}
@@ -0,0 +1,215 @@
+use near_workspaces::types::NearToken;
+use serde_json::{json, Value};
+
+const PRICE: NearToken = NearToken::from_near(2);
+const TOKEN_ID: &str = "voice-1";
+const STORAGE_DEPOSIT: NearToken = NearToken::from_millinear(10);
+
+/// Lists a token with a royalty to its creator, buys it, and checks what the
+/// seller and the creator received against `preview_payout`
+async fn sell_with_royalty(price: NearToken, royalty_bps: u32) -> Result<(u128, u128, Value), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let creator = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    // Funded for prices above the balance of a dev account
+    let bob = sandbox
+        .root_account()?
+        .create_subaccount("bob")
+        .initial_balance(price.saturating_add(NearToken::from_near(10)))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = nft
+        .call("nft_mint")
+        .args_json(json!({
+            "token_id": TOKEN_ID,
+            "receiver_id": alice.id(),
+            "metadata": {"title": "Recording voice-1"},
+            "royalty": {creator.id().to_string(): royalty_bps},
+            "creator_id": creator.id(),
+        }))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Storage for the listing, refunded when it closes
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let msg = json!({"type": "sale", "price": price.as_yoctonear().to_string()});
+    let outcome = alice
+        .call(nft.id(), "nft_approve")
+        .args_json(json!({"token_id": TOKEN_ID, "account_id": marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let preview: Value = marketplace
+        .view("preview_payout")
+        .args_json(json!({
+            "price": price.as_yoctonear().to_string(),
+            "seller_id": alice.id(),
+            "royalty": {creator.id().to_string(): royalty_bps},
+        }))
+        .await?
+        .json()?;
+
+    let alice_before = alice.view_account().await?.balance;
+    let creator_before = creator.view_account().await?.balance;
+
+    let outcome = bob
+        .call(marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": "1"}))
+        .deposit(price)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let settled = outcome
+        .logs()
+        .iter()
+        .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+        .map(|event| serde_json::from_str::<Value>(event).unwrap())
+        .find(|event| event["event"] == "sale_settled")
+        .expect("A settled sale emits an event");
+    assert_eq!(settled["data"][0]["listing_id"], "1");
+    assert_eq!(settled["data"][0]["marketplace_fee"], preview["marketplace_fee"]);
+
+    // The storage deposit of the listing comes back on top of the proceeds
+    let alice_gain = alice.view_account().await?.balance.as_yoctonear() - alice_before.as_yoctonear() - STORAGE_DEPOSIT.as_yoctonear();
+    let creator_gain = creator.view_account().await?.balance.as_yoctonear() - creator_before.as_yoctonear();
+
+    assert_eq!(preview["seller_proceeds"], alice_gain.to_string());
+    assert_eq!(preview["royalties"][creator.id().as_str()], creator_gain.to_string());
+
+    Ok((alice_gain, creator_gain, preview))
+}
+
+#[tokio::test]
+async fn test_sale_pays_creator_royalty() -> Result<(), Box<dyn std::error::Error>> {
+    let (seller_gain, creator_gain, preview) = sell_with_royalty(PRICE, 1000).await?;
+
+    let fee = PRICE.as_yoctonear() * 250 / 10_000;
+    let royalty = (PRICE.as_yoctonear() - fee) / 10;
+    assert_eq!(preview["marketplace_fee"], fee.to_string());
+    assert_eq!(creator_gain, royalty);
+    assert_eq!(seller_gain, PRICE.as_yoctonear() - fee - royalty);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_royalties_are_capped() -> Result<(), Box<dyn std::error::Error>> {
+    let (seller_gain, creator_gain, _) = sell_with_royalty(PRICE, 5000).await?;
+
+    // 50% royalty on the token, the marketplace pays out at most 20% of the price
+    let fee = PRICE.as_yoctonear() * 250 / 10_000;
+    let cap = PRICE.as_yoctonear() * 2000 / 10_000;
+    assert_eq!(creator_gain, cap);
+    assert_eq!(seller_gain, PRICE.as_yoctonear() - fee - cap);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_royalties_are_capped_at_high_prices() -> Result<(), Box<dyn std::error::Error>> {
+    // Scaling the royalty down multiplies two amounts of this size
+    let price = NearToken::from_near(100);
+    let (seller_gain, creator_gain, _) = sell_with_royalty(price, 5000).await?;
+
+    let fee = price.as_yoctonear() * 250 / 10_000;
+    let cap = price.as_yoctonear() * 2000 / 10_000;
+    assert_eq!(creator_gain, cap);
+    assert_eq!(seller_gain, price.as_yoctonear() - fee - cap);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_undeliverable_royalty_is_withdrawable() -> Result<(), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // The marketplace cannot create this account, so the royalty transfer fails
+    let creator_id = "missing-creator.test.near";
+    let outcome = nft
+        .call("nft_mint")
+        .args_json(json!({
+            "token_id": TOKEN_ID,
+            "receiver_id": alice.id(),
+            "metadata": {"title": "Recording voice-1"},
+            "royalty": {creator_id: 1000},
+        }))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let msg = json!({"type": "sale", "price": PRICE.as_yoctonear().to_string()});
+    let outcome = alice
+        .call(nft.id(), "nft_approve")
+        .args_json(json!({"token_id": TOKEN_ID, "account_id": marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = bob
+        .call(marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": "1"}))
+        .deposit(PRICE)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let fee = PRICE.as_yoctonear() * 250 / 10_000;
+    let royalty = (PRICE.as_yoctonear() - fee) / 10;
+    let pending: String = marketplace
+        .view("get_pending_withdrawal")
+        .args_json(json!({"account_id": creator_id, "ft_token_id": null}))
+        .await?
+        .json()?;
+    assert_eq!(pending, royalty.to_string());
+
+    Ok(())
+}
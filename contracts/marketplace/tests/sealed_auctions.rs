@@ -0,0 +1,228 @@
+use near_workspaces::{network::Sandbox, types::NearToken, Account, Contract, Worker};
+use serde_json::{json, Value};
+
+const START_PRICE: NearToken = NearToken::from_near(2);
+const TOKEN_ID: &str = "voice-1";
+const STORAGE_DEPOSIT: NearToken = NearToken::from_millinear(10);
+const COMMIT_DURATION: u64 = 30 * 1_000_000_000; // 30 seconds in nanoseconds
+const REVEAL_DURATION: u64 = 15 * 60 * 1_000_000_000; // 15 minutes in nanoseconds
+// Upper bound on what a transaction costs in gas, to tell refunds apart from fees
+const MAX_TX_COST: NearToken = NearToken::from_millinear(100);
+
+struct Setup {
+    marketplace: Contract,
+    nft: Contract,
+    alice: Account,
+    bob: Account,
+    carol: Account,
+    dave: Account,
+}
+
+/// Deploys both contracts and lists a token for a sealed-bid auction,
+/// `config` is merged into the auction arguments
+async fn setup(config: Value) -> Result<(Worker<Sandbox>, Setup), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+    let carol = sandbox.dev_create_account().await?;
+    let dave = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = nft
+        .call("nft_mint")
+        .args_json(json!({
+            "token_id": TOKEN_ID,
+            "receiver_id": alice.id(),
+            "metadata": {"title": "Recording voice-1"},
+        }))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let now = sandbox.view_block().await?.timestamp();
+    let mut msg = json!({
+        "type": "sealed_auction",
+        "start_price": START_PRICE.as_yoctonear().to_string(),
+        "start_time": now.to_string(),
+        "commit_end": (now + COMMIT_DURATION).to_string(),
+        "reveal_end": (now + COMMIT_DURATION + REVEAL_DURATION).to_string(),
+    });
+    msg.as_object_mut().unwrap().extend(config.as_object().unwrap().clone());
+    let outcome = alice
+        .call(nft.id(), "nft_approve")
+        .args_json(json!({"token_id": TOKEN_ID, "account_id": marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    Ok((sandbox, Setup { marketplace, nft, alice, bob, carol, dave }))
+}
+
+/// Commits a sealed bid of `amount` with `deposit` in escrow, returns the bid ID
+async fn commit(setup: &Setup, bidder: &Account, amount: NearToken, deposit: NearToken, salt: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let commitment: String = setup
+        .marketplace
+        .view("sealed_bid_commitment")
+        .args_json(json!({"auction_id": "1", "bidder_id": bidder.id(), "amount": amount.as_yoctonear().to_string(), "salt": salt}))
+        .await?
+        .json()?;
+    let outcome = bidder
+        .call(setup.marketplace.id(), "commit_bid")
+        .args_json(json!({"auction_id": "1", "commitment": commitment}))
+        .deposit(deposit)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    Ok(outcome.json()?)
+}
+
+async fn reveal(setup: &Setup, bidder: &Account, bid_id: &str, amount: NearToken, salt: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    let outcome = bidder
+        .call(setup.marketplace.id(), "reveal_bid")
+        .args_json(json!({"bid_id": bid_id, "amount": amount.as_yoctonear().to_string(), "salt": salt}))
+        .max_gas()
+        .transact()
+        .await?;
+    Ok(outcome.is_success())
+}
+
+#[tokio::test]
+async fn test_second_price_sealed_auction() -> Result<(), Box<dyn std::error::Error>> {
+    let (sandbox, setup) = setup(json!({"pricing": "second_price"})).await?;
+
+    // Bob deposits more than he bids so the deposit does not give the bid away
+    let bob_bid = NearToken::from_near(5);
+    let carol_bid = NearToken::from_near(3);
+    let bob_bid_id = commit(&setup, &setup.bob, bob_bid, NearToken::from_near(8), "bob-salt").await?;
+    let carol_bid_id = commit(&setup, &setup.carol, carol_bid, carol_bid, "carol-salt").await?;
+    commit(&setup, &setup.dave, NearToken::from_near(4), NearToken::from_near(4), "dave-salt").await?;
+
+    let listing: Value = setup.marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(listing["listing_type"], "sealed_auction");
+    assert_eq!(listing["bid_count"], 3);
+    assert_eq!(listing["highest_bid"], Value::Null);
+    let bid: Value = setup.marketplace.view("get_sealed_bid").args_json(json!({"bid_id": bob_bid_id})).await?.json()?;
+    assert_eq!(bid["amount"], Value::Null);
+
+    assert!(!reveal(&setup, &setup.bob, &bob_bid_id, bob_bid, "bob-salt").await?, "Bids are revealed after bidding closed");
+
+    sandbox.fast_forward(100).await?;
+    let outcome = setup
+        .carol
+        .call(setup.marketplace.id(), "commit_bid")
+        .args_json(json!({"auction_id": "1", "commitment": "11111111111111111111111111111111"}))
+        .deposit(START_PRICE)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure(), "Bidding has closed");
+
+    assert!(!reveal(&setup, &setup.bob, &bob_bid_id, bob_bid, "wrong-salt").await?, "The salt must match the commitment");
+    assert!(reveal(&setup, &setup.bob, &bob_bid_id, bob_bid, "bob-salt").await?);
+
+    // Carol's lower bid cannot win and is refunded on reveal
+    let carol_before = setup.carol.view_account().await?.balance;
+    assert!(reveal(&setup, &setup.carol, &carol_bid_id, carol_bid, "carol-salt").await?);
+    let carol_after = setup.carol.view_account().await?.balance;
+    assert!(carol_after.as_yoctonear() + MAX_TX_COST.as_yoctonear() > carol_before.as_yoctonear() + carol_bid.as_yoctonear());
+
+    let outcome = setup.alice.call(setup.marketplace.id(), "end_auction").args_json(json!({"auction_id": "1"})).max_gas().transact().await?;
+    assert!(outcome.is_failure(), "The auction must not end before the reveal phase");
+
+    sandbox.fast_forward(1000).await?;
+
+    // Bob wins and pays Carol's bid, the rest of his deposit comes back
+    let bob_before = setup.bob.view_account().await?.balance;
+    let outcome = setup.alice.call(setup.marketplace.id(), "end_auction").args_json(json!({"auction_id": "1"})).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let ended = outcome
+        .logs()
+        .iter()
+        .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+        .map(|event| serde_json::from_str::<Value>(event).unwrap())
+        .find(|event| event["event"] == "auction_ended")
+        .expect("An ended auction emits an event");
+    assert_eq!(ended["data"][0]["winner_id"], setup.bob.id().as_str());
+    assert_eq!(ended["data"][0]["winning_bid"], carol_bid.as_yoctonear().to_string());
+
+    let bob_after = setup.bob.view_account().await?.balance;
+    assert_eq!(bob_after.as_yoctonear() - bob_before.as_yoctonear(), NearToken::from_near(8).as_yoctonear() - carol_bid.as_yoctonear());
+    let token: Value = setup.nft.view("nft_token").args_json(json!({"token_id": TOKEN_ID})).await?.json()?;
+    assert_eq!(token["owner_id"], setup.bob.id().as_str());
+
+    // Dave never revealed, his deposit is refunded by default
+    let dave_before = setup.dave.view_account().await?.balance;
+    let outcome = setup
+        .alice
+        .call(setup.marketplace.id(), "settle_unrevealed_bids")
+        .args_json(json!({"auction_id": "1", "limit": 10}))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    assert_eq!(outcome.json::<u32>()?, 1);
+    let dave_after = setup.dave.view_account().await?.balance;
+    assert_eq!(dave_after.as_yoctonear() - dave_before.as_yoctonear(), NearToken::from_near(4).as_yoctonear());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unrevealed_bids_are_slashed() -> Result<(), Box<dyn std::error::Error>> {
+    let (sandbox, setup) = setup(json!({"slash_unrevealed": true})).await?;
+
+    let bob_bid = NearToken::from_near(3);
+    commit(&setup, &setup.bob, bob_bid, bob_bid, "bob-salt").await?;
+    let carol_bid_id = commit(&setup, &setup.carol, START_PRICE, START_PRICE, "carol-salt").await?;
+
+    let outcome = setup.alice.call(setup.marketplace.id(), "cancel_listing").args_json(json!({"listing_id": "1"})).transact().await?;
+    assert!(outcome.is_failure(), "An auction with bids cannot be cancelled");
+
+    sandbox.fast_forward(100).await?;
+    assert!(reveal(&setup, &setup.carol, &carol_bid_id, START_PRICE, "carol-salt").await?);
+    sandbox.fast_forward(1000).await?;
+
+    // First price, Carol pays her own bid
+    let outcome = setup.alice.call(setup.marketplace.id(), "end_auction").args_json(json!({"auction_id": "1"})).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let listing: Value = setup.marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    assert_eq!(listing["status"], "sold");
+    assert_eq!(listing["buyer_id"], setup.carol.id().as_str());
+
+    let fees_before: String = setup.marketplace.view("get_accrued_fees").args_json(json!({"ft_token_id": null})).await?.json()?;
+    let outcome = setup
+        .carol
+        .call(setup.marketplace.id(), "settle_unrevealed_bids")
+        .args_json(json!({"auction_id": "1", "limit": 10}))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    assert!(outcome.logs().iter().any(|log| log.contains("\"event\":\"sealed_bid_slashed\"")));
+
+    let fees_after: String = setup.marketplace.view("get_accrued_fees").args_json(json!({"ft_token_id": null})).await?.json()?;
+    assert_eq!(fees_after.parse::<u128>()? - fees_before.parse::<u128>()?, bob_bid.as_yoctonear());
+
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use near_workspaces::{types::NearToken, Account, Contract};
+use serde_json::{json, Value};
+
+const STORAGE_DEPOSIT: NearToken = NearToken::from_millinear(20);
+
+fn near(amount: u128) -> String {
+    NearToken::from_near(amount).as_yoctonear().to_string()
+}
+
+async fn list(marketplace: &Contract, nft: &Contract, seller: &Account, token_id: &str, price: u128) -> Result<(), Box<dyn std::error::Error>> {
+    let outcome = seller
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let msg = json!({"type": "sale", "price": near(price)});
+    let outcome = seller
+        .call(nft.id(), "nft_approve")
+        .args_json(json!({"token_id": token_id, "account_id": marketplace.id(), "msg": msg.to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    Ok(())
+}
+
+async fn buy(marketplace: &Contract, buyer: &Account, listing_id: &str, price: u128) -> Result<(), Box<dyn std::error::Error>> {
+    let outcome = buyer
+        .call(marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": listing_id}))
+        .deposit(NearToken::from_near(price))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stats_follow_settled_sales() -> Result<(), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+    let carol = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": marketplace.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    for token_id in ["voice-1", "voice-2"] {
+        let outcome = nft
+            .call("nft_mint")
+            .args_json(json!({"token_id": token_id, "receiver_id": alice.id(), "metadata": {"title": format!("Recording {}", token_id)}}))
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    }
+
+    let stats: Value = marketplace.view("get_token_stats").args_json(json!({"nft_contract_id": nft.id(), "token_id": "voice-1"})).await?.json()?;
+    assert_eq!(stats["trades"], 0);
+    assert_eq!(stats["last_sale_price"], Value::Null);
+
+    // Alice sells both tokens to Bob, who resells one to Carol
+    list(&marketplace, &nft, &alice, "voice-1", 2).await?;
+    list(&marketplace, &nft, &alice, "voice-2", 5).await?;
+    buy(&marketplace, &bob, "1", 2).await?;
+    buy(&marketplace, &bob, "2", 5).await?;
+    list(&marketplace, &nft, &bob, "voice-1", 3).await?;
+    buy(&marketplace, &carol, "3", 3).await?;
+
+    let stats: Value = marketplace.view("get_token_stats").args_json(json!({"nft_contract_id": nft.id(), "token_id": "voice-1"})).await?.json()?;
+    assert_eq!(stats["volume"], near(5));
+    assert_eq!(stats["trades"], 2);
+    assert_eq!(stats["last_sale_price"], near(3));
+    assert_eq!(stats["all_time_high"], near(3));
+
+    let stats: Value = marketplace.view("get_collection_stats").args_json(json!({"nft_contract_id": nft.id()})).await?.json()?;
+    assert_eq!(stats["volume"], near(10));
+    assert_eq!(stats["trades"], 3);
+    assert_eq!(stats["last_sale_price"], near(3));
+    assert_eq!(stats["all_time_high"], near(5));
+
+    let stats: Value = marketplace.view("get_account_stats").args_json(json!({"account_id": alice.id()})).await?.json()?;
+    assert_eq!(stats["sold"]["volume"], near(7));
+    assert_eq!(stats["sold"]["trades"], 2);
+    assert_eq!(stats["bought"]["trades"], 0);
+    let stats: Value = marketplace.view("get_account_stats").args_json(json!({"account_id": bob.id()})).await?.json()?;
+    assert_eq!(stats["bought"]["volume"], near(7));
+    assert_eq!(stats["sold"]["volume"], near(3));
+
+    let stats: Value = marketplace.view("get_market_stats").args_json(json!({})).await?.json()?;
+    assert_eq!(stats["total"]["volume"], near(10));
+    assert_eq!(stats["total"]["trades"], 3);
+    assert_eq!(stats["volume_24h"], near(10));
+    assert_eq!(stats["trades_24h"], 3);
+    assert_eq!(stats["volume_7d"], near(10));
+    assert_eq!(stats["trades_7d"], 3);
+
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use near_workspaces::types::NearToken;
+use serde_json::{json, Value};
+
+const PRICE: NearToken = NearToken::from_near(2);
+const BID: NearToken = NearToken::from_near(3);
+const STORAGE_DEPOSIT: NearToken = NearToken::from_millinear(30);
+const AUCTION_DURATION: u64 = 60 * 60 * 1_000_000_000; // 1 hour in nanoseconds
+const OFFER_DURATION: u64 = 24 * 60 * 60 * 1_000_000_000; // 1 day in nanoseconds
+// Upper bound on what a transaction costs in gas, to tell refunds apart from fees
+const MAX_TX_COST: NearToken = NearToken::from_millinear(100);
+
+#[tokio::test]
+async fn test_upgrade_keeps_listings_and_escrow() -> Result<(), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let dao = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+    let carol = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Populate the marketplace: a sale, an auction with a bid, an offer and a storage deposit
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let now = sandbox.view_block().await?.timestamp();
+    let listings = [
+        ("voice-1", json!({"type": "sale", "price": PRICE.as_yoctonear().to_string()})),
+        (
+            "voice-2",
+            json!({
+                "type": "auction",
+                "start_price": PRICE.as_yoctonear().to_string(),
+                "start_time": now.to_string(),
+                "end_time": (now + AUCTION_DURATION).to_string(),
+            }),
+        ),
+    ];
+    for (token_id, msg) in listings {
+        let outcome = nft
+            .call("nft_mint")
+            .args_json(json!({"token_id": token_id, "receiver_id": alice.id(), "metadata": {"title": format!("Recording {}", token_id)}}))
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+        let outcome = alice
+            .call(nft.id(), "nft_approve")
+            .args_json(json!({"token_id": token_id, "account_id": marketplace.id(), "msg": msg.to_string()}))
+            .deposit(NearToken::from_yoctonear(1))
+            .max_gas()
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    }
+
+    let outcome = bob.call(marketplace.id(), "place_bid").args_json(json!({"auction_id": "2"})).deposit(BID).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = carol
+        .call(marketplace.id(), "make_offer")
+        .args_json(json!({"nft_contract_id": nft.id(), "token_id": "voice-1", "expires_at": (now + OFFER_DURATION).to_string()}))
+        .deposit(PRICE)
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let offer_id: String = outcome.json()?;
+
+    let sale_before: Value = marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    let auction_before: Value = marketplace.view("get_listing").args_json(json!({"listing_id": "2"})).await?.json()?;
+    let offer_before: Value = marketplace.view("get_offer").args_json(json!({"offer_id": offer_id})).await?.json()?;
+    let storage_before: String = marketplace.view("storage_balance_of").args_json(json!({"account_id": alice.id()})).await?.json()?;
+    let balance_before = marketplace.view_account().await?.balance;
+
+    let outcome = alice
+        .call(marketplace.id(), "upgrade")
+        .args_borsh(marketplace_wasm.clone())
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_failure(), "Only owner or governance can upgrade");
+    let outcome = owner.call(marketplace.id(), "upgrade").args_borsh(marketplace_wasm.clone()).max_gas().transact().await?;
+    assert!(outcome.is_failure(), "Upgrading requires 1 yoctoNEAR");
+
+    // Once governance is handed to the DAO, both can upgrade
+    let outcome = owner.call(marketplace.id(), "set_governance").args_json(json!({"governance_id": dao.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    for upgrader in [&owner, &dao] {
+        let outcome = upgrader
+            .call(marketplace.id(), "upgrade")
+            .args_borsh(marketplace_wasm.clone())
+            .deposit(NearToken::from_yoctonear(1))
+            .max_gas()
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+        assert!(outcome.logs().iter().any(|log| log.starts_with("State is already at version")));
+    }
+
+    let version: String = marketplace.view("get_state_version").args_json(json!({})).await?.json()?;
+    assert_eq!(version, "V5");
+
+    // Nothing was lost, the escrow is still held
+    let sale_after: Value = marketplace.view("get_listing").args_json(json!({"listing_id": "1"})).await?.json()?;
+    let auction_after: Value = marketplace.view("get_listing").args_json(json!({"listing_id": "2"})).await?.json()?;
+    let offer_after: Value = marketplace.view("get_offer").args_json(json!({"offer_id": offer_id})).await?.json()?;
+    let storage_after: String = marketplace.view("storage_balance_of").args_json(json!({"account_id": alice.id()})).await?.json()?;
+    assert_eq!(sale_after, sale_before);
+    assert_eq!(auction_after, auction_before);
+    assert_eq!(auction_after["highest_bid"], BID.as_yoctonear().to_string());
+    assert_eq!(offer_after, offer_before);
+    assert_eq!(storage_after, storage_before);
+    assert!(marketplace.view_account().await?.balance >= balance_before);
+
+    // The escrowed offer is refunded by the upgraded code
+    let carol_before = carol.view_account().await?.balance;
+    let outcome = carol.call(marketplace.id(), "cancel_offer").args_json(json!({"offer_id": offer_id})).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let carol_after = carol.view_account().await?.balance;
+    assert!(carol_after.as_yoctonear() + MAX_TX_COST.as_yoctonear() > carol_before.as_yoctonear() + PRICE.as_yoctonear());
+
+    // And the sale can still be bought
+    let outcome = bob.call(marketplace.id(), "buy_item").args_json(json!({"listing_id": "1"})).deposit(PRICE).max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let token: Value = nft.view("nft_token").args_json(json!({"token_id": "voice-1"})).await?.json()?;
+    assert_eq!(token["owner_id"], bob.id().as_str());
+
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use near_workspaces::types::NearToken;
+use serde_json::{json, Value};
+
+const AUCTION_DURATION: u64 = 3600 * 1_000_000_000; // 1 hour in nanoseconds
+const STORAGE_DEPOSIT: NearToken = NearToken::from_millinear(40);
+
+fn ids(listings: &Value) -> Vec<&str> {
+    listings.as_array().unwrap().iter().map(|listing| listing["id"].as_str().unwrap()).collect()
+}
+
+#[tokio::test]
+async fn test_browse_listings() -> Result<(), Box<dyn std::error::Error>> {
+    let marketplace_wasm = near_workspaces::compile_project("./").await?;
+    let nft_wasm = near_workspaces::compile_project("../voice_nft").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let marketplace = sandbox.dev_deploy(&marketplace_wasm).await?;
+    let nft = sandbox.dev_deploy(&nft_wasm).await?;
+    let owner = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+
+    let outcome = marketplace.call("new").args_json(json!({"owner_id": owner.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = nft.call("new").args_json(json!({"owner_id": nft.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Storage for the four listings
+    let outcome = alice
+        .call(marketplace.id(), "storage_deposit")
+        .args_json(json!({}))
+        .deposit(STORAGE_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let now = sandbox.view_block().await?.timestamp();
+    let msgs = [
+        json!({"type": "sale", "price": NearToken::from_near(3).as_yoctonear().to_string()}),
+        json!({"type": "sale", "price": NearToken::from_near(2).as_yoctonear().to_string()}),
+        json!({
+            "type": "auction",
+            "start_price": NearToken::from_near(5).as_yoctonear().to_string(),
+            "start_time": now.to_string(),
+            "end_time": (now + 2 * AUCTION_DURATION).to_string(),
+        }),
+        json!({
+            "type": "dutch_auction",
+            "start_price": NearToken::from_near(10).as_yoctonear().to_string(),
+            "end_price": NearToken::from_near(8).as_yoctonear().to_string(),
+            "start_time": now.to_string(),
+            "end_time": (now + AUCTION_DURATION).to_string(),
+        }),
+    ];
+    for (index, msg) in msgs.iter().enumerate() {
+        let token_id = format!("voice-{}", index + 1);
+        let outcome = nft
+            .call("nft_mint")
+            .args_json(json!({"token_id": token_id, "receiver_id": alice.id(), "metadata": {"title": format!("Recording {}", token_id)}}))
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+        let outcome = alice
+            .call(nft.id(), "nft_approve")
+            .args_json(json!({"token_id": token_id, "account_id": marketplace.id(), "msg": msg.to_string()}))
+            .deposit(NearToken::from_yoctonear(1))
+            .max_gas()
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    }
+
+    let listings: Value = marketplace.view("get_listings").args_json(json!({})).await?.json()?;
+    assert_eq!(listings.as_array().unwrap().len(), 4);
+
+    let sales: Value = marketplace
+        .view("get_listings")
+        .args_json(json!({"filter": {"listing_type": "sale", "seller_id": alice.id(), "currency": "near"}}))
+        .await?
+        .json()?;
+    let mut sale_ids = ids(&sales);
+    sale_ids.sort();
+    assert_eq!(sale_ids, vec!["1", "2"]);
+
+    let in_range: Value = marketplace
+        .view("get_listings")
+        .args_json(json!({"filter": {
+            "min_price": NearToken::from_near(3).as_yoctonear().to_string(),
+            "max_price": NearToken::from_near(9).as_yoctonear().to_string(),
+        }}))
+        .await?
+        .json()?;
+    let mut in_range_ids = ids(&in_range);
+    in_range_ids.sort();
+    assert_eq!(in_range_ids, vec!["1", "3"]);
+
+    let by_price: Value = marketplace.view("get_listings_by_price").args_json(json!({})).await?.json()?;
+    assert_eq!(ids(&by_price), vec!["2", "1", "3", "4"]);
+    let page: Value = marketplace
+        .view("get_listings_by_price")
+        .args_json(json!({"from_index": "1", "limit": 2, "descending": true}))
+        .await?
+        .json()?;
+    assert_eq!(ids(&page), vec!["3", "1"]);
+
+    let ending_soon: Value = marketplace.view("get_auctions_ending_soon").args_json(json!({})).await?.json()?;
+    assert_eq!(ids(&ending_soon), vec!["4", "3"]);
+
+    // A sold listing leaves the active views and shows up by status
+    let outcome = bob
+        .call(marketplace.id(), "buy_item")
+        .args_json(json!({"listing_id": "2"}))
+        .deposit(NearToken::from_near(2))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let listings: Value = marketplace.view("get_listings").args_json(json!({})).await?.json()?;
+    assert_eq!(listings.as_array().unwrap().len(), 3);
+    let sold: Value = marketplace.view("get_listings").args_json(json!({"filter": {"status": "sold"}})).await?.json()?;
+    assert_eq!(ids(&sold), vec!["2"]);
+
+    // A bid moves the auction in the price index
+    let outcome = bob
+        .call(marketplace.id(), "place_bid")
+        .args_json(json!({"auction_id": "3"}))
+        .deposit(NearToken::from_near(6))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let below: Value = marketplace
+        .view("get_listings_by_price")
+        .args_json(json!({"filter": {"max_price": NearToken::from_near(7).as_yoctonear().to_string()}}))
+        .await?
+        .json()?;
+    assert_eq!(ids(&below), vec!["1", "3"]);
+    let above: Value = marketplace
+        .view("get_listings_by_price")
+        .args_json(json!({"filter": {"min_price": NearToken::from_near(4).as_yoctonear().to_string()}, "descending": true}))
+        .await?
+        .json()?;
+    assert_eq!(ids(&above), vec!["4", "3"]);
+
+    let by_seller: Vec<String> = marketplace
+        .view("get_listings_by_seller")
+        .args_json(json!({"seller_id": alice.id(), "from_index": "3", "limit": 10}))
+        .await?
+        .json()?;
+    assert_eq!(by_seller.len(), 1);
+
+    Ok(())
+}
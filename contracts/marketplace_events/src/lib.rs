@@ -0,0 +1,252 @@
+//! Marketplace Events - Schema of the NEP-297 events the marketplace emits
+//!
+//! Every event is logged as `EVENT_JSON:` followed by
+//! `{"standard": "voice_marketplace", "version": "1.3.0", "event": "...", "data": [...]}`.
+//! Amounts and timestamps are strings, like in the marketplace views.
+//!
+//! The crate version is the schema version. New events and new optional
+//! fields bump the minor version, anything an older indexer cannot parse
+//! bumps the major version.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The `standard` of every marketplace event
+pub const EVENT_STANDARD: &str = "voice_marketplace";
+/// The schema version events are emitted with
+pub const EVENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// The prefix NEP-297 puts before the event JSON in a log
+pub const EVENT_JSON_PREFIX: &str = "EVENT_JSON:";
+
+/// An event as logged, with its standard and schema version
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MarketplaceEventLog {
+    pub standard: String,
+    pub version: String,
+    #[serde(flatten)]
+    pub event: MarketplaceEvent,
+}
+
+impl MarketplaceEventLog {
+    /// Parses a log line into an event. `None` when the log is not a
+    /// marketplace event or its major version differs from this crate's.
+    pub fn from_log_str(log: &str) -> Option<Self> {
+        let event: Self = serde_json::from_str(log.strip_prefix(EVENT_JSON_PREFIX)?).ok()?;
+        let major = |version: &str| version.split('.').next().map(str::to_string);
+        (event.standard == EVENT_STANDARD && major(&event.version) == major(EVENT_VERSION)).then_some(event)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum MarketplaceEvent {
+    ListingCreated(Vec<ListingCreatedData>),
+    ListingUpdated(Vec<ListingUpdatedData>),
+    ListingCancelled(Vec<ListingCancelledData>),
+    BidPlaced(Vec<BidPlacedData>),
+    BidRefunded(Vec<BidRefundedData>),
+    SaleSettled(Vec<SaleSettledData>),
+    AuctionEnded(Vec<AuctionEndedData>),
+    /// Since 1.1.0
+    RentalStarted(Vec<RentalStartedData>),
+    /// Since 1.1.0
+    RentStreamed(Vec<RentStreamedData>),
+    /// Since 1.1.0
+    RentalEnded(Vec<RentalEndedData>),
+    /// Since 1.2.0
+    SealedBidCommitted(Vec<SealedBidCommittedData>),
+    /// Since 1.2.0
+    SealedBidRevealed(Vec<SealedBidRevealedData>),
+    /// Since 1.2.0
+    SealedBidSlashed(Vec<SealedBidSlashedData>),
+    /// Since 1.3.0
+    ListingFrozen(Vec<ListingFrozenData>),
+    /// Since 1.3.0
+    ListingUnfrozen(Vec<ListingUnfrozenData>),
+    /// Since 1.3.0
+    DisputeOpened(Vec<DisputeOpenedData>),
+    /// Since 1.3.0
+    DisputeResolved(Vec<DisputeResolvedData>),
+}
+
+impl MarketplaceEvent {
+    /// The log line announcing the event
+    pub fn to_log_string(self) -> String {
+        let log = MarketplaceEventLog { standard: EVENT_STANDARD.to_string(), version: EVENT_VERSION.to_string(), event: self };
+        format!("{}{}", EVENT_JSON_PREFIX, serde_json::to_string(&log).expect("Events serialize to JSON"))
+    }
+}
+
+/// A token of a bundle listing
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BundleItemData {
+    pub nft_contract_id: String,
+    pub token_id: String,
+}
+
+/// A new listing, including the sold listings recording accepted offers
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ListingCreatedData {
+    pub listing_id: String,
+    /// `sale`, `auction`, `dutch_auction`, `sealed_auction`, `bundle` or `rental`
+    pub listing_type: String,
+    pub status: String,
+    pub seller_id: String,
+    pub nft_contract_id: String,
+    pub token_id: String,
+    /// The NEP-141 token of the prices, `None` for NEAR
+    pub ft_token_id: Option<String>,
+    /// The fixed price, the start price of an auction, the price per day of a rental
+    pub price: String,
+    pub expires_at: String,
+    /// The tokens of a bundle, the listed token first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle_items: Option<Vec<BundleItemData>>,
+}
+
+/// A changed price, or a token joining a bundle
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ListingUpdatedData {
+    pub listing_id: String,
+    pub price: String,
+    /// The token that joined the bundle
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_token_id: Option<String>,
+}
+
+/// A listing closed without a sale
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ListingCancelledData {
+    pub listing_id: String,
+    /// `cancelled`, or `expired` when it ended without a buyer
+    pub status: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BidPlacedData {
+    pub listing_id: String,
+    pub bid_id: String,
+    pub bidder_id: String,
+    pub amount: String,
+    /// The end of the auction, later when the bid extended it
+    pub end_time: String,
+}
+
+/// An escrowed bid sent back, outbid, below the reserve price, or a sealed
+/// bid that lost or was not revealed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BidRefundedData {
+    pub listing_id: String,
+    pub bidder_id: String,
+    pub amount: String,
+}
+
+/// A sale paid out once the token was transferred, with the price split
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SaleSettledData {
+    pub listing_id: String,
+    pub seller_id: String,
+    pub buyer_id: String,
+    pub ft_token_id: Option<String>,
+    pub price: String,
+    pub marketplace_fee: String,
+    pub royalties: HashMap<String, String>,
+    pub seller_proceeds: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuctionEndedData {
+    pub listing_id: String,
+    /// `sold`, or `expired` without a bid above the reserve price
+    pub status: String,
+    pub winner_id: Option<String>,
+    pub winning_bid: Option<String>,
+}
+
+/// A renter was granted the use of the token, rent and collateral are escrowed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RentalStartedData {
+    pub listing_id: String,
+    pub renter_id: String,
+    pub rent: String,
+    pub collateral: String,
+    pub ends_at: String,
+}
+
+/// Rent earned since the last stream paid out, split like a sale
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RentStreamedData {
+    pub listing_id: String,
+    pub amount: String,
+    pub marketplace_fee: String,
+    pub royalties: HashMap<String, String>,
+    pub seller_proceeds: String,
+}
+
+/// A rental over, its collateral returned to the renter
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RentalEndedData {
+    pub listing_id: String,
+    pub renter_id: String,
+    pub collateral: String,
+}
+
+/// A sealed bid committed, only its deposit is known until it is revealed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SealedBidCommittedData {
+    pub listing_id: String,
+    pub bid_id: String,
+    pub bidder_id: String,
+    pub deposit: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SealedBidRevealedData {
+    pub listing_id: String,
+    pub bid_id: String,
+    pub bidder_id: String,
+    pub amount: String,
+}
+
+/// The deposit of a sealed bid never revealed, kept as a fee
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SealedBidSlashedData {
+    pub listing_id: String,
+    pub bid_id: String,
+    pub bidder_id: String,
+    pub amount: String,
+}
+
+/// A listing taken out of trading by a moderator
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ListingFrozenData {
+    pub listing_id: String,
+    pub moderator_id: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ListingUnfrozenData {
+    pub listing_id: String,
+    pub moderator_id: String,
+}
+
+/// Settlement funds of a listing held in escrow until the dispute is resolved
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DisputeOpenedData {
+    pub listing_id: String,
+    pub moderator_id: String,
+    pub reason: String,
+    pub expires_at: String,
+}
+
+/// The held funds released, to their original receivers unless a moderator
+/// awarded them to `payee_id`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DisputeResolvedData {
+    pub listing_id: String,
+    pub payee_id: Option<String>,
+    pub ft_token_id: Option<String>,
+    pub amount: String,
+    pub timed_out: bool,
+}
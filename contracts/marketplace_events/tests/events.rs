@@ -0,0 +1,39 @@
+use marketplace_events::{ListingCancelledData, MarketplaceEvent, MarketplaceEventLog, EVENT_STANDARD, EVENT_VERSION};
+
+#[test]
+fn test_event_log_round_trip() {
+    let event = MarketplaceEvent::ListingCancelled(vec![ListingCancelledData { listing_id: "7".to_string(), status: "expired".to_string() }]);
+
+    let log = event.clone().to_log_string();
+    assert_eq!(
+        log,
+        format!(
+            r#"EVENT_JSON:{{"standard":"voice_marketplace","version":"{}","event":"listing_cancelled","data":[{{"listing_id":"7","status":"expired"}}]}}"#,
+            EVENT_VERSION
+        )
+    );
+
+    let parsed = MarketplaceEventLog::from_log_str(&log).unwrap();
+    assert_eq!(parsed.standard, EVENT_STANDARD);
+    assert_eq!(parsed.event, event);
+}
+
+#[test]
+fn test_other_logs_are_skipped() {
+    assert!(MarketplaceEventLog::from_log_str("Sold listing 1 of token voice-1").is_none());
+    assert!(MarketplaceEventLog::from_log_str(
+        r#"EVENT_JSON:{"standard":"nep171","version":"1.0.0","event":"nft_transfer","data":[]}"#
+    )
+    .is_none());
+
+    // A newer major version may have changed the schema
+    assert!(MarketplaceEventLog::from_log_str(
+        r#"EVENT_JSON:{"standard":"voice_marketplace","version":"2.0.0","event":"listing_cancelled","data":[{"listing_id":"7","status":"expired"}]}"#
+    )
+    .is_none());
+    // A newer minor version only adds to it
+    assert!(MarketplaceEventLog::from_log_str(
+        r#"EVENT_JSON:{"standard":"voice_marketplace","version":"1.9.0","event":"listing_cancelled","data":[{"listing_id":"7","status":"expired","reason":"moderation"}]}"#
+    )
+    .is_some());
+}
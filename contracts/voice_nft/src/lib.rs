@@ -2,13 +2,73 @@
 // (migrated from monolithic contracts/src/voice_nft.rs)
 
 use near_sdk::{
-    env, log, AccountId, PanicOnDefault,
-    collections::{LookupMap, UnorderedMap, UnorderedSet},
+    assert_one_yocto, env, ext_contract, log, AccountId, BorshStorageKey, Gas, NearToken, PanicOnDefault, Promise,
+    // `store::TreeMap` is still gated behind near-sdk's `unstable` feature
+    collections::TreeMap,
     json_types::{U128, U64},
     serde::{Deserialize, Serialize},
+    store::{IterableMap, IterableSet, LookupMap},
 };
 use borsh::{BorshDeserialize, BorshSerialize};
 use std::collections::HashMap;
+use std::ops::Bound;
+
+mod migration;
+
+const MAX_DRAFT_DURATION: u64 = 30 * 24 * 60 * 60 * 1_000_000_000; // 30 days in nanoseconds
+const MAX_ROYALTY_TOTAL: u32 = 10_000; // 100% in basis points
+const MAX_LEN_PAYOUT: u32 = 10; // Max receivers of a payout, the owner included
+const GAS_FOR_NFT_ON_APPROVE: Gas = Gas::from_tgas(25);
+
+/// NEP-178 receiver notified when it is approved to transfer a token
+#[ext_contract(ext_nft_approval_receiver)]
+pub trait NonFungibleTokenApprovalReceiver {
+    fn nft_on_approve(&mut self, token_id: String, owner_id: AccountId, approval_id: u64, msg: String);
+}
+
+// Storage keys
+#[derive(BorshSerialize, BorshStorageKey)]
+enum StorageKey {
+    Tokens,
+    TokensByOwner,
+    TokensPerOwner { account_hash: [u8; 32] },
+    TokensBySeq,
+    OwnerTokensBySeq,
+    TokensByCreator,
+    TokensPerCreator { account_hash: [u8; 32] },
+    TokensByLanguage,
+    TokensPerLanguage { language_hash: [u8; 32] },
+    TokensByVoiceType,
+    TokensPerVoiceType { voice_type_hash: [u8; 32] },
+    TokensByTag,
+    TokensPerTag { tag_hash: [u8; 32] },
+    // Prefix of the V3 single approval per token, kept so the prefixes after it do not move
+    #[allow(dead_code)]
+    TokenApprovals,
+    OperatorApprovals,
+    OperatorsPerOwner { account_hash: [u8; 32] },
+    CreatorVerifiers,
+    MintDrafts,
+    TokenUsers,
+    Minters,
+}
+
+/// Layout of the contract state, `migrate` converts older layouts to the current one
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateVersion {
+    /// `near_sdk::collections` with nested sets prefixed by raw account bytes
+    V1,
+    /// `near_sdk::store` collections prefixed by `StorageKey`
+    V2,
+    /// Adds collaborative mint drafts and minters
+    V3,
+    /// Replaces the single approval per token with NEP-178 approval IDs
+    V4,
+    /// Adds time-limited token users
+    V5,
+}
+
+const CURRENT_STATE_VERSION: StateVersion = StateVersion::V5;
 
 // JSON-compatible types for view methods
 #[derive(Serialize, Deserialize, schemars::JsonSchema)]
@@ -35,6 +95,8 @@ pub struct VoiceNFTMetadataView {
 pub struct VoiceNFTView {
     pub token_id: String,
     pub owner_id: String,
+    pub creator_id: String,
+    pub creator_verified: bool,
     pub metadata: VoiceNFTMetadataView,
     pub approved_account_ids: HashMap<String, u64>,
     pub royalty: Option<HashMap<String, u32>>,
@@ -42,6 +104,39 @@ pub struct VoiceNFTView {
     pub updated_at: String,
 }
 
+/// NEP-199 payout, amounts owed to each account out of a sale balance
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+pub struct Payout {
+    pub payout: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+pub struct MintDraftView {
+    pub id: String,
+    pub proposer_id: String,
+    pub token_id: String,
+    pub receiver_id: String,
+    pub creator_id: String,
+    pub metadata: VoiceNFTMetadataView,
+    pub shares: HashMap<String, u32>,
+    pub approved_by: Vec<String>,
+    pub storage_deposit: String,
+    pub expires_at: String,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+pub struct TokenUserView {
+    pub user_id: String,
+    pub expires_at: String,
+}
+
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+pub struct VoiceNFTPageView {
+    pub tokens: Vec<VoiceNFTView>,
+    pub next_cursor: Option<String>, // Pass back as `from_cursor` to fetch the next page
+}
+
 #[derive(Serialize, Deserialize, schemars::JsonSchema)]
 pub struct ContractMetadataView {
     pub spec: String,
@@ -78,13 +173,40 @@ pub struct VoiceNFTMetadata {
 pub struct VoiceNFT {
     pub token_id: String,
     pub owner_id: AccountId,
+    pub creator_id: AccountId, // Account that recorded the voice; never changes after mint
+    pub creator_verified: bool, // Whether a verifier has confirmed the creator attribution
     pub metadata: VoiceNFTMetadata,
-    pub approved_account_ids: HashMap<AccountId, u64>,
+    pub approved_account_ids: HashMap<AccountId, u64>, // Account -> NEP-178 approval ID
     pub royalty: Option<HashMap<AccountId, u32>>, // Account -> royalty percentage (basis points)
+    pub mint_seq: U128, // Mint sequence number, used as a stable pagination cursor
     pub created_at: U64,
     pub updated_at: U64,
 }
 
+/// A collaborative mint waiting for every collaborator to sign off
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
+pub struct MintDraft {
+    pub id: u64,
+    pub proposer_id: AccountId,
+    pub token_id: String,
+    pub receiver_id: AccountId,
+    pub creator_id: AccountId,
+    pub metadata: VoiceNFTMetadata,
+    pub shares: HashMap<AccountId, u32>, // Collaborator -> royalty share (basis points)
+    pub approved_by: Vec<AccountId>,
+    pub storage_deposit: U128, // Paid by the proposer for the draft's storage, refunded once it is minted or withdrawn
+    pub expires_at: U64,
+    pub created_at: U64,
+}
+
+/// A time-limited right to use a token granted by its owner, e.g. a rental
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
+pub struct TokenUser {
+    pub user_id: AccountId,
+    pub expires_at: U64,
+    pub set_by: AccountId, // Owner or approved account that granted the right; only it can change the user early
+}
+
 #[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq, Eq)]
 pub struct ContractMetadata {
     pub spec: String,
@@ -100,22 +222,46 @@ pub struct ContractMetadata {
 #[near_sdk::near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct VoiceNFTContract {
+    /// Layout version of this state, always kept as the first field
+    pub state_version: StateVersion,
     /// Contract owner
     pub owner_id: AccountId,
     /// Total number of tokens
     pub total_supply: U128,
     /// Mapping from token ID to token
-    pub tokens: UnorderedMap<String, VoiceNFT>,
+    pub tokens: IterableMap<String, VoiceNFT>,
     /// Mapping from owner to list of token IDs
-    pub tokens_by_owner: LookupMap<AccountId, UnorderedSet<String>>,
-    /// Mapping from token ID to approved account
-    pub token_approvals: LookupMap<String, AccountId>,
+    pub tokens_by_owner: LookupMap<AccountId, IterableSet<String>>,
+    /// Mapping from mint sequence number to token ID, in mint order
+    pub tokens_by_seq: TreeMap<u128, String>,
+    /// Mapping from (owner, mint sequence number) to token ID, in mint order per owner
+    pub owner_tokens_by_seq: TreeMap<(AccountId, u128), String>,
+    /// Mapping from creator to list of token IDs
+    pub tokens_by_creator: LookupMap<AccountId, IterableSet<String>>,
+    /// Mapping from normalized language to list of token IDs
+    pub tokens_by_language: LookupMap<String, IterableSet<String>>,
+    /// Mapping from normalized voice type to list of token IDs
+    pub tokens_by_voice_type: LookupMap<String, IterableSet<String>>,
+    /// Mapping from normalized tag to list of token IDs
+    pub tokens_by_tag: LookupMap<String, IterableSet<String>>,
     /// Mapping from owner to operator approvals
-    pub operator_approvals: LookupMap<AccountId, UnorderedSet<AccountId>>,
+    pub operator_approvals: LookupMap<AccountId, IterableSet<AccountId>>,
+    /// Accounts allowed to verify creator attributions
+    pub creator_verifiers: IterableSet<AccountId>,
+    /// Accounts allowed to propose and finalize collaborative mints, besides the owner
+    pub minters: IterableSet<AccountId>,
+    /// Pending collaborative mints by draft ID
+    pub mint_drafts: IterableMap<u64, MintDraft>,
+    /// Next collaborative mint draft ID
+    pub next_draft_id: u64,
+    /// Next NEP-178 approval ID
+    pub next_approval_id: u64,
     /// Contract metadata
     pub metadata: ContractMetadata,
-    /// Next token ID counter
+    /// Next mint sequence number
     pub next_token_id: U128,
+    /// Mapping from token ID to the account allowed to use it, until it expires
+    pub token_users: LookupMap<String, TokenUser>,
 }
 
 #[near_sdk::near_bindgen]
@@ -135,23 +281,29 @@ impl VoiceNFTContract {
             reference_hash: None,
         };
 
-        let this = Self {
-            owner_id: owner_id.clone(),
-            total_supply: U128(0),
-            tokens: UnorderedMap::new(b"t".to_vec()),
-            tokens_by_owner: LookupMap::new(b"tbo".to_vec()),
-            token_approvals: LookupMap::new(b"ta".to_vec()),
-            operator_approvals: LookupMap::new(b"oa".to_vec()),
-            metadata,
-            next_token_id: U128(1),
-        };
+        let this = Self::empty_state(owner_id.clone(), metadata);
 
         log!("Voice NFT contract deployed. Owner: {}", owner_id);
 
         this
     }
 
+    /// Migrate the state of a previous deployment to the current layout.
+    /// Call it right after deploying the new code, in the same batch transaction.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        migration::migrate_state()
+    }
+
+    /// Get the layout version of the contract state
+    pub fn get_state_version(&self) -> String {
+        format!("{:?}", self.state_version)
+    }
+
     /// Mint a new voice NFT
+    ///
+    /// `creator_id` attests who recorded the voice and defaults to `receiver_id`.
     #[payable]
     pub fn nft_mint(
         &mut self,
@@ -159,74 +311,219 @@ impl VoiceNFTContract {
         receiver_id: AccountId,
         metadata: VoiceNFTMetadataView,
         royalty: Option<HashMap<String, u32>>,
+        creator_id: Option<AccountId>,
     ) -> VoiceNFTView {
         // Only owner can mint for now
         assert_eq!(env::predecessor_account_id(), self.owner_id, "Only owner can mint");
-        
-        // Ensure token doesn't already exist
-        assert!(!self.tokens.get(&token_id).is_some(), "Token already exists");
 
-        // Convert view metadata to internal metadata
-        let internal_metadata = VoiceNFTMetadata {
-            title: metadata.title,
-            description: metadata.description,
-            media: metadata.media,
-            media_hash: metadata.media_hash,
-            copies: metadata.copies,
-            issued_at: metadata.issued_at,
-            expires_at: metadata.expires_at,
-            starts_at: metadata.starts_at,
-            updated_at: metadata.updated_at,
-            extra: metadata.extra,
-            reference: metadata.reference,
-            reference_hash: metadata.reference_hash,
-            duration: metadata.duration,
-            voice_type: metadata.voice_type,
-            language: metadata.language,
-            tags: metadata.tags,
-        };
+        let internal_metadata = self.metadata_from_view(metadata);
 
         // Convert royalty from String keys to AccountId keys
-        let internal_royalty = royalty.map(|r| 
+        let internal_royalty: Option<HashMap<AccountId, u32>> = royalty.map(|r| 
             r.into_iter()
                 .map(|(k, v)| (k.parse().expect("Invalid account ID"), v))
                 .collect()
         );
+        if let Some(royalty) = &internal_royalty {
+            assert_valid_royalty(royalty);
+        }
 
-        let token = VoiceNFT {
-            token_id: token_id.clone(),
-            owner_id: receiver_id.clone(),
-            metadata: internal_metadata,
-            approved_account_ids: HashMap::new(),
-            royalty: internal_royalty,
-            created_at: U64(env::block_timestamp()),
-            updated_at: U64(env::block_timestamp()),
+        let creator_id = creator_id.unwrap_or_else(|| receiver_id.clone());
+        let token = self.internal_mint(token_id, receiver_id, creator_id, internal_metadata, internal_royalty);
+
+        self.token_to_view(token)
+    }
+
+    /// Propose a voice NFT recorded by several creators. Once every collaborator
+    /// has approved the draft, the owner or a minter mints it with `shares` as its
+    /// royalty map.
+    ///
+    /// The proposer must be the contract owner or a minter and attaches a deposit
+    /// covering the draft's storage, refunded when it is minted or withdrawn. A
+    /// proposer who is a collaborator approves right away. `creator_id` defaults
+    /// to the proposer.
+    #[payable]
+    pub fn nft_propose_collaborative_mint(
+        &mut self,
+        token_id: String,
+        receiver_id: AccountId,
+        metadata: VoiceNFTMetadataView,
+        shares: HashMap<String, u32>,
+        expires_at: U64,
+        creator_id: Option<AccountId>,
+    ) -> MintDraftView {
+        let proposer_id = env::predecessor_account_id();
+        let now = env::block_timestamp();
+        let initial_storage_usage = env::storage_usage();
+        self.assert_minter(&proposer_id, "Only the owner or a minter can propose a collaborative mint");
+
+        // Convert shares from String keys to AccountId keys
+        let shares: HashMap<AccountId, u32> = shares.into_iter()
+            .map(|(k, v)| (k.parse().expect("Invalid account ID"), v))
+            .collect();
+
+        assert!(shares.len() >= 2, "A collaborative mint needs at least two collaborators");
+        assert_valid_royalty(&shares);
+        assert!(expires_at.0 > now, "Deadline must be in the future");
+        assert!(expires_at.0 <= now + MAX_DRAFT_DURATION, "Deadline is too far in the future");
+        assert!(self.tokens.get(&token_id).is_none(), "Token already exists");
+
+        let creator_id = creator_id.unwrap_or_else(|| proposer_id.clone());
+        assert!(shares.contains_key(&creator_id), "Creator must be a collaborator");
+
+        let approved_by = if shares.contains_key(&proposer_id) {
+            vec![proposer_id.clone()]
+        } else {
+            Vec::new()
         };
 
-        // Insert token
-        self.tokens.insert(&token_id, &token);
+        let draft = MintDraft {
+            id: self.next_draft_id,
+            proposer_id: proposer_id.clone(),
+            token_id,
+            receiver_id,
+            creator_id,
+            metadata: self.metadata_from_view(metadata),
+            shares,
+            approved_by,
+            storage_deposit: U128(0),
+            expires_at,
+            created_at: U64(now),
+        };
 
-        // Add to owner's tokens
-        let mut owner_tokens = self.tokens_by_owner.get(&receiver_id).unwrap_or_else(|| UnorderedSet::new(receiver_id.as_bytes().to_vec()));
-        owner_tokens.insert(&token_id);
-        self.tokens_by_owner.insert(&receiver_id, &owner_tokens);
+        self.mint_drafts.insert(draft.id, draft.clone());
+        self.next_draft_id += 1;
+        self.mint_drafts.flush();
 
-        // Increment counters
-        self.total_supply.0 += 1;
-        self.next_token_id.0 += 1;
+        // The proposer pays for the draft's storage, the rest of the deposit is refunded
+        let storage_used = env::storage_usage() - initial_storage_usage;
+        let storage_cost = env::storage_byte_cost().as_yoctonear() * u128::from(storage_used);
+        let attached_deposit = env::attached_deposit().as_yoctonear();
+        assert!(attached_deposit >= storage_cost, "Attach at least {} yoctoNEAR to cover the draft's storage", storage_cost);
+        if attached_deposit > storage_cost {
+            Promise::new(proposer_id.clone()).transfer(NearToken::from_yoctonear(attached_deposit - storage_cost));
+        }
+        let draft = self.mint_drafts.get_mut(&draft.id).unwrap();
+        draft.storage_deposit = U128(storage_cost);
+        let draft = draft.clone();
 
-        log!("Minted voice NFT {} to {}", token_id, receiver_id);
+        log!("{} proposed collaborative mint {} for token {}", proposer_id, draft.id, draft.token_id);
+
+        self.draft_to_view(draft)
+    }
+
+    /// Approve a collaborative mint draft (collaborators only)
+    pub fn nft_approve_collaborative_mint(&mut self, draft_id: u64) -> MintDraftView {
+        let collaborator_id = env::predecessor_account_id();
+        let draft = self.mint_drafts.get_mut(&draft_id).expect("Draft not found");
+
+        assert!(draft.shares.contains_key(&collaborator_id), "Only collaborators can approve");
+        assert!(env::block_timestamp() <= draft.expires_at.0, "Draft has expired");
+        assert!(!draft.approved_by.contains(&collaborator_id), "Already approved");
+
+        draft.approved_by.push(collaborator_id.clone());
+        let draft = draft.clone();
+        log!("{} approved collaborative mint {}", collaborator_id, draft_id);
+
+        self.draft_to_view(draft)
+    }
+
+    /// Mint a collaborative mint draft every collaborator has approved (owner or minters only).
+    /// The proposer gets the draft's storage deposit back.
+    pub fn nft_finalize_collaborative_mint(&mut self, draft_id: u64) -> VoiceNFTView {
+        self.assert_minter(&env::predecessor_account_id(), "Only the owner or a minter can finalize a collaborative mint");
+        let draft = self.mint_drafts.get(&draft_id).expect("Draft not found");
+
+        assert!(env::block_timestamp() <= draft.expires_at.0, "Draft has expired");
+        assert_eq!(draft.approved_by.len(), draft.shares.len(), "Not every collaborator has approved");
+
+        let draft = self.mint_drafts.remove(&draft_id).unwrap();
+        refund_draft_deposit(&draft);
+        let token = self.internal_mint(draft.token_id, draft.receiver_id, draft.creator_id, draft.metadata, Some(draft.shares));
 
         self.token_to_view(token)
     }
 
-    /// Transfer a token
+    /// Withdraw a collaborative mint draft. Collaborators and the proposer can
+    /// cancel it at any time, anyone can clear it once it has expired. The
+    /// proposer gets the draft's storage deposit back.
+    pub fn nft_cancel_collaborative_mint(&mut self, draft_id: u64) {
+        let account_id = env::predecessor_account_id();
+        let draft = self.mint_drafts.get(&draft_id).expect("Draft not found");
+
+        assert!(
+            account_id == draft.proposer_id
+                || draft.shares.contains_key(&account_id)
+                || env::block_timestamp() > draft.expires_at.0,
+            "Only the proposer or a collaborator can cancel an active draft"
+        );
+
+        let draft = self.mint_drafts.remove(&draft_id).unwrap();
+        refund_draft_deposit(&draft);
+
+        log!("{} cancelled collaborative mint {}", account_id, draft_id);
+    }
+
+    /// Transfer a token, either as its owner or as an approved account
     #[payable]
-    pub fn nft_transfer(&mut self, receiver_id: AccountId, token_id: String, memo: Option<String>) {
-        let sender_id = env::predecessor_account_id();
+    pub fn nft_transfer(&mut self, receiver_id: AccountId, token_id: String, approval_id: Option<u64>, memo: Option<String>) {
+        let predecessor_id = env::predecessor_account_id();
+        let token = self.tokens.get(&token_id).expect("Token not found");
+        let sender_id = token.owner_id.clone();
+
+        if predecessor_id != sender_id {
+            self.assert_approved(&token_id, &predecessor_id, approval_id);
+        }
+
         self.internal_transfer(&sender_id, &receiver_id, &token_id, memo);
     }
 
+    /// Transfer a token and return the NEP-199 payout for `balance` owed by the receiver.
+    /// Used by marketplaces to settle a sale in the same call as the transfer.
+    #[payable]
+    pub fn nft_transfer_payout(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: String,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+        balance: U128,
+        max_len_payout: Option<u32>,
+    ) -> Payout {
+        assert_one_yocto();
+
+        // The payout is owed to the owner at the time of the sale
+        let payout = self.nft_payout(token_id.clone(), balance, max_len_payout);
+        self.nft_transfer(receiver_id, token_id, approval_id, memo);
+
+        payout
+    }
+
+    /// Get the NEP-199 payout of a token for a sale of `balance`: royalties
+    /// from the token's royalty map, the remainder to the current owner.
+    pub fn nft_payout(&self, token_id: String, balance: U128, max_len_payout: Option<u32>) -> Payout {
+        let token = self.tokens.get(&token_id).expect("Token not found");
+        let royalty = token.royalty.clone().unwrap_or_default();
+
+        if let Some(max_len_payout) = max_len_payout {
+            assert!(royalty.len() < max_len_payout as usize, "Market cannot payout to that many receivers");
+        }
+
+        let mut payout: HashMap<String, String> = HashMap::new();
+        let mut total_royalties = 0;
+
+        // The owner's own royalty share is part of the remainder below
+        for (account_id, share) in royalty.iter().filter(|(account_id, _)| **account_id != token.owner_id) {
+            let amount = balance.0 * *share as u128 / MAX_ROYALTY_TOTAL as u128;
+            payout.insert(account_id.to_string(), amount.to_string());
+            total_royalties += amount;
+        }
+
+        payout.insert(token.owner_id.to_string(), (balance.0 - total_royalties).to_string());
+
+        Payout { payout }
+    }
+
     /// Transfer a token from one account to another (requires approval)
     #[payable]
     pub fn nft_transfer_from(&mut self, sender_id: AccountId, receiver_id: AccountId, token_id: String, memo: Option<String>) {
@@ -234,56 +531,191 @@ impl VoiceNFTContract {
         self.internal_transfer_from(&sender_id, &receiver_id, &token_id, &predecessor_id, memo);
     }
 
-    /// Approve an account to transfer a specific token
+    /// Update the metadata of an existing token (owner only)
+    pub fn nft_update_metadata(&mut self, token_id: String, metadata: VoiceNFTMetadataView) -> VoiceNFTView {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "Only owner can update metadata");
+
+        let mut token = self.tokens.get(&token_id).cloned().expect("Token not found");
+
+        // Drop the old metadata from the indexes before replacing it
+        self.internal_remove_from_indexes(&token);
+
+        token.metadata = self.metadata_from_view(metadata);
+        token.updated_at = U64(env::block_timestamp());
+        self.tokens.insert(token_id.clone(), token.clone());
+
+        self.internal_add_to_indexes(&token);
+
+        log!("Updated metadata of voice NFT {}", token_id);
+
+        self.token_to_view(token)
+    }
+
+    /// Burn a token (token owner only)
     #[payable]
-    pub fn nft_approve(&mut self, token_id: String, account_id: AccountId) {
+    pub fn nft_burn(&mut self, token_id: String) {
         let owner_id = env::predecessor_account_id();
-        let token = self.tokens.get(&token_id).expect("Token not found");
+        let token = self.tokens.get(&token_id).cloned().expect("Token not found");
+
+        assert_eq!(token.owner_id, owner_id, "Only owner can burn");
+        self.assert_not_in_use(&token_id);
+        self.token_users.remove(&token_id);
+
+        // Remove from owner's tokens
+        remove_from_index(&mut self.tokens_by_owner, &owner_id, &token_id);
+        self.tokens_by_seq.remove(&token.mint_seq.0);
+        self.owner_tokens_by_seq.remove(&(owner_id.clone(), token.mint_seq.0));
+
+        self.internal_remove_from_indexes(&token);
+
+        self.tokens.remove(&token_id);
+        self.total_supply.0 -= 1;
+
+        log!("Burned voice NFT {} owned by {}", token_id, owner_id);
+    }
+
+    /// Mark the creator attribution of a token as verified or unverified (verifiers only)
+    pub fn nft_set_creator_verified(&mut self, token_id: String, verified: bool) -> VoiceNFTView {
+        let verifier_id = env::predecessor_account_id();
+        assert!(self.creator_verifiers.contains(&verifier_id), "Only creator verifiers can verify creators");
+
+        let token = self.tokens.get_mut(&token_id).expect("Token not found");
+        token.creator_verified = verified;
+        token.updated_at = U64(env::block_timestamp());
+        let token = token.clone();
+
+        log!("{} set creator of voice NFT {} as verified: {}", verifier_id, token_id, verified);
+
+        self.token_to_view(token)
+    }
+
+    /// Allow an account to propose and finalize collaborative mints (owner only)
+    pub fn add_minter(&mut self, account_id: AccountId) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "Only owner can manage minters");
+        self.minters.insert(account_id.clone());
+        log!("Added minter {}", account_id);
+    }
+
+    /// Revoke an account's right to propose and finalize collaborative mints (owner only)
+    pub fn remove_minter(&mut self, account_id: AccountId) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "Only owner can manage minters");
+        self.minters.remove(&account_id);
+        log!("Removed minter {}", account_id);
+    }
+
+    /// Allow an account to verify creator attributions (owner only)
+    pub fn add_creator_verifier(&mut self, account_id: AccountId) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "Only owner can manage verifiers");
+        self.creator_verifiers.insert(account_id.clone());
+        log!("Added creator verifier {}", account_id);
+    }
+
+    /// Revoke an account's right to verify creator attributions (owner only)
+    pub fn remove_creator_verifier(&mut self, account_id: AccountId) {
+        assert_eq!(env::predecessor_account_id(), self.owner_id, "Only owner can manage verifiers");
+        self.creator_verifiers.remove(&account_id);
+        log!("Removed creator verifier {}", account_id);
+    }
+
+    /// Approve an account to transfer a specific token (NEP-178).
+    /// When `msg` is given, the account is notified through `nft_on_approve`,
+    /// which is how marketplaces list a token.
+    #[payable]
+    pub fn nft_approve(&mut self, token_id: String, account_id: AccountId, msg: Option<String>) -> Option<Promise> {
+        let owner_id = env::predecessor_account_id();
+        let approval_id = self.next_approval_id;
+        let token = self.tokens.get_mut(&token_id).expect("Token not found");
         
         assert_eq!(token.owner_id, owner_id, "Only owner can approve");
         
-        self.token_approvals.insert(&token_id, &account_id);
+        token.approved_account_ids.insert(account_id.clone(), approval_id);
+        self.next_approval_id += 1;
         
-        log!("Approved {} to transfer token {}", account_id, token_id);
+        log!("Approved {} to transfer token {} with approval ID {}", account_id, token_id, approval_id);
+
+        msg.map(|msg| {
+            ext_nft_approval_receiver::ext(account_id)
+                .with_static_gas(GAS_FOR_NFT_ON_APPROVE)
+                .nft_on_approve(token_id, owner_id, approval_id, msg)
+        })
     }
 
-    /// Revoke approval for a specific token
+    /// Revoke an account's approval for a specific token
     #[payable]
-    pub fn nft_revoke(&mut self, token_id: String) {
+    pub fn nft_revoke(&mut self, token_id: String, account_id: AccountId) {
         let owner_id = env::predecessor_account_id();
-        let token = self.tokens.get(&token_id).expect("Token not found");
+        let token = self.tokens.get_mut(&token_id).expect("Token not found");
         
         assert_eq!(token.owner_id, owner_id, "Only owner can revoke");
         
-        self.token_approvals.remove(&token_id);
+        token.approved_account_ids.remove(&account_id);
         
-        log!("Revoked approval for token {}", token_id);
+        log!("Revoked approval of {} for token {}", account_id, token_id);
+    }
+
+    /// Revoke every approval for a specific token
+    #[payable]
+    pub fn nft_revoke_all(&mut self, token_id: String) {
+        let owner_id = env::predecessor_account_id();
+        let token = self.tokens.get_mut(&token_id).expect("Token not found");
+
+        assert_eq!(token.owner_id, owner_id, "Only owner can revoke");
+
+        token.approved_account_ids.clear();
+
+        log!("Revoked all approvals for token {}", token_id);
+    }
+
+    /// Grant `user_id` the right to use a token until `expires_at` (nanoseconds),
+    /// as its owner or as an approved account such as a rental marketplace.
+    /// The token cannot be transferred or burned while it has a user, and an
+    /// unexpired user can only be replaced by whoever set it.
+    #[payable]
+    pub fn nft_set_user(&mut self, token_id: String, user_id: AccountId, expires_at: U64, approval_id: Option<u64>) {
+        assert_one_yocto();
+        let predecessor_id = env::predecessor_account_id();
+        let token = self.tokens.get(&token_id).expect("Token not found");
+
+        if predecessor_id != token.owner_id {
+            self.assert_approved(&token_id, &predecessor_id, approval_id);
+        }
+        assert!(expires_at.0 > env::block_timestamp(), "Expiry must be in the future");
+        if let Some(current) = self.internal_user_of(&token_id) {
+            assert_eq!(current.set_by, predecessor_id, "Token is in use until {}", current.expires_at.0);
+        }
+
+        self.token_users.insert(token_id.clone(), TokenUser { user_id: user_id.clone(), expires_at, set_by: predecessor_id });
+
+        log!("Set {} as user of token {} until {}", user_id, token_id, expires_at.0);
+    }
+
+    /// Get the account allowed to use a token, if its right has not expired
+    pub fn nft_user_of(&self, token_id: String) -> Option<TokenUserView> {
+        self.internal_user_of(&token_id).map(|user| TokenUserView {
+            user_id: user.user_id.to_string(),
+            expires_at: user.expires_at.0.to_string(),
+        })
+    }
+
+    /// Check whether an account is approved for a token, optionally with a specific approval ID
+    pub fn nft_is_approved(&self, token_id: String, approved_account_id: AccountId, approval_id: Option<u64>) -> bool {
+        let token = self.tokens.get(&token_id).expect("Token not found");
+
+        match (token.approved_account_ids.get(&approved_account_id), approval_id) {
+            (Some(current_id), Some(approval_id)) => *current_id == approval_id,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
     }
 
     /// Get token information
     pub fn nft_token(&self, token_id: String) -> Option<VoiceNFTView> {
-        self.tokens.get(&token_id).map(|token| self.token_to_view(token))
+        self.tokens.get(&token_id).map(|token| self.token_to_view(token.clone()))
     }
 
     /// Get tokens owned by an account
     pub fn nft_tokens_for_owner(&self, account_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<VoiceNFTView> {
-        let tokens_set = self.tokens_by_owner.get(&account_id);
-        
-        if let Some(tokens_set) = tokens_set {
-            let start = from_index.map(|i| i.0 as usize).unwrap_or(0);
-            let limit = limit.unwrap_or(50) as usize;
-            
-            tokens_set
-                .to_vec()
-                .into_iter()
-                .skip(start)
-                .take(limit)
-                .filter_map(|token_id| self.tokens.get(&token_id))
-                .map(|token| self.token_to_view(token))
-                .collect()
-        } else {
-            Vec::new()
-        }
+        self.paginate_token_set(self.tokens_by_owner.get(&account_id), from_index, limit)
     }
 
     /// Get total supply
@@ -313,10 +745,29 @@ impl VoiceNFTContract {
             .values()
             .skip(start)
             .take(limit)
-            .map(|token| self.token_to_view(token))
+            .map(|token| self.token_to_view(token.clone()))
             .collect()
     }
 
+    /// Get a page of tokens in mint order, starting after `from_cursor`
+    ///
+    /// Unlike `nft_tokens`, the cursor stays valid while new tokens are minted.
+    pub fn nft_tokens_page(&self, from_cursor: Option<U128>, limit: Option<u64>) -> VoiceNFTPageView {
+        let lower = from_cursor.map_or(Bound::Unbounded, |cursor| Bound::Excluded(cursor.0));
+        let entries = self.tokens_by_seq.range((lower, Bound::Unbounded));
+        self.collect_page(entries.map(|(_, token_id)| token_id), limit)
+    }
+
+    /// Get a page of tokens owned by an account in mint order, starting after `from_cursor`
+    ///
+    /// Unlike `nft_tokens_for_owner`, the cursor stays valid while the owner receives new tokens.
+    pub fn nft_tokens_for_owner_page(&self, account_id: AccountId, from_cursor: Option<U128>, limit: Option<u64>) -> VoiceNFTPageView {
+        let lower = from_cursor.map_or(Bound::Included((account_id.clone(), 0)), |cursor| Bound::Excluded((account_id.clone(), cursor.0)));
+        let upper = Bound::Included((account_id, u128::MAX));
+        let entries = self.owner_tokens_by_seq.range((lower, upper));
+        self.collect_page(entries.map(|(_, token_id)| token_id), limit)
+    }
+
     /// Get supply for owner
     pub fn nft_supply_for_owner(&self, account_id: AccountId) -> String {
         self.tokens_by_owner
@@ -325,35 +776,237 @@ impl VoiceNFTContract {
             .unwrap_or("0".to_string())
     }
 
+    /// Get tokens recorded by a creator
+    pub fn nft_tokens_for_creator(&self, creator_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<VoiceNFTView> {
+        self.paginate_token_set(self.tokens_by_creator.get(&creator_id), from_index, limit)
+    }
+
+    /// Get supply for creator
+    pub fn nft_supply_for_creator(&self, account_id: AccountId) -> String {
+        self.tokens_by_creator
+            .get(&account_id)
+            .map(|tokens| tokens.len().to_string())
+            .unwrap_or("0".to_string())
+    }
+
+    /// Get a collaborative mint draft
+    pub fn get_mint_draft(&self, draft_id: u64) -> Option<MintDraftView> {
+        self.mint_drafts.get(&draft_id).map(|draft| self.draft_to_view(draft.clone()))
+    }
+
+    /// Get pending collaborative mint drafts
+    pub fn get_mint_drafts(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<MintDraftView> {
+        let start = from_index.map(|i| i.0 as usize).unwrap_or(0);
+        let limit = limit.unwrap_or(50) as usize;
+
+        self.mint_drafts
+            .values()
+            .skip(start)
+            .take(limit)
+            .map(|draft| self.draft_to_view(draft.clone()))
+            .collect()
+    }
+
+    /// Get the accounts allowed to propose and finalize collaborative mints, besides the owner
+    pub fn get_minters(&self) -> Vec<String> {
+        self.minters.iter().map(|id| id.to_string()).collect()
+    }
+
+    /// Get the accounts allowed to verify creator attributions
+    pub fn get_creator_verifiers(&self) -> Vec<String> {
+        self.creator_verifiers.iter().map(|id| id.to_string()).collect()
+    }
+
+    /// Get tokens recorded in a language
+    pub fn nft_tokens_by_language(&self, language: String, from_index: Option<U128>, limit: Option<u64>) -> Vec<VoiceNFTView> {
+        self.paginate_token_set(self.tokens_by_language.get(&index_key(&language)), from_index, limit)
+    }
+
+    /// Get tokens of a voice type
+    pub fn nft_tokens_by_voice_type(&self, voice_type: String, from_index: Option<U128>, limit: Option<u64>) -> Vec<VoiceNFTView> {
+        self.paginate_token_set(self.tokens_by_voice_type.get(&index_key(&voice_type)), from_index, limit)
+    }
+
+    /// Get tokens carrying a tag
+    pub fn nft_tokens_by_tag(&self, tag: String, from_index: Option<U128>, limit: Option<u64>) -> Vec<VoiceNFTView> {
+        self.paginate_token_set(self.tokens_by_tag.get(&index_key(&tag)), from_index, limit)
+    }
+
     // Internal methods
+    fn empty_state(owner_id: AccountId, metadata: ContractMetadata) -> Self {
+        Self {
+            state_version: CURRENT_STATE_VERSION,
+            owner_id,
+            total_supply: U128(0),
+            tokens: IterableMap::new(StorageKey::Tokens),
+            tokens_by_owner: LookupMap::new(StorageKey::TokensByOwner),
+            tokens_by_seq: TreeMap::new(StorageKey::TokensBySeq),
+            owner_tokens_by_seq: TreeMap::new(StorageKey::OwnerTokensBySeq),
+            tokens_by_creator: LookupMap::new(StorageKey::TokensByCreator),
+            tokens_by_language: LookupMap::new(StorageKey::TokensByLanguage),
+            tokens_by_voice_type: LookupMap::new(StorageKey::TokensByVoiceType),
+            tokens_by_tag: LookupMap::new(StorageKey::TokensByTag),
+            operator_approvals: LookupMap::new(StorageKey::OperatorApprovals),
+            creator_verifiers: IterableSet::new(StorageKey::CreatorVerifiers),
+            minters: IterableSet::new(StorageKey::Minters),
+            mint_drafts: IterableMap::new(StorageKey::MintDrafts),
+            next_draft_id: 1,
+            next_approval_id: 1,
+            metadata,
+            next_token_id: U128(1),
+            token_users: LookupMap::new(StorageKey::TokenUsers),
+        }
+    }
+
+    fn internal_mint(
+        &mut self,
+        token_id: String,
+        receiver_id: AccountId,
+        creator_id: AccountId,
+        metadata: VoiceNFTMetadata,
+        royalty: Option<HashMap<AccountId, u32>>,
+    ) -> VoiceNFT {
+        // Ensure token doesn't already exist
+        assert!(self.tokens.get(&token_id).is_none(), "Token already exists");
+
+        let token = VoiceNFT {
+            token_id: token_id.clone(),
+            owner_id: receiver_id.clone(),
+            creator_id,
+            creator_verified: false,
+            metadata,
+            approved_account_ids: HashMap::new(),
+            royalty,
+            mint_seq: self.next_token_id,
+            created_at: U64(env::block_timestamp()),
+            updated_at: U64(env::block_timestamp()),
+        };
+
+        // Insert token and add it to the owner and discovery indexes
+        self.internal_add_token(&token);
+
+        // Increment counters
+        self.total_supply.0 += 1;
+        self.next_token_id.0 += 1;
+
+        log!("Minted voice NFT {} to {}", token_id, receiver_id);
+
+        token
+    }
+
+    /// Stores a token and adds it to the owner, mint-ordered and discovery indexes
+    fn internal_add_token(&mut self, token: &VoiceNFT) {
+        self.tokens.insert(token.token_id.clone(), token.clone());
+
+        add_to_index(&mut self.tokens_by_owner, &token.owner_id, &token.token_id, || StorageKey::TokensPerOwner {
+            account_hash: env::sha256_array(token.owner_id.as_bytes()),
+        });
+        self.tokens_by_seq.insert(&token.mint_seq.0, &token.token_id);
+        self.owner_tokens_by_seq.insert(&(token.owner_id.clone(), token.mint_seq.0), &token.token_id);
+
+        self.internal_add_to_indexes(token);
+    }
+
+    fn internal_add_to_indexes(&mut self, token: &VoiceNFT) {
+        add_to_index(&mut self.tokens_by_creator, &token.creator_id, &token.token_id, || StorageKey::TokensPerCreator {
+            account_hash: env::sha256_array(token.creator_id.as_bytes()),
+        });
+        if let Some(language) = &token.metadata.language {
+            let language = index_key(language);
+            add_to_index(&mut self.tokens_by_language, &language, &token.token_id, || StorageKey::TokensPerLanguage {
+                language_hash: env::sha256_array(language.as_bytes()),
+            });
+        }
+        if let Some(voice_type) = &token.metadata.voice_type {
+            let voice_type = index_key(voice_type);
+            add_to_index(&mut self.tokens_by_voice_type, &voice_type, &token.token_id, || StorageKey::TokensPerVoiceType {
+                voice_type_hash: env::sha256_array(voice_type.as_bytes()),
+            });
+        }
+        for tag in token.metadata.tags.iter().flatten() {
+            let tag = index_key(tag);
+            add_to_index(&mut self.tokens_by_tag, &tag, &token.token_id, || StorageKey::TokensPerTag {
+                tag_hash: env::sha256_array(tag.as_bytes()),
+            });
+        }
+    }
+
+    fn internal_remove_from_indexes(&mut self, token: &VoiceNFT) {
+        remove_from_index(&mut self.tokens_by_creator, &token.creator_id, &token.token_id);
+        if let Some(language) = &token.metadata.language {
+            remove_from_index(&mut self.tokens_by_language, &index_key(language), &token.token_id);
+        }
+        if let Some(voice_type) = &token.metadata.voice_type {
+            remove_from_index(&mut self.tokens_by_voice_type, &index_key(voice_type), &token.token_id);
+        }
+        for tag in token.metadata.tags.iter().flatten() {
+            remove_from_index(&mut self.tokens_by_tag, &index_key(tag), &token.token_id);
+        }
+    }
+
+    fn paginate_token_set(&self, tokens_set: Option<&IterableSet<String>>, from_index: Option<U128>, limit: Option<u64>) -> Vec<VoiceNFTView> {
+        let Some(tokens_set) = tokens_set else {
+            return Vec::new();
+        };
+
+        let start = from_index.map(|i| i.0 as usize).unwrap_or(0);
+        let limit = limit.unwrap_or(50) as usize;
+
+        tokens_set
+            .iter()
+            .skip(start)
+            .take(limit)
+            .filter_map(|token_id| self.tokens.get(token_id))
+            .map(|token| self.token_to_view(token.clone()))
+            .collect()
+    }
+
+    fn collect_page(&self, token_ids: impl Iterator<Item = String>, limit: Option<u64>) -> VoiceNFTPageView {
+        let limit = limit.unwrap_or(50) as usize;
+
+        let tokens: Vec<VoiceNFT> = token_ids
+            .take(limit)
+            .filter_map(|token_id| self.tokens.get(&token_id).cloned())
+            .collect();
+
+        // A short page means the end of the index was reached
+        let next_cursor = if tokens.len() == limit {
+            tokens.last().map(|token| token.mint_seq.0.to_string())
+        } else {
+            None
+        };
+
+        VoiceNFTPageView {
+            tokens: tokens.into_iter().map(|token| self.token_to_view(token)).collect(),
+            next_cursor,
+        }
+    }
+
     fn internal_transfer(&mut self, sender_id: &AccountId, receiver_id: &AccountId, token_id: &String, _memo: Option<String>) {
-        let mut token = self.tokens.get(token_id).expect("Token not found");
+        let mut token = self.tokens.get(token_id).cloned().expect("Token not found");
         
         assert_eq!(&token.owner_id, sender_id, "Only owner can transfer");
         assert_ne!(sender_id, receiver_id, "Cannot transfer to self");
+        self.assert_not_in_use(token_id);
+        self.token_users.remove(token_id);
         
         // Remove from sender's tokens
-        let mut sender_tokens = self.tokens_by_owner.get(sender_id).unwrap();
-        sender_tokens.remove(token_id);
-        if sender_tokens.is_empty() {
-            self.tokens_by_owner.remove(sender_id);
-        } else {
-            self.tokens_by_owner.insert(sender_id, &sender_tokens);
-        }
+        remove_from_index(&mut self.tokens_by_owner, sender_id, token_id);
         
         // Add to receiver's tokens
-        let mut receiver_tokens = self.tokens_by_owner.get(receiver_id).unwrap_or_else(|| UnorderedSet::new(receiver_id.as_bytes().to_vec()));
-        receiver_tokens.insert(token_id);
-        self.tokens_by_owner.insert(receiver_id, &receiver_tokens);
+        add_to_index(&mut self.tokens_by_owner, receiver_id, token_id, || StorageKey::TokensPerOwner {
+            account_hash: env::sha256_array(receiver_id.as_bytes()),
+        });
+
+        // Move the token between the owners' mint-ordered indexes
+        self.owner_tokens_by_seq.remove(&(sender_id.clone(), token.mint_seq.0));
+        self.owner_tokens_by_seq.insert(&(receiver_id.clone(), token.mint_seq.0), token_id);
         
         // Update token owner
         token.owner_id = receiver_id.clone();
         token.updated_at = U64(env::block_timestamp());
         token.approved_account_ids.clear();
-        self.tokens.insert(token_id, &token);
-        
-        // Clear approvals
-        self.token_approvals.remove(token_id);
+        self.tokens.insert(token_id.clone(), token);
         
         log!("Transferred token {} from {} to {}", token_id, sender_id, receiver_id);
     }
@@ -363,38 +1016,111 @@ impl VoiceNFTContract {
         
         assert_eq!(&token.owner_id, sender_id, "Sender is not the owner");
         
-        // Check if predecessor is approved
-        let approved = self.token_approvals.get(token_id).map(|approved_id| &approved_id == predecessor_id).unwrap_or(false);
-        let operator_approved = self.operator_approvals.get(sender_id).map(|operators| operators.contains(predecessor_id)).unwrap_or(false);
-        
-        assert!(approved || operator_approved || predecessor_id == sender_id, "Not approved to transfer");
+        if predecessor_id != sender_id {
+            self.assert_approved(token_id, predecessor_id, None);
+        }
         
         self.internal_transfer(sender_id, receiver_id, token_id, _memo);
     }
 
-    // Helper methods to convert internal types to view types
+    /// The user of a token whose right has not expired
+    fn internal_user_of(&self, token_id: &String) -> Option<&TokenUser> {
+        self.token_users.get(token_id).filter(|user| user.expires_at.0 > env::block_timestamp())
+    }
+
+    fn assert_minter(&self, account_id: &AccountId, message: &str) {
+        assert!(*account_id == self.owner_id || self.minters.contains(account_id), "{}", message);
+    }
+
+    fn assert_not_in_use(&self, token_id: &String) {
+        if let Some(user) = self.internal_user_of(token_id) {
+            env::panic_str(&format!("Token is in use by {} until {}", user.user_id, user.expires_at.0));
+        }
+    }
+
+    /// Panics unless `account_id` holds an approval for the token (matching
+    /// `approval_id` when given) or is an operator of the token's owner
+    fn assert_approved(&self, token_id: &String, account_id: &AccountId, approval_id: Option<u64>) {
+        let token = self.tokens.get(token_id).expect("Token not found");
+
+        let approved = match (token.approved_account_ids.get(account_id), approval_id) {
+            (Some(current_id), Some(approval_id)) => *current_id == approval_id,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        let operator_approved = self.operator_approvals.get(&token.owner_id).map(|operators| operators.contains(account_id)).unwrap_or(false);
+
+        assert!(approved || operator_approved, "Not approved to transfer");
+    }
+
+    // Helper methods to convert between view and internal types
+    fn metadata_from_view(&self, metadata: VoiceNFTMetadataView) -> VoiceNFTMetadata {
+        VoiceNFTMetadata {
+            title: metadata.title,
+            description: metadata.description,
+            media: metadata.media,
+            media_hash: metadata.media_hash,
+            copies: metadata.copies,
+            issued_at: metadata.issued_at,
+            expires_at: metadata.expires_at,
+            starts_at: metadata.starts_at,
+            updated_at: metadata.updated_at,
+            extra: metadata.extra,
+            reference: metadata.reference,
+            reference_hash: metadata.reference_hash,
+            duration: metadata.duration,
+            voice_type: metadata.voice_type,
+            language: metadata.language,
+            tags: metadata.tags,
+        }
+    }
+
+    fn draft_to_view(&self, draft: MintDraft) -> MintDraftView {
+        MintDraftView {
+            id: draft.id.to_string(),
+            proposer_id: draft.proposer_id.to_string(),
+            token_id: draft.token_id,
+            receiver_id: draft.receiver_id.to_string(),
+            creator_id: draft.creator_id.to_string(),
+            metadata: self.metadata_to_view(draft.metadata),
+            shares: draft.shares.into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+            approved_by: draft.approved_by.iter().map(|id| id.to_string()).collect(),
+            storage_deposit: draft.storage_deposit.0.to_string(),
+            expires_at: draft.expires_at.0.to_string(),
+            created_at: draft.created_at.0.to_string(),
+        }
+    }
+
+    fn metadata_to_view(&self, metadata: VoiceNFTMetadata) -> VoiceNFTMetadataView {
+        VoiceNFTMetadataView {
+            title: metadata.title,
+            description: metadata.description,
+            media: metadata.media,
+            media_hash: metadata.media_hash,
+            copies: metadata.copies,
+            issued_at: metadata.issued_at,
+            expires_at: metadata.expires_at,
+            starts_at: metadata.starts_at,
+            updated_at: metadata.updated_at,
+            extra: metadata.extra,
+            reference: metadata.reference,
+            reference_hash: metadata.reference_hash,
+            duration: metadata.duration,
+            voice_type: metadata.voice_type,
+            language: metadata.language,
+            tags: metadata.tags,
+        }
+    }
+
     fn token_to_view(&self, token: VoiceNFT) -> VoiceNFTView {
         VoiceNFTView {
             token_id: token.token_id,
             owner_id: token.owner_id.to_string(),
-            metadata: VoiceNFTMetadataView {
-                title: token.metadata.title,
-                description: token.metadata.description,
-                media: token.metadata.media,
-                media_hash: token.metadata.media_hash,
-                copies: token.metadata.copies,
-                issued_at: token.metadata.issued_at,
-                expires_at: token.metadata.expires_at,
-                starts_at: token.metadata.starts_at,
-                updated_at: token.metadata.updated_at,
-                extra: token.metadata.extra,
-                reference: token.metadata.reference,
-                reference_hash: token.metadata.reference_hash,
-                duration: token.metadata.duration,
-                voice_type: token.metadata.voice_type,
-                language: token.metadata.language,
-                tags: token.metadata.tags,
-            },
+            creator_id: token.creator_id.to_string(),
+            creator_verified: token.creator_verified,
+            metadata: self.metadata_to_view(token.metadata),
             approved_account_ids: token.approved_account_ids.into_iter()
                 .map(|(k, v)| (k.to_string(), v))
                 .collect(),
@@ -408,3 +1134,59 @@ impl VoiceNFTContract {
         }
     }
 }
+
+/// Asserts a royalty map can be paid out: its shares add up to at most 100%
+/// and it leaves room for the owner in a payout of `MAX_LEN_PAYOUT` receivers
+fn assert_valid_royalty(royalty: &HashMap<AccountId, u32>) {
+    assert!(
+        royalty.values().map(|share| *share as u64).sum::<u64>() <= MAX_ROYALTY_TOTAL as u64,
+        "Royalty shares exceed 100%"
+    );
+    assert!(royalty.len() < MAX_LEN_PAYOUT as usize, "Royalty has more than {} receivers", MAX_LEN_PAYOUT - 1);
+}
+
+/// Returns the storage deposit of a removed draft to its proposer
+fn refund_draft_deposit(draft: &MintDraft) {
+    if draft.storage_deposit.0 > 0 {
+        Promise::new(draft.proposer_id.clone()).transfer(NearToken::from_yoctonear(draft.storage_deposit.0));
+    }
+}
+
+/// Normalizes a language, voice type or tag so index lookups are case-insensitive
+fn index_key(value: &str) -> String {
+    value.trim().to_lowercase()
+}
+
+/// Adds a token to the set stored under `key`, creating the set on first use
+fn add_to_index<K, T>(
+    index: &mut LookupMap<K, IterableSet<T>>,
+    key: &K,
+    value: &T,
+    storage_key: impl FnOnce() -> StorageKey,
+) where
+    K: BorshSerialize + Ord + Clone,
+    T: BorshSerialize + BorshDeserialize + Ord + Clone,
+{
+    index
+        .entry(key.clone())
+        .or_insert_with(|| IterableSet::new(storage_key()))
+        .insert(value.clone());
+}
+
+/// Removes a token from the set stored under `key`, dropping the set once empty
+fn remove_from_index<K, T>(index: &mut LookupMap<K, IterableSet<T>>, key: &K, value: &T)
+where
+    K: BorshSerialize + Ord + Clone,
+    T: BorshSerialize + BorshDeserialize + Ord + Clone,
+{
+    let now_empty = match index.get_mut(key) {
+        Some(tokens_set) => {
+            tokens_set.remove(value);
+            tokens_set.is_empty()
+        }
+        None => false,
+    };
+    if now_empty {
+        index.remove(key);
+    }
+}
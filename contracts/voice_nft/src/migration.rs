@@ -0,0 +1,295 @@
+//! State migrations from the layouts of previous deployments. Each step
+//! converts one version to the next, `migrate_state` runs them in order.
+
+use near_sdk::{
+    collections::{LookupMap, TreeMap, UnorderedMap, UnorderedSet},
+    env, log, store, AccountId,
+    json_types::{U128, U64},
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+use std::collections::HashMap;
+
+use crate::{
+    add_to_index, ContractMetadata, MintDraft, StateVersion, StorageKey, VoiceNFT, VoiceNFTContract, VoiceNFTMetadata,
+    CURRENT_STATE_VERSION,
+};
+
+const STATE_KEY: &[u8] = b"STATE";
+
+/// Token as written by V1 deployments, before creators and mint sequence numbers
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct VoiceNFTV1 {
+    pub token_id: String,
+    pub owner_id: AccountId,
+    pub metadata: VoiceNFTMetadata,
+    pub approved_account_ids: HashMap<AccountId, u64>,
+    pub royalty: Option<HashMap<AccountId, u32>>,
+    pub created_at: U64,
+    pub updated_at: U64,
+}
+
+/// Contract state as written by V1 deployments. Field order must match the
+/// V1 struct exactly, it is only ever read from storage.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct VoiceNFTContractV1 {
+    pub owner_id: AccountId,
+    pub total_supply: U128,
+    pub tokens: UnorderedMap<String, VoiceNFTV1>,
+    pub tokens_by_owner: LookupMap<AccountId, UnorderedSet<String>>,
+    pub token_approvals: LookupMap<String, AccountId>,
+    pub operator_approvals: LookupMap<AccountId, UnorderedSet<AccountId>>,
+    pub metadata: ContractMetadata,
+    pub next_token_id: U128,
+}
+
+/// Contract state as written by V2 deployments, before collaborative mints
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct VoiceNFTContractV2 {
+    pub state_version: StateVersion,
+    pub owner_id: AccountId,
+    pub total_supply: U128,
+    pub tokens: store::IterableMap<String, VoiceNFT>,
+    pub tokens_by_owner: store::LookupMap<AccountId, store::IterableSet<String>>,
+    pub tokens_by_seq: TreeMap<u128, String>,
+    pub owner_tokens_by_seq: TreeMap<(AccountId, u128), String>,
+    pub tokens_by_creator: store::LookupMap<AccountId, store::IterableSet<String>>,
+    pub tokens_by_language: store::LookupMap<String, store::IterableSet<String>>,
+    pub tokens_by_voice_type: store::LookupMap<String, store::IterableSet<String>>,
+    pub tokens_by_tag: store::LookupMap<String, store::IterableSet<String>>,
+    pub token_approvals: store::LookupMap<String, AccountId>,
+    pub operator_approvals: store::LookupMap<AccountId, store::IterableSet<AccountId>>,
+    pub creator_verifiers: store::IterableSet<AccountId>,
+    pub metadata: ContractMetadata,
+    pub next_token_id: U128,
+}
+
+/// Contract state as written by V3 deployments, before NEP-178 approval IDs
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct VoiceNFTContractV3 {
+    pub state_version: StateVersion,
+    pub owner_id: AccountId,
+    pub total_supply: U128,
+    pub tokens: store::IterableMap<String, VoiceNFT>,
+    pub tokens_by_owner: store::LookupMap<AccountId, store::IterableSet<String>>,
+    pub tokens_by_seq: TreeMap<u128, String>,
+    pub owner_tokens_by_seq: TreeMap<(AccountId, u128), String>,
+    pub tokens_by_creator: store::LookupMap<AccountId, store::IterableSet<String>>,
+    pub tokens_by_language: store::LookupMap<String, store::IterableSet<String>>,
+    pub tokens_by_voice_type: store::LookupMap<String, store::IterableSet<String>>,
+    pub tokens_by_tag: store::LookupMap<String, store::IterableSet<String>>,
+    pub token_approvals: store::LookupMap<String, AccountId>,
+    pub operator_approvals: store::LookupMap<AccountId, store::IterableSet<AccountId>>,
+    pub creator_verifiers: store::IterableSet<AccountId>,
+    pub minters: store::IterableSet<AccountId>,
+    pub mint_drafts: store::IterableMap<u64, MintDraft>,
+    pub next_draft_id: u64,
+    pub metadata: ContractMetadata,
+    pub next_token_id: U128,
+}
+
+/// Contract state as written by V4 deployments, before token users
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct VoiceNFTContractV4 {
+    pub state_version: StateVersion,
+    pub owner_id: AccountId,
+    pub total_supply: U128,
+    pub tokens: store::IterableMap<String, VoiceNFT>,
+    pub tokens_by_owner: store::LookupMap<AccountId, store::IterableSet<String>>,
+    pub tokens_by_seq: TreeMap<u128, String>,
+    pub owner_tokens_by_seq: TreeMap<(AccountId, u128), String>,
+    pub tokens_by_creator: store::LookupMap<AccountId, store::IterableSet<String>>,
+    pub tokens_by_language: store::LookupMap<String, store::IterableSet<String>>,
+    pub tokens_by_voice_type: store::LookupMap<String, store::IterableSet<String>>,
+    pub tokens_by_tag: store::LookupMap<String, store::IterableSet<String>>,
+    pub operator_approvals: store::LookupMap<AccountId, store::IterableSet<AccountId>>,
+    pub creator_verifiers: store::IterableSet<AccountId>,
+    pub minters: store::IterableSet<AccountId>,
+    pub mint_drafts: store::IterableMap<u64, MintDraft>,
+    pub next_draft_id: u64,
+    pub next_approval_id: u64,
+    pub metadata: ContractMetadata,
+    pub next_token_id: U128,
+}
+
+/// Reads the state of a previous deployment and converts it to the current layout
+pub(crate) fn migrate_state() -> VoiceNFTContract {
+    let state = env::storage_read(STATE_KEY).expect("No state to migrate");
+
+    // V1 has no version tag, it is told apart by decoding it strictly
+    if let Ok(old) = VoiceNFTContractV1::try_from_slice(&state) {
+        return migrate_v1(old);
+    }
+
+    let version = StateVersion::deserialize(&mut state.as_slice()).expect("Unknown state layout");
+    match version {
+        StateVersion::V1 => env::panic_str("Cannot read the V1 state"),
+        StateVersion::V2 => migrate_v4(migrate_v3(migrate_v2(read_state(&state)))),
+        StateVersion::V3 => migrate_v4(migrate_v3(read_state(&state))),
+        StateVersion::V4 => migrate_v4(read_state(&state)),
+        StateVersion::V5 => {
+            log!("State is already at version {:?}", version);
+            read_state(&state)
+        }
+    }
+}
+
+fn read_state<T: BorshDeserialize>(state: &[u8]) -> T {
+    T::try_from_slice(state).unwrap_or_else(|_| env::panic_str("Cannot read the state"))
+}
+
+/// Moves every token of a V1 state into the `near_sdk::store` collections,
+/// rebuilding the indexes under `StorageKey` prefixes and clearing the legacy
+/// entries so their storage is released.
+///
+/// V1 did not record creators, each token is attributed to its current owner,
+/// unverified. Mint sequence numbers are assigned in mint order.
+///
+/// All tokens are moved in a single call, which is sized for the current
+/// deployments; a much larger collection would need to be migrated in batches.
+fn migrate_v1(mut old: VoiceNFTContractV1) -> VoiceNFTContract {
+    let mut this = VoiceNFTContract::empty_state(old.owner_id.clone(), old.metadata.clone());
+
+    let mut tokens = old.tokens.to_vec();
+    tokens.sort_by(|(a_id, a), (b_id, b)| a.created_at.0.cmp(&b.created_at.0).then_with(|| a_id.cmp(b_id)));
+
+    for (mint_seq, (token_id, token)) in tokens.iter().enumerate() {
+        let mut approved_account_ids = token.approved_account_ids.clone();
+
+        // V1 kept a single approved account per token, it becomes a NEP-178 approval
+        if let Some(approved_id) = old.token_approvals.remove(token_id) {
+            approved_account_ids.insert(approved_id, this.next_approval_id);
+            this.next_approval_id += 1;
+        }
+
+        this.internal_add_token(&VoiceNFT {
+            token_id: token_id.clone(),
+            owner_id: token.owner_id.clone(),
+            creator_id: token.owner_id.clone(),
+            creator_verified: false,
+            metadata: token.metadata.clone(),
+            approved_account_ids,
+            royalty: token.royalty.clone(),
+            mint_seq: U128(mint_seq as u128 + 1),
+            created_at: token.created_at,
+            updated_at: token.updated_at,
+        });
+    }
+
+    // Operator approvals are keyed by owner, every owner of a token has been seen above
+    for (_, token) in tokens.iter() {
+        if let Some(mut operators) = old.operator_approvals.remove(&token.owner_id) {
+            for operator_id in operators.iter() {
+                add_to_index(&mut this.operator_approvals, &token.owner_id, &operator_id, || StorageKey::OperatorsPerOwner {
+                    account_hash: env::sha256_array(token.owner_id.as_bytes()),
+                });
+            }
+            operators.clear();
+        }
+    }
+
+    // Release the legacy entries, their keys are all derivable from the tokens
+    for (token_id, token) in tokens.iter() {
+        if let Some(mut tokens_set) = old.tokens_by_owner.remove(&token.owner_id) {
+            tokens_set.clear();
+        }
+        old.tokens.remove(token_id);
+    }
+
+    this.total_supply = old.total_supply;
+    // Mint sequence numbers continue after the migrated tokens
+    this.next_token_id = U128(old.next_token_id.0.max(tokens.len() as u128 + 1));
+
+    log!("Migrated {} voice NFTs to state version {:?}", tokens.len(), this.state_version);
+
+    this
+}
+
+/// V3 adds collaborative mint drafts and minters
+fn migrate_v2(old: VoiceNFTContractV2) -> VoiceNFTContractV3 {
+    VoiceNFTContractV3 {
+        state_version: StateVersion::V3,
+        owner_id: old.owner_id,
+        total_supply: old.total_supply,
+        tokens: old.tokens,
+        tokens_by_owner: old.tokens_by_owner,
+        tokens_by_seq: old.tokens_by_seq,
+        owner_tokens_by_seq: old.owner_tokens_by_seq,
+        tokens_by_creator: old.tokens_by_creator,
+        tokens_by_language: old.tokens_by_language,
+        tokens_by_voice_type: old.tokens_by_voice_type,
+        tokens_by_tag: old.tokens_by_tag,
+        token_approvals: old.token_approvals,
+        operator_approvals: old.operator_approvals,
+        creator_verifiers: old.creator_verifiers,
+        minters: store::IterableSet::new(StorageKey::Minters),
+        mint_drafts: store::IterableMap::new(StorageKey::MintDrafts),
+        next_draft_id: 1,
+        metadata: old.metadata,
+        next_token_id: old.next_token_id,
+    }
+}
+
+/// V4 turns the single approved account V3 kept per token into a NEP-178 approval
+fn migrate_v3(mut old: VoiceNFTContractV3) -> VoiceNFTContractV4 {
+    let mut next_approval_id = 1;
+    let token_ids: Vec<String> = old.tokens.keys().cloned().collect();
+    for token_id in token_ids.iter() {
+        if let Some(approved_id) = old.token_approvals.remove(token_id) {
+            let token = old.tokens.get_mut(token_id).unwrap();
+            token.approved_account_ids.insert(approved_id, next_approval_id);
+            next_approval_id += 1;
+        }
+    }
+
+    VoiceNFTContractV4 {
+        state_version: StateVersion::V4,
+        owner_id: old.owner_id,
+        total_supply: old.total_supply,
+        tokens: old.tokens,
+        tokens_by_owner: old.tokens_by_owner,
+        tokens_by_seq: old.tokens_by_seq,
+        owner_tokens_by_seq: old.owner_tokens_by_seq,
+        tokens_by_creator: old.tokens_by_creator,
+        tokens_by_language: old.tokens_by_language,
+        tokens_by_voice_type: old.tokens_by_voice_type,
+        tokens_by_tag: old.tokens_by_tag,
+        operator_approvals: old.operator_approvals,
+        creator_verifiers: old.creator_verifiers,
+        minters: old.minters,
+        mint_drafts: old.mint_drafts,
+        next_draft_id: old.next_draft_id,
+        next_approval_id,
+        metadata: old.metadata,
+        next_token_id: old.next_token_id,
+    }
+}
+
+/// V5 adds token users, no token has one yet
+fn migrate_v4(old: VoiceNFTContractV4) -> VoiceNFTContract {
+    let this = VoiceNFTContract {
+        state_version: CURRENT_STATE_VERSION,
+        owner_id: old.owner_id,
+        total_supply: old.total_supply,
+        tokens: old.tokens,
+        tokens_by_owner: old.tokens_by_owner,
+        tokens_by_seq: old.tokens_by_seq,
+        owner_tokens_by_seq: old.owner_tokens_by_seq,
+        tokens_by_creator: old.tokens_by_creator,
+        tokens_by_language: old.tokens_by_language,
+        tokens_by_voice_type: old.tokens_by_voice_type,
+        tokens_by_tag: old.tokens_by_tag,
+        operator_approvals: old.operator_approvals,
+        creator_verifiers: old.creator_verifiers,
+        minters: old.minters,
+        mint_drafts: old.mint_drafts,
+        next_draft_id: old.next_draft_id,
+        next_approval_id: old.next_approval_id,
+        metadata: old.metadata,
+        next_token_id: old.next_token_id,
+        token_users: store::LookupMap::new(StorageKey::TokenUsers),
+    };
+
+    log!("Migrated {} voice NFTs to state version {:?}", this.total_supply.0, this.state_version);
+
+    this
+}
@@ -0,0 +1,94 @@
+use near_workspaces::types::NearToken;
+use serde_json::{json, Value};
+
+const DRAFT_DEPOSIT: NearToken = NearToken::from_millinear(100);
+const DRAFT_DURATION: u64 = 24 * 60 * 60 * 1_000_000_000; // 1 day in nanoseconds
+// Upper bound on what a transaction costs in gas, to tell refunds apart from fees
+const MAX_TX_COST: NearToken = NearToken::from_millinear(10);
+
+#[tokio::test]
+async fn test_collaborative_mint_by_minter() -> Result<(), Box<dyn std::error::Error>> {
+    let contract_wasm = near_workspaces::compile_project("./").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let contract = sandbox.dev_deploy(&contract_wasm).await?;
+    let minter = sandbox.dev_create_account().await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+
+    let outcome = contract.call("new").args_json(json!({"owner_id": contract.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let now = sandbox.view_block().await?.timestamp();
+    let proposal = json!({
+        "token_id": "duet-1",
+        "receiver_id": alice.id(),
+        "metadata": {"title": "Duet"},
+        "shares": {alice.id().to_string(): 500, bob.id().to_string(): 500},
+        "expires_at": (now + DRAFT_DURATION).to_string(),
+        "creator_id": alice.id(),
+    });
+
+    let outcome = alice
+        .call(contract.id(), "nft_propose_collaborative_mint")
+        .args_json(&proposal)
+        .deposit(DRAFT_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_failure(), "Only the owner or a minter can propose");
+
+    let outcome = contract.call("add_minter").args_json(json!({"account_id": minter.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = minter.call(contract.id(), "nft_propose_collaborative_mint").args_json(&proposal).transact().await?;
+    assert!(outcome.is_failure(), "The draft's storage must be paid for");
+
+    let minter_before = minter.view_account().await?.balance;
+    let outcome = minter
+        .call(contract.id(), "nft_propose_collaborative_mint")
+        .args_json(&proposal)
+        .deposit(DRAFT_DEPOSIT)
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let draft: Value = outcome.json()?;
+    let storage_deposit: u128 = draft["storage_deposit"].as_str().unwrap().parse()?;
+    assert!(storage_deposit > 0 && storage_deposit < DRAFT_DEPOSIT.as_yoctonear());
+    let minter_after = minter.view_account().await?.balance;
+    assert!(minter_before.as_yoctonear() - minter_after.as_yoctonear() < storage_deposit + MAX_TX_COST.as_yoctonear());
+    let draft_id: u64 = draft["id"].as_str().unwrap().parse()?;
+
+    for collaborator in [&alice, &bob] {
+        let outcome = collaborator
+            .call(contract.id(), "nft_approve_collaborative_mint")
+            .args_json(json!({"draft_id": draft_id}))
+            .transact()
+            .await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    }
+
+    let outcome = alice
+        .call(contract.id(), "nft_finalize_collaborative_mint")
+        .args_json(json!({"draft_id": draft_id}))
+        .transact()
+        .await?;
+    assert!(outcome.is_failure(), "Only the owner or a minter can finalize");
+
+    let minter_before = minter.view_account().await?.balance;
+    let outcome = minter
+        .call(contract.id(), "nft_finalize_collaborative_mint")
+        .args_json(json!({"draft_id": draft_id}))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let minter_after = minter.view_account().await?.balance;
+    assert!(minter_after.as_yoctonear() + MAX_TX_COST.as_yoctonear() > minter_before.as_yoctonear() + storage_deposit);
+
+    let token: Value = contract.view("nft_token").args_json(json!({"token_id": "duet-1"})).await?.json()?;
+    assert_eq!(token["owner_id"], alice.id().as_str());
+    assert_eq!(token["royalty"][bob.id().as_str()], 500);
+    let draft: Option<Value> = contract.view("get_mint_draft").args_json(json!({"draft_id": draft_id})).await?.json()?;
+    assert!(draft.is_none());
+
+    Ok(())
+}
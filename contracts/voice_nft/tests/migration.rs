@@ -0,0 +1,252 @@
+use near_workspaces::{operations::Function, types::NearToken, Account, Contract};
+use serde_json::{json, Value};
+
+// Release build of the originally deployed contract, using the legacy `near_sdk::collections` layout
+const V1_WASM_PATH: &str = "tests/res/voice_nft_v1.wasm";
+// Release build of the first contract using `near_sdk::store` collections, with a single approval per token
+const V2_WASM_PATH: &str = "tests/res/voice_nft_v2.wasm";
+
+#[tokio::test]
+async fn test_migrate_v1_state() -> Result<(), Box<dyn std::error::Error>> {
+    let v1_wasm = std::fs::read(V1_WASM_PATH)?;
+    let contract_wasm = near_workspaces::compile_project("./").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let contract = sandbox.dev_deploy(&v1_wasm).await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+
+    let outcome = contract
+        .call("new")
+        .args_json(json!({"owner_id": contract.id()}))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Populate the V1 state
+    mint(&contract, "voice-1", &alice, &["podcast", "comedy"]).await?;
+    mint(&contract, "voice-2", &alice, &["podcast"]).await?;
+    mint(&contract, "voice-3", &bob, &["audiobook"]).await?;
+
+    let outcome = alice
+        .call(contract.id(), "nft_transfer")
+        .args_json(json!({"receiver_id": bob.id(), "token_id": "voice-2"}))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // V1 kept a single approved account per token
+    let outcome = bob
+        .call(contract.id(), "nft_approve")
+        .args_json(json!({"token_id": "voice-3", "account_id": alice.id()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let tokens_before: Vec<Value> = contract.view("nft_tokens").args_json(json!({})).await?.json()?;
+    assert_eq!(tokens_before.len(), 3);
+
+    // Upgrade and migrate in one batch so the new code never runs on the old layout
+    let outcome = contract
+        .batch()
+        .deploy(&contract_wasm)
+        .call(Function::new("migrate").max_gas())
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let version: String = contract.view("get_state_version").args_json(json!({})).await?.json()?;
+    assert_eq!(version, "V5");
+
+    let supply: String = contract.view("nft_total_supply").args_json(json!({})).await?.json()?;
+    assert_eq!(supply, "3");
+
+    for token_before in &tokens_before {
+        let token_after: Value = contract
+            .view("nft_token")
+            .args_json(json!({"token_id": token_before["token_id"]}))
+            .await?
+            .json()?;
+        for field in ["owner_id", "metadata", "royalty", "created_at", "updated_at"] {
+            assert_eq!(token_after[field], token_before[field], "{} of {}", field, token_before["token_id"]);
+        }
+        // V1 did not record creators, the owner is taken as the creator
+        assert_eq!(token_after["creator_id"], token_before["owner_id"]);
+        assert_eq!(token_after["creator_verified"], false);
+    }
+
+    let voice_3: Value = contract.view("nft_token").args_json(json!({"token_id": "voice-3"})).await?.json()?;
+    assert!(voice_3["approved_account_ids"][alice.id().as_str()].is_u64());
+    let outcome = alice
+        .call(contract.id(), "nft_transfer")
+        .args_json(json!({"receiver_id": alice.id(), "token_id": "voice-3"}))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "The V1 approval still lets Alice transfer: {:#?}", outcome.into_result().unwrap_err());
+    let outcome = alice
+        .call(contract.id(), "nft_transfer")
+        .args_json(json!({"receiver_id": bob.id(), "token_id": "voice-3"}))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let alice_tokens = token_ids(&contract, "nft_tokens_for_owner", json!({"account_id": alice.id()})).await?;
+    assert_eq!(alice_tokens, vec!["voice-1"]);
+    let bob_tokens = token_ids(&contract, "nft_tokens_for_owner", json!({"account_id": bob.id()})).await?;
+    assert_eq!(bob_tokens.len(), 2);
+    assert!(bob_tokens.contains(&"voice-2".to_string()) && bob_tokens.contains(&"voice-3".to_string()));
+
+    let bob_creations = token_ids(&contract, "nft_tokens_for_creator", json!({"creator_id": bob.id()})).await?;
+    assert_eq!(bob_creations.len(), 2);
+    let podcasts = token_ids(&contract, "nft_tokens_by_tag", json!({"tag": "Podcast"})).await?;
+    assert_eq!(podcasts.len(), 2);
+
+    let page: Value = contract
+        .view("nft_tokens_for_owner_page")
+        .args_json(json!({"account_id": bob.id()}))
+        .await?
+        .json()?;
+    let page_ids: Vec<&str> = page["tokens"].as_array().unwrap().iter().map(|t| t["token_id"].as_str().unwrap()).collect();
+    assert_eq!(page_ids, vec!["voice-2", "voice-3"]);
+    let page: Value = contract.view("nft_tokens_page").args_json(json!({})).await?.json()?;
+    let page_ids: Vec<&str> = page["tokens"].as_array().unwrap().iter().map(|t| t["token_id"].as_str().unwrap()).collect();
+    assert_eq!(page_ids, vec!["voice-1", "voice-2", "voice-3"]);
+
+    // The migrated state keeps working for writes
+    mint(&contract, "voice-4", &alice, &["podcast"]).await?;
+    let outcome = bob
+        .call(contract.id(), "nft_transfer")
+        .args_json(json!({"receiver_id": alice.id(), "token_id": "voice-3"}))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let alice_supply: String = contract.view("nft_supply_for_owner").args_json(json!({"account_id": alice.id()})).await?.json()?;
+    assert_eq!(alice_supply, "3");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_migrate_v2_state() -> Result<(), Box<dyn std::error::Error>> {
+    let v2_wasm = std::fs::read(V2_WASM_PATH)?;
+    let contract_wasm = near_workspaces::compile_project("./").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let contract = sandbox.dev_deploy(&v2_wasm).await?;
+    let alice = sandbox.dev_create_account().await?;
+    let bob = sandbox.dev_create_account().await?;
+
+    let outcome = contract.call("new").args_json(json!({"owner_id": contract.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Populate the V2 state, with a verified creator and a single approval
+    mint(&contract, "voice-1", &alice, &["podcast"]).await?;
+    mint(&contract, "voice-2", &alice, &["audiobook"]).await?;
+
+    let outcome = contract.call("add_creator_verifier").args_json(json!({"account_id": contract.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    let outcome = contract
+        .call("nft_set_creator_verified")
+        .args_json(json!({"token_id": "voice-1", "verified": true}))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let outcome = alice
+        .call(contract.id(), "nft_approve")
+        .args_json(json!({"token_id": "voice-1", "account_id": bob.id()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let tokens_before: Vec<Value> = contract.view("nft_tokens").args_json(json!({})).await?.json()?;
+
+    let outcome = contract
+        .batch()
+        .deploy(&contract_wasm)
+        .call(Function::new("migrate").max_gas())
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let version: String = contract.view("get_state_version").args_json(json!({})).await?.json()?;
+    assert_eq!(version, "V5");
+
+    for token_before in &tokens_before {
+        let token_after: Value = contract
+            .view("nft_token")
+            .args_json(json!({"token_id": token_before["token_id"]}))
+            .await?
+            .json()?;
+        for field in ["owner_id", "creator_id", "creator_verified", "metadata", "royalty", "created_at", "updated_at"] {
+            assert_eq!(token_after[field], token_before[field], "{} of {}", field, token_before["token_id"]);
+        }
+    }
+
+    // The V2 approval became a NEP-178 approval
+    let approved: bool = contract
+        .view("nft_is_approved")
+        .args_json(json!({"token_id": "voice-1", "approved_account_id": bob.id()}))
+        .await?
+        .json()?;
+    assert!(approved);
+    let outcome = bob
+        .call(contract.id(), "nft_transfer")
+        .args_json(json!({"receiver_id": bob.id(), "token_id": "voice-1"}))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // The collections added since V2 start empty and work
+    let drafts: Vec<Value> = contract.view("get_mint_drafts").args_json(json!({})).await?.json()?;
+    assert!(drafts.is_empty());
+    let user: Option<Value> = contract.view("nft_user_of").args_json(json!({"token_id": "voice-2"})).await?.json()?;
+    assert!(user.is_none());
+    let now = sandbox.view_block().await?.timestamp();
+    let outcome = alice
+        .call(contract.id(), "nft_set_user")
+        .args_json(json!({"token_id": "voice-2", "user_id": bob.id(), "expires_at": (now + 60_000_000_000).to_string()}))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // Migrating again leaves the state as it is
+    let outcome = contract.call("migrate").max_gas().transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    assert!(outcome.logs().iter().any(|log| log.starts_with("State is already at version")));
+
+    Ok(())
+}
+
+async fn mint(contract: &Contract, token_id: &str, receiver: &Account, tags: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+    let outcome = contract
+        .call("nft_mint")
+        .args_json(json!({
+            "token_id": token_id,
+            "receiver_id": receiver.id(),
+            "metadata": {
+                "title": format!("Recording {}", token_id),
+                "language": "en",
+                "voice_type": "narration",
+                "tags": tags,
+            },
+        }))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    Ok(())
+}
+
+async fn token_ids(contract: &Contract, method: &str, args: Value) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let tokens: Vec<Value> = contract.view(method).args_json(args).await?.json()?;
+    Ok(tokens.iter().map(|token| token["token_id"].as_str().unwrap().to_string()).collect())
+}
@@ -0,0 +1,154 @@
+use near_workspaces::{operations::Function, types::Gas, Account, Contract};
+use serde_json::{json, Value};
+
+const TOTAL_TOKENS: u64 = 10_000;
+const MINTS_PER_TX: u64 = 20;
+const PAGE_SIZE: u64 = 50;
+// Enumeration must stay well under the 300 TGas transaction limit at any offset
+const MAX_PAGE_GAS: Gas = Gas::from_tgas(100);
+
+#[tokio::test]
+async fn test_pagination_with_10k_tokens() -> Result<(), Box<dyn std::error::Error>> {
+    let contract_wasm = near_workspaces::compile_project("./").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let contract = sandbox.dev_deploy(&contract_wasm).await?;
+    let creator = sandbox.dev_create_account().await?;
+
+    let outcome = contract
+        .call("new")
+        .args_json(json!({"owner_id": contract.id()}))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    mint_tokens(&contract, &creator, TOTAL_TOKENS).await?;
+
+    let supply: String = contract.view("nft_supply_for_owner").args_json(json!({"account_id": creator.id()})).await?.json()?;
+    assert_eq!(supply, TOTAL_TOKENS.to_string());
+
+    // Offset pagination near the end of the owner's set
+    let from_index = (TOTAL_TOKENS - PAGE_SIZE).to_string();
+    let outcome = creator
+        .call(contract.id(), "nft_tokens_for_owner")
+        .args_json(json!({"account_id": creator.id(), "from_index": from_index, "limit": PAGE_SIZE}))
+        .max_gas()
+        .transact()
+        .await?;
+    let gas = outcome.total_gas_burnt;
+    let tokens: Vec<Value> = outcome.into_result()?.json()?;
+    assert_eq!(tokens.len() as u64, PAGE_SIZE);
+    assert!(gas < MAX_PAGE_GAS, "nft_tokens_for_owner at offset {} burnt {} TGas", from_index, gas.as_tgas());
+
+    let outcome = creator
+        .call(contract.id(), "nft_tokens")
+        .args_json(json!({"from_index": from_index, "limit": PAGE_SIZE}))
+        .max_gas()
+        .transact()
+        .await?;
+    let gas = outcome.total_gas_burnt;
+    let tokens: Vec<Value> = outcome.into_result()?.json()?;
+    assert_eq!(tokens.len() as u64, PAGE_SIZE);
+    assert!(gas < MAX_PAGE_GAS, "nft_tokens at offset {} burnt {} TGas", from_index, gas.as_tgas());
+
+    // Cursor pagination from a cursor near the end of the owner's tokens
+    let first_page: Value = contract
+        .view("nft_tokens_for_owner_page")
+        .args_json(json!({"account_id": creator.id(), "limit": PAGE_SIZE}))
+        .await?
+        .json()?;
+    assert_eq!(first_page["tokens"].as_array().unwrap().len() as u64, PAGE_SIZE);
+    assert_eq!(first_page["tokens"][0]["token_id"], "voice-0");
+
+    let cursor = (TOTAL_TOKENS - PAGE_SIZE).to_string();
+    let outcome = creator
+        .call(contract.id(), "nft_tokens_for_owner_page")
+        .args_json(json!({"account_id": creator.id(), "from_cursor": cursor, "limit": PAGE_SIZE}))
+        .max_gas()
+        .transact()
+        .await?;
+    let gas = outcome.total_gas_burnt;
+    let page: Value = outcome.into_result()?.json()?;
+    assert_eq!(page["tokens"].as_array().unwrap().len() as u64, PAGE_SIZE);
+    assert!(gas < MAX_PAGE_GAS, "nft_tokens_for_owner_page at cursor {} burnt {} TGas", cursor, gas.as_tgas());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cursor_is_stable_under_inserts() -> Result<(), Box<dyn std::error::Error>> {
+    let contract_wasm = near_workspaces::compile_project("./").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let contract = sandbox.dev_deploy(&contract_wasm).await?;
+    let creator = sandbox.dev_create_account().await?;
+
+    let outcome = contract
+        .call("new")
+        .args_json(json!({"owner_id": contract.id()}))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    mint_tokens(&contract, &creator, 10).await?;
+
+    let first_page: Value = contract
+        .view("nft_tokens_page")
+        .args_json(json!({"limit": 5}))
+        .await?
+        .json()?;
+    let cursor = first_page["next_cursor"].as_str().unwrap().to_string();
+
+    // Tokens minted between two page requests only ever show up at the end
+    let outcome = contract
+        .call("nft_mint")
+        .args_json(mint_args("voice-late", creator.id().as_str()))
+        .transact()
+        .await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let second_page: Value = contract
+        .view("nft_tokens_page")
+        .args_json(json!({"from_cursor": cursor, "limit": 10}))
+        .await?
+        .json()?;
+    let token_ids: Vec<&str> = second_page["tokens"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|token| token["token_id"].as_str().unwrap())
+        .collect();
+    assert_eq!(token_ids, vec!["voice-5", "voice-6", "voice-7", "voice-8", "voice-9", "voice-late"]);
+    assert!(second_page["next_cursor"].is_null());
+
+    Ok(())
+}
+
+async fn mint_tokens(contract: &Contract, receiver: &Account, count: u64) -> Result<(), Box<dyn std::error::Error>> {
+    for batch_start in (0..count).step_by(MINTS_PER_TX as usize) {
+        let mut tx = contract.batch();
+        for i in batch_start..(batch_start + MINTS_PER_TX).min(count) {
+            tx = tx.call(
+                Function::new("nft_mint")
+                    .args_json(mint_args(&format!("voice-{}", i), receiver.id().as_str()))
+                    .gas(Gas::from_tgas(14)),
+            );
+        }
+        let outcome = tx.transact().await?;
+        assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+    }
+    Ok(())
+}
+
+fn mint_args(token_id: &str, receiver_id: &str) -> Value {
+    json!({
+        "token_id": token_id,
+        "receiver_id": receiver_id,
+        "metadata": {
+            "title": format!("Recording {}", token_id),
+            "language": "en",
+            "voice_type": "narration",
+            "tags": ["benchmark"],
+        },
+    })
+}
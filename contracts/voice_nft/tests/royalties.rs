@@ -0,0 +1,50 @@
+use serde_json::{json, Value};
+
+// Max receivers of a payout, the owner included
+const MAX_LEN_PAYOUT: usize = 10;
+
+#[tokio::test]
+async fn test_mint_rejects_royalties_that_cannot_be_paid_out() -> Result<(), Box<dyn std::error::Error>> {
+    let contract_wasm = near_workspaces::compile_project("./").await?;
+
+    let sandbox = near_workspaces::sandbox().await?;
+    let contract = sandbox.dev_deploy(&contract_wasm).await?;
+    let alice = sandbox.dev_create_account().await?;
+
+    let outcome = contract.call("new").args_json(json!({"owner_id": contract.id()})).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    let royalty = |receivers: usize, share: u32| -> serde_json::Map<String, Value> {
+        (0..receivers).map(|index| (format!("royalty-{}.test.near", index), json!(share))).collect()
+    };
+    let mint = |token_id: &str, royalty: serde_json::Map<String, Value>| {
+        json!({
+            "token_id": token_id,
+            "receiver_id": alice.id(),
+            "metadata": {"title": format!("Recording {}", token_id)},
+            "royalty": royalty,
+        })
+    };
+
+    // Shares above 100% would pay out more than the sale
+    let outcome = contract.call("nft_mint").args_json(mint("voice-1", royalty(2, 6000))).transact().await?;
+    assert!(outcome.is_failure(), "Royalty shares above 100% must be rejected");
+
+    // A payout holds the owner besides the royalty receivers
+    let outcome = contract.call("nft_mint").args_json(mint("voice-1", royalty(MAX_LEN_PAYOUT, 100))).transact().await?;
+    assert!(outcome.is_failure(), "A royalty map with no room for the owner must be rejected");
+
+    let outcome = contract.call("nft_mint").args_json(mint("voice-1", royalty(MAX_LEN_PAYOUT - 1, 1000))).transact().await?;
+    assert!(outcome.is_success(), "{:#?}", outcome.into_result().unwrap_err());
+
+    // The largest royalty map accepted can be paid out by a market
+    let payout: Value = contract
+        .view("nft_payout")
+        .args_json(json!({"token_id": "voice-1", "balance": "1000000", "max_len_payout": MAX_LEN_PAYOUT}))
+        .await?
+        .json()?;
+    assert_eq!(payout["payout"].as_object().unwrap().len(), MAX_LEN_PAYOUT);
+    assert_eq!(payout["payout"][alice.id().as_str()], "100000");
+
+    Ok(())
+}
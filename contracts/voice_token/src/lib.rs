@@ -2,7 +2,7 @@
 // (migrated from monolithic contracts/src/voice_token.rs)
 
 use near_sdk::{
-    env, log, AccountId, PanicOnDefault,
+    assert_one_yocto, env, ext_contract, log, AccountId, Gas, PanicOnDefault, PromiseError, PromiseOrValue,
     collections::{LookupMap, UnorderedSet},
     json_types::U128,
     serde::{Deserialize, Serialize},
@@ -11,6 +11,14 @@ use borsh::{BorshDeserialize, BorshSerialize};
 
 const TOTAL_SUPPLY: u128 = 1_000_000_000_000_000_000_000_000_000_000; // 1 billion tokens with 18 decimals
 const DECIMALS: u8 = 18;
+const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas::from_tgas(5);
+
+/// NEP-141 receiver of `ft_transfer_call`
+#[ext_contract(ext_ft_receiver)]
+pub trait FungibleTokenReceiver {
+    /// Returns the amount of the transfer the receiver did not use, refunded to the sender
+    fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) -> PromiseOrValue<U128>;
+}
 
 // JSON-compatible types for view methods
 #[derive(Serialize, Deserialize, schemars::JsonSchema)]
@@ -98,6 +106,52 @@ impl VoiceToken {
         self.internal_transfer(&sender_id, &receiver_id, amount.0, memo);
     }
 
+    /// Transfer tokens to a contract and call its `ft_on_transfer` with `msg`.
+    /// Whatever the receiver reports as unused is refunded to the sender.
+    /// Requires a deposit of exactly 1 yoctoNEAR.
+    #[payable]
+    pub fn ft_transfer_call(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>, msg: String) -> PromiseOrValue<U128> {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        self.internal_transfer(&sender_id, &receiver_id, amount.0, memo);
+
+        // The receiver gets all the remaining gas, the resolution only its static part
+        ext_ft_receiver::ext(receiver_id.clone())
+            .ft_on_transfer(sender_id.clone(), amount, msg)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                    .with_unused_gas_weight(0)
+                    .ft_resolve_transfer(sender_id, receiver_id, amount),
+            )
+            .into()
+    }
+
+    /// Refund the unused part of an `ft_transfer_call`, returns the amount used
+    #[private]
+    pub fn ft_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        #[callback_result] unused_amount: Result<U128, PromiseError>,
+    ) -> U128 {
+        // A failed receiver call gives everything back
+        let unused_amount = unused_amount.map_or(amount.0, |unused| unused.0.min(amount.0));
+        if unused_amount == 0 {
+            return amount;
+        }
+
+        // The receiver may have moved some of the tokens on already
+        let receiver_balance = self.balances.get(&receiver_id).unwrap_or(U128(0)).0;
+        let refund = unused_amount.min(receiver_balance);
+        if refund > 0 {
+            self.internal_transfer(&receiver_id, &sender_id, refund, Some("Refund".to_string()));
+        }
+
+        U128(amount.0 - refund)
+    }
+
     /// Transfer tokens from one account to another (requires allowance)
     #[payable]
     pub fn ft_transfer_from(&mut self, sender_id: AccountId, receiver_id: AccountId, amount: U128, memo: Option<String>) {